@@ -0,0 +1,84 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Compares the span operations of the software rasterizer with their scalar
+//! versions. Run with `cargo bench --bench simd`; their equality is tested in
+//! the module itself.
+
+use std::{hint::black_box, time::{Duration, Instant}};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/gfx/soft/simd.rs"]
+mod simd;
+
+/// The number of pixels per span, i.e. a row of a 4K frame.
+const SPAN: usize = 3840;
+
+fn main() {
+    let colors: Vec<u32> = (0..SPAN as u32).map(|i| i.wrapping_mul(0x9E37_79B9)).collect();
+    let unorm: Vec<[f32; 4]> = colors.iter().map(|c| c.to_le_bytes().map(|v| v as f32 / 255.0)).collect();
+    let texture: Vec<u8> = colors.iter().flat_map(|c| c.to_le_bytes()).collect();
+
+    let mut span = vec![0; SPAN];
+    let mut span_simd = span.clone();
+    compare(
+        "fill",
+        || simd::scalar::fill(black_box(&mut span), black_box(0x8040_20FF)),
+        || simd::fill(black_box(&mut span_simd), black_box(0x8040_20FF)),
+    );
+
+    let mut span = vec![0x4080_C0FF; SPAN];
+    let mut span_simd = span.clone();
+    compare(
+        "blend_over",
+        || simd::scalar::blend_over(black_box(&mut span), black_box(&colors)),
+        || simd::blend_over(black_box(&mut span_simd), black_box(&colors)),
+    );
+
+    let mut span = vec![0xFFC0_8040; SPAN];
+    let mut span_simd = span.clone();
+    compare(
+        "blend_over_bgra",
+        || simd::scalar::blend_over_bgra(black_box(&mut span), black_box(&colors)),
+        || simd::blend_over_bgra(black_box(&mut span_simd), black_box(&colors)),
+    );
+
+    let mut packed = vec![0; SPAN];
+    let mut packed_simd = packed.clone();
+    compare(
+        "pack_unorm",
+        || simd::scalar::pack_unorm(black_box(&unorm), black_box(&mut packed)),
+        || simd::pack_unorm(black_box(&unorm), black_box(&mut packed_simd)),
+    );
+
+    compare(
+        "fetch_nearest",
+        || simd::scalar::fetch_nearest(black_box(&texture), 0.0, black_box(0.5 / SPAN as f32), black_box(&mut packed)),
+        || simd::fetch_nearest(black_box(&texture), 0.0, black_box(0.5 / SPAN as f32), black_box(&mut packed_simd)),
+    );
+}
+
+fn compare(name: &str, mut scalar: impl FnMut(), mut simd: impl FnMut()) {
+    let scalar = measure(&mut scalar);
+    let simd = measure(&mut simd);
+
+    println!(
+        "{name:<16} scalar {:>9.1} ns/span   simd {:>9.1} ns/span   {:.2}x",
+        scalar.as_secs_f64() * 1e9,
+        simd.as_secs_f64() * 1e9,
+        scalar.as_secs_f64() / simd.as_secs_f64(),
+    );
+}
+
+/// The mean duration of `f`, over at least half a second.
+fn measure(f: &mut impl FnMut()) -> Duration {
+    let start = Instant::now();
+    let mut iterations = 0;
+    while start.elapsed() < Duration::from_millis(500) {
+        for _ in 0..100 {
+            f();
+        }
+        iterations += 100;
+    }
+    start.elapsed() / iterations
+}
@@ -0,0 +1,181 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::{collections::HashMap, rc::Rc};
+
+use euclid::default::{Point2D, Rect, Size2D};
+use zinnebeeld::{
+    include_image,
+    winit::{
+        application::ApplicationHandler,
+        dpi::LogicalSize,
+        event::{ElementState, KeyEvent, WindowEvent},
+        event_loop::{ActiveEventLoop, EventLoop},
+        keyboard::{Key, NamedKey},
+        window::{Window, WindowId},
+    },
+    Backend,
+    Color,
+    Context,
+    ContextBuilder,
+    EmbeddedImage,
+};
+
+const TEST_IMAGE: EmbeddedImage = include_image!("../res/test-image.png");
+
+struct App {
+    window: Rc<Window>,
+
+    /// The windows that are opened with `I`, which show the image at its
+    /// original size.
+    inspectors: HashMap<WindowId, Rc<Window>>,
+
+    context: Context,
+}
+
+impl App {
+    fn draw(&mut self) {
+        let image = self.context.load_embedded_image(&TEST_IMAGE).unwrap();
+
+        self.context.paint(|painter| {
+
+            painter.paint_filled_rect(
+                Rect::new(
+                    Point2D::new(200.0, 200.0),
+                    Size2D::new(400.0, 200.0),
+                ),
+                Color::YELLOW,
+            );
+
+            painter.paint_filled_rect(
+                Rect::new(
+                    Point2D::new(400.0, 400.0),
+                    Size2D::new(640.0, 480.0),
+                ),
+                image,
+            );
+        });
+    }
+
+    fn draw_inspector(&mut self, id: WindowId) {
+        let image = self.context.load_embedded_image(&TEST_IMAGE).unwrap();
+
+        self.context.paint_window(id, |painter| {
+            painter.paint_filled_rect(Rect::from_size(image.logical_size()), image);
+        });
+    }
+
+    fn open_inspector(&mut self, event_loop: &ActiveEventLoop) {
+        let attributes = Window::default_attributes()
+            .with_title("Inspector")
+            .with_inner_size(LogicalSize::new(640, 480));
+
+        match self.context.create_window(event_loop, attributes) {
+            Ok(window) => {
+                self.inspectors.insert(window.id(), window);
+            }
+            Err(error) => eprintln!("failed to open an inspector: {error}"),
+        }
+    }
+
+    fn inspector_event(&mut self, id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => {
+                self.context.close_window(id);
+                self.inspectors.remove(&id);
+            }
+
+            WindowEvent::RedrawRequested => self.draw_inspector(id),
+
+            WindowEvent::Resized(size) => {
+                self.context.resize_window(id, Size2D::new(size.width, size.height));
+            }
+
+            _ => (),
+        }
+    }
+
+    /// Compares the backends by toggling between them.
+    fn switch_backend(&mut self) {
+        let backend = match self.context.backend_info().name {
+            "OpenGL" => Backend::Software,
+            _ => Backend::Gl,
+        };
+
+        if let Err(error) = self.context.switch_backend(backend) {
+            eprintln!("failed to switch to the {backend:?} backend: {error}");
+        }
+
+        self.window.request_redraw();
+        self.inspectors.values().for_each(|window| window.request_redraw());
+    }
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        _ = event_loop;
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId, event: WindowEvent) {
+        if window_id != self.window.id() {
+            return self.inspector_event(window_id, event);
+        }
+
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+
+            WindowEvent::RedrawRequested => {
+                self.draw();
+            }
+
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    logical_key: Key::Named(NamedKey::Tab),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } => self.switch_backend(),
+
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    logical_key: Key::Character(character),
+                    state: ElementState::Pressed,
+                    ..
+                },
+                ..
+            } if character.as_str() == "i" => self.open_inspector(event_loop),
+
+            WindowEvent::Resized(size) => {
+                self.context.resize(Size2D::new(size.width, size.height));
+            }
+
+            WindowEvent::ScaleFactorChanged { .. } => {
+                self.window.request_redraw();
+            }
+
+            _ => (),
+        }
+    }
+
+}
+
+fn main() {
+    let event_loop = EventLoop::builder()
+        .build()
+        .expect("event loop building");
+
+    let (context, window) = ContextBuilder::new()
+        .with_title("Zinnebeeld demo")
+        .with_backend_switching(true)
+        .build(&event_loop)
+        .expect("failed to create a context");
+
+    let mut app = App {
+        window,
+        inspectors: HashMap::new(),
+        context,
+    };
+
+    let _ = event_loop.run_app(&mut app);
+}
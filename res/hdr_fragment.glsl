@@ -0,0 +1,37 @@
+#version 140
+
+out vec4 out_color;
+
+in vec2 frag_pos;
+in vec2 frag_tex_coords;
+
+uniform sampler2D tex;
+uniform float exposure;
+uniform int tone_mapping;
+uniform bool srgb_output;
+
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 linear_to_srgb(vec3 x) {
+    x = clamp(x, 0.0, 1.0);
+    return mix(x * 12.92, 1.055 * pow(x, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, x));
+}
+
+void main() {
+    vec4 color = texture(tex, frag_tex_coords);
+    vec3 rgb = color.rgb * exp2(exposure);
+
+    if (tone_mapping == 1) {
+        rgb = rgb / (1.0 + rgb);
+    } else if (tone_mapping == 2) {
+        rgb = aces(rgb);
+    }
+
+    if (srgb_output) {
+        rgb = linear_to_srgb(rgb);
+    }
+
+    out_color = vec4(rgb, color.a);
+}
@@ -8,7 +8,11 @@ out vec2 frag_tex_coords;
 
 uniform mat4 matrix;
 
+// The area of the texture to use: offset in xy and size in zw.
+uniform vec4 tex_rect;
+
 void main() {
     gl_Position = matrix * vec4(position, 0.0, 1.0);
     frag_pos = position;
+    frag_tex_coords = tex_rect.xy + tex_coords * tex_rect.zw;
 }
@@ -1,7 +1,8 @@
 // Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
-use glium::texture::TextureCreationError;
+#[cfg(feature = "gl")]
+use glium::{glutin, texture::TextureCreationError, IncompatibleOpenGl};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -9,12 +10,77 @@ pub enum ImageLoadError {
     #[error("decode error: failed to decode")]
     DecodeError(image::ImageError),
 
+    #[error("invalid buffer size: expected {expected} bytes, but got {actual}")]
+    InvalidBufferSize {
+        expected: usize,
+        actual: usize,
+    },
+
     #[error("invalid path: the operating system cannot understand this path")]
     InvalidPath,
 
+    #[error("empty image: the width or height is zero")]
+    EmptyImage,
+
+    #[error("invalid pixel target: {0}")]
+    InvalidPixelTarget(&'static str),
+
+    #[error("region out of bounds: the region does not fit inside the image")]
+    RegionOutOfBounds,
+
+    #[error("framebuffer error: failed to create an offscreen render target")]
+    FramebufferCreation,
+
+    #[error("only uncompressed 8-bit images can be updated")]
+    ImageNotUpdatable,
+
+    #[error("unknown image: it was unloaded or created by another context")]
+    UnknownImage,
+
+    #[error("unsupported compressed format: {0}")]
+    UnsupportedCompressedFormat(String),
+
+    #[error("unknown container: not a DDS or KTX2 file")]
+    UnknownContainer,
+
+    #[error("block decode error: {0}")]
+    BlockDecode(&'static str),
+
+    #[cfg(feature = "dds")]
+    #[error("DDS error: {0}")]
+    Dds(ddsfile::Error),
+
+    #[cfg(feature = "ktx2")]
+    #[error("KTX2 error: {0}")]
+    Ktx2(ktx2::ParseError),
+
+    #[cfg(feature = "ktx2")]
+    #[error("KTX2 supercompression is not supported")]
+    Ktx2Supercompression,
+
+    #[error("sprite sheet error: {0}")]
+    SpriteSheetJson(serde_json::Error),
+
+    #[error("animated image without frames")]
+    NoFrames,
+
+    #[error("invalid sprite sheet: {0}")]
+    InvalidSpriteSheet(&'static str),
+
+    #[error("invalid nine-patch: {0}")]
+    InvalidNinePatch(&'static str),
+
+    #[cfg(feature = "svg")]
+    #[error("SVG error: {0}")]
+    Svg(resvg::usvg::Error),
+
+    #[error("unsupported by this backend: {0}")]
+    Unsupported(&'static str),
+
     #[error("I/O error: {0}")]
     Io(std::io::Error),
 
+    #[cfg(feature = "gl")]
     #[error("texture error: {0}")]
     TextureError(TextureCreationError),
 }
@@ -31,8 +97,106 @@ impl From<std::io::Error> for ImageLoadError {
     }
 }
 
+impl From<serde_json::Error> for ImageLoadError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::SpriteSheetJson(value)
+    }
+}
+
+#[cfg(feature = "gl")]
 impl From<TextureCreationError> for ImageLoadError {
     fn from(value: TextureCreationError) -> Self {
         Self::TextureError(value)
     }
 }
+
+#[cfg(feature = "dds")]
+impl From<ddsfile::Error> for ImageLoadError {
+    fn from(value: ddsfile::Error) -> Self {
+        Self::Dds(value)
+    }
+}
+
+#[cfg(feature = "ktx2")]
+impl From<ktx2::ParseError> for ImageLoadError {
+    fn from(value: ktx2::ParseError) -> Self {
+        Self::Ktx2(value)
+    }
+}
+
+#[cfg(feature = "svg")]
+impl From<resvg::usvg::Error> for ImageLoadError {
+    fn from(value: resvg::usvg::Error) -> Self {
+        Self::Svg(value)
+    }
+}
+
+/// Why a [`Context`](crate::Context) with a window couldn't be created.
+#[cfg(feature = "window")]
+#[derive(Debug, Error)]
+pub enum ContextCreationError {
+    #[error("none of the preferred backends is compiled in")]
+    NoBackend,
+
+    #[error("the context has no window")]
+    NoWindow,
+
+    #[error("backend switching isn't enabled, see ContextBuilder::with_backend_switching")]
+    SwitchingDisabled,
+
+    #[error("failed to restore an image: {0}")]
+    Image(ImageLoadError),
+
+    #[error("window error: {0}")]
+    Window(winit::error::OsError),
+
+    #[cfg(feature = "gl")]
+    #[error("OpenGL error: {0}")]
+    Gl(Box<dyn std::error::Error>),
+
+    #[cfg(feature = "software")]
+    #[error("software surface error: {0}")]
+    Surface(softbuffer::SoftBufferError),
+}
+
+#[cfg(feature = "window")]
+impl From<winit::error::OsError> for ContextCreationError {
+    fn from(value: winit::error::OsError) -> Self {
+        Self::Window(value)
+    }
+}
+
+#[cfg(feature = "window")]
+impl From<ImageLoadError> for ContextCreationError {
+    fn from(value: ImageLoadError) -> Self {
+        Self::Image(value)
+    }
+}
+
+#[cfg(feature = "gl")]
+impl From<glutin::error::Error> for ContextCreationError {
+    fn from(value: glutin::error::Error) -> Self {
+        Self::Gl(Box::new(value))
+    }
+}
+
+#[cfg(feature = "gl")]
+impl From<winit::raw_window_handle::HandleError> for ContextCreationError {
+    fn from(value: winit::raw_window_handle::HandleError) -> Self {
+        Self::Gl(Box::new(value))
+    }
+}
+
+#[cfg(feature = "gl")]
+impl From<IncompatibleOpenGl> for ContextCreationError {
+    fn from(value: IncompatibleOpenGl) -> Self {
+        Self::Gl(Box::new(value))
+    }
+}
+
+#[cfg(all(feature = "software", feature = "window"))]
+impl From<softbuffer::SoftBufferError> for ContextCreationError {
+    fn from(value: softbuffer::SoftBufferError) -> Self {
+        Self::Surface(value)
+    }
+}
@@ -0,0 +1,350 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::{io::Cursor, time::Duration};
+
+use euclid::default::Size2D;
+use image::{Frame, ImageFormat, ImageReader, RgbaImage};
+
+use crate::{Image, ImageLoadError, Material};
+
+/// How many times an [`AnimatedImage`] is played.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopCount {
+    Infinite,
+    Finite(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AnimationFrame {
+    pub image: Image,
+    pub delay: Duration,
+}
+
+/// An image consisting of multiple frames, e.g. loaded from an animated GIF,
+/// APNG or WebP file. Every frame is stored as a fully composited [`Image`].
+#[derive(Debug, Clone)]
+pub struct AnimatedImage {
+    pub(super) size: Size2D<u32>,
+    pub(super) frames: Vec<AnimationFrame>,
+    pub(super) loop_count: LoopCount,
+}
+
+impl AnimatedImage {
+    #[must_use]
+    pub const fn size(&self) -> Size2D<u32> {
+        self.size
+    }
+
+    #[must_use]
+    pub fn frames(&self) -> &[AnimationFrame] {
+        &self.frames
+    }
+
+    #[must_use]
+    pub const fn loop_count(&self) -> LoopCount {
+        self.loop_count
+    }
+
+    /// The duration of a single play of the animation.
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        self.frames.iter().map(|frame| frame.delay).sum()
+    }
+
+    /// Returns the frame that should be visible `time` after the animation
+    /// started. After the last loop has finished, the last frame stays visible.
+    #[must_use]
+    pub fn frame_at(&self, time: Duration) -> Image {
+        let last = self.frames.last().expect("animated image without frames").image;
+
+        let duration = self.duration();
+        if duration.is_zero() {
+            return last;
+        }
+
+        let play = time.as_nanos() / duration.as_nanos();
+        if let LoopCount::Finite(count) = self.loop_count {
+            if play >= count as u128 {
+                return last;
+            }
+        }
+
+        let mut remaining = Duration::from_nanos((time.as_nanos() % duration.as_nanos()) as u64);
+        for frame in &self.frames {
+            if remaining < frame.delay {
+                return frame.image;
+            }
+            remaining -= frame.delay;
+        }
+
+        last
+    }
+
+    /// Decodes the frames of an animated image. Formats that aren't animated
+    /// result in a single frame.
+    pub(super) fn decode(bytes: &[u8]) -> Result<(Vec<(RgbaImage, Duration)>, LoopCount), ImageLoadError> {
+        let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+
+        let frames: Option<Vec<Frame>> = match reader.format() {
+            #[cfg(feature = "gif")]
+            Some(ImageFormat::Gif) => {
+                use image::{codecs::gif::GifDecoder, AnimationDecoder};
+                Some(GifDecoder::new(Cursor::new(bytes))?.into_frames().collect_frames()?)
+            }
+
+            #[cfg(feature = "png")]
+            Some(ImageFormat::Png) => {
+                use image::{codecs::png::PngDecoder, AnimationDecoder};
+                let decoder = PngDecoder::new(Cursor::new(bytes))?;
+                if decoder.is_apng()? {
+                    Some(decoder.apng()?.into_frames().collect_frames()?)
+                } else {
+                    None
+                }
+            }
+
+            #[cfg(feature = "webp")]
+            Some(ImageFormat::WebP) => {
+                use image::{codecs::webp::WebPDecoder, AnimationDecoder};
+                let decoder = WebPDecoder::new(Cursor::new(bytes))?;
+                if decoder.has_animation() {
+                    Some(decoder.into_frames().collect_frames()?)
+                } else {
+                    None
+                }
+            }
+
+            _ => None,
+        };
+
+        let Some(frames) = frames else {
+            let image = reader.decode()?.to_rgba8();
+            return Ok((vec![(image, Duration::ZERO)], LoopCount::Finite(1)));
+        };
+
+        if frames.is_empty() {
+            return Err(ImageLoadError::NoFrames);
+        }
+
+        let loop_count = reader.format().map_or(LoopCount::Finite(1), |format| parse_loop_count(format, bytes));
+        let frames = frames.into_iter()
+            .map(|frame| {
+                let delay = frame.delay().into();
+                (frame.into_buffer(), delay)
+            })
+            .collect();
+
+        Ok((frames, loop_count))
+    }
+}
+
+impl From<&AnimatedImage> for Material {
+    /// Uses the first frame of the animation.
+    fn from(value: &AnimatedImage) -> Self {
+        Self::Image(value.frames[0].image)
+    }
+}
+
+/// The image crate doesn't expose the loop counts, so read them from the
+/// container ourselves. Data that can't be parsed plays once.
+fn parse_loop_count(format: ImageFormat, bytes: &[u8]) -> LoopCount {
+    let count = match format {
+        ImageFormat::Gif => parse_gif_loop_count(bytes),
+        ImageFormat::Png => parse_apng_loop_count(bytes),
+        ImageFormat::WebP => parse_webp_loop_count(bytes),
+        _ => Some(1),
+    };
+
+    match count {
+        Some(0) => LoopCount::Infinite,
+        Some(count) => LoopCount::Finite(count),
+        None => LoopCount::Finite(1),
+    }
+}
+
+/// Walks the blocks up to the first image, looking for the NETSCAPE2.0
+/// application extension. Its sub-block holds the number of repetitions
+/// *after* the first play, or `0` for infinite.
+fn parse_gif_loop_count(bytes: &[u8]) -> Option<u32> {
+    let flags = *bytes.get(10)?;
+    let mut pos = 13;
+    if flags & 0x80 != 0 {
+        pos += 3 << ((flags & 0x07) + 1);
+    }
+
+    loop {
+        match *bytes.get(pos)? {
+            0x21 => {
+                let label = *bytes.get(pos + 1)?;
+                let first = bytes.get(pos + 2..)?;
+                if label == 0xFF && first.get(..12)? == b"\x0bNETSCAPE2.0" {
+                    let sub_block = first.get(12..16)?;
+                    if sub_block[..2] != [0x03, 0x01] {
+                        return None;
+                    }
+                    let count = u16::from_le_bytes([sub_block[2], sub_block[3]]) as u32;
+                    return Some(if count == 0 { 0 } else { count + 1 });
+                }
+                pos = skip_gif_sub_blocks(bytes, pos + 2)?;
+            }
+
+            // The extension has to come before the first image.
+            0x2C | 0x3B => return Some(1),
+            _ => return None,
+        }
+    }
+}
+
+/// The position after the sub-blocks starting at `pos`, which end with an
+/// empty one.
+fn skip_gif_sub_blocks(bytes: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let size = *bytes.get(pos)? as usize;
+        pos += 1 + size;
+        if size == 0 {
+            return Some(pos);
+        }
+    }
+}
+
+/// Walks the chunks before the image data, looking for the acTL chunk with
+/// the number of frames and then the number of plays, big endian.
+fn parse_apng_loop_count(bytes: &[u8]) -> Option<u32> {
+    let mut pos = 8;
+    loop {
+        let length = u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().unwrap()) as usize;
+        let data = bytes.get(pos + 8..)?.get(..length)?;
+        match bytes.get(pos + 4..pos + 8)? {
+            b"acTL" => return Some(u32::from_be_bytes(data.get(4..8)?.try_into().unwrap())),
+            b"IDAT" | b"IEND" => return Some(1),
+            _ => pos += 12 + length,
+        }
+    }
+}
+
+/// Walks the RIFF chunks, looking for the ANIM chunk with the background
+/// color and then the loop count, little endian.
+fn parse_webp_loop_count(bytes: &[u8]) -> Option<u32> {
+    if bytes.get(..4)? != b"RIFF" || bytes.get(8..12)? != b"WEBP" {
+        return None;
+    }
+
+    let mut pos = 12;
+    while pos < bytes.len() {
+        let size = u32::from_le_bytes(bytes.get(pos + 4..pos + 8)?.try_into().unwrap()) as usize;
+        let data = bytes.get(pos + 8..)?.get(..size)?;
+        if bytes.get(pos..pos + 4)? == b"ANIM" {
+            return Some(u16::from_le_bytes(data.get(4..6)?.try_into().unwrap()) as u32);
+        }
+
+        // Chunks are padded to an even size.
+        pos += 8 + size + (size & 1);
+    }
+
+    Some(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gif(blocks: &[u8]) -> Vec<u8> {
+        let mut bytes = b"GIF89a\x01\x00\x01\x00\x00\x00\x00".to_vec();
+        bytes.extend_from_slice(blocks);
+        bytes.push(0x3B);
+        bytes
+    }
+
+    fn netscape(count: u16) -> Vec<u8> {
+        let mut bytes = b"\x21\xff\x0bNETSCAPE2.0\x03\x01".to_vec();
+        bytes.extend_from_slice(&count.to_le_bytes());
+        bytes.push(0x00);
+        bytes
+    }
+
+    #[test]
+    fn gif_loop_count() {
+        assert_eq!(parse_loop_count(ImageFormat::Gif, &gif(&netscape(0))), LoopCount::Infinite);
+        assert_eq!(parse_loop_count(ImageFormat::Gif, &gif(&netscape(2))), LoopCount::Finite(3));
+        assert_eq!(parse_loop_count(ImageFormat::Gif, &gif(&[])), LoopCount::Finite(1));
+
+        // After a comment and a global color table of two colors.
+        let mut bytes = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff".to_vec();
+        bytes.extend_from_slice(b"\x21\xfe\x02hi\x00");
+        bytes.extend_from_slice(&netscape(4));
+        assert_eq!(parse_loop_count(ImageFormat::Gif, &bytes), LoopCount::Finite(5));
+    }
+
+    #[test]
+    fn gif_loop_count_ignores_other_data() {
+        // A comment that happens to contain the name of the extension.
+        let comment = b"\x21\xfe\x0fNETSCAPE2.0\x03\x01\x00\x00\x00";
+        assert_eq!(parse_loop_count(ImageFormat::Gif, &gif(comment)), LoopCount::Finite(1));
+
+        let truncated = &gif(&netscape(0))[..20];
+        assert_eq!(parse_loop_count(ImageFormat::Gif, truncated), LoopCount::Finite(1));
+        assert_eq!(parse_loop_count(ImageFormat::Gif, b"GIF89a"), LoopCount::Finite(1));
+    }
+
+    fn png(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, data) in chunks {
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(*kind);
+            bytes.extend_from_slice(data);
+            bytes.extend_from_slice(&[0; 4]);
+        }
+        bytes
+    }
+
+    fn actl(plays: u32) -> Vec<u8> {
+        [4u32.to_be_bytes(), plays.to_be_bytes()].concat()
+    }
+
+    #[test]
+    fn apng_loop_count() {
+        let ihdr: &[u8] = &[0; 13];
+        assert_eq!(parse_loop_count(ImageFormat::Png, &png(&[(b"IHDR", ihdr), (b"acTL", &actl(0))])), LoopCount::Infinite);
+        assert_eq!(parse_loop_count(ImageFormat::Png, &png(&[(b"IHDR", ihdr), (b"acTL", &actl(5))])), LoopCount::Finite(5));
+        assert_eq!(parse_loop_count(ImageFormat::Png, &png(&[(b"IHDR", ihdr), (b"IEND", &[])])), LoopCount::Finite(1));
+    }
+
+    #[test]
+    fn apng_loop_count_ignores_other_data() {
+        // Only chunks before the image data count, not text or pixels.
+        let text = png(&[(b"tEXt", b"acTL\x00\x00\x00\x01\x00\x00\x00\x00"), (b"IEND", &[])]);
+        assert_eq!(parse_loop_count(ImageFormat::Png, &text), LoopCount::Finite(1));
+
+        let late = png(&[(b"IDAT", &[0; 4]), (b"acTL", &actl(0))]);
+        assert_eq!(parse_loop_count(ImageFormat::Png, &late), LoopCount::Finite(1));
+
+        let truncated = png(&[(b"acTL", &[0; 8])]);
+        assert_eq!(parse_loop_count(ImageFormat::Png, &truncated[..20]), LoopCount::Finite(1));
+    }
+
+    fn webp(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        for (kind, data) in chunks {
+            body.extend_from_slice(*kind);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        [b"RIFF".as_slice(), &(body.len() as u32).to_le_bytes(), &body].concat()
+    }
+
+    #[test]
+    fn webp_loop_count() {
+        let anim = |count: u16| [[0; 4].as_slice(), &count.to_le_bytes()].concat();
+
+        // After a chunk of an odd size, which is padded.
+        let bytes = webp(&[(b"VP8X", &[0; 9]), (b"ANIM", &anim(3))]);
+        assert_eq!(parse_loop_count(ImageFormat::WebP, &bytes), LoopCount::Finite(3));
+        assert_eq!(parse_loop_count(ImageFormat::WebP, &webp(&[(b"ANIM", &anim(0))])), LoopCount::Infinite);
+        assert_eq!(parse_loop_count(ImageFormat::WebP, &webp(&[(b"VP8 ", b"ANIM\x00")])), LoopCount::Finite(1));
+        assert_eq!(parse_loop_count(ImageFormat::WebP, &webp(&[(b"ANIM", &[0; 4])])), LoopCount::Finite(1));
+    }
+}
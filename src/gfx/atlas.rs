@@ -0,0 +1,345 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::collections::HashMap;
+
+use euclid::default::{Point2D, Rect, Size2D};
+use image::{imageops, GenericImageView, RgbaImage};
+
+use crate::{ResourceId, ResourceNamespace, SamplingQuality};
+
+/// The width and height of an atlas page.
+pub(super) const ATLAS_PAGE_SIZE: u32 = 2048;
+
+/// Images for which both dimensions are at most this size are put in an atlas.
+pub(super) const ATLAS_MAX_IMAGE_SIZE: u32 = 256;
+
+/// The number of mipmap levels of a page below the base level.
+pub(super) const ATLAS_MIP_LEVELS: u32 = 2;
+
+/// The transparent border around every image, so that filtering doesn't pick
+/// up the pixels of neighbouring images. Entries are also aligned to it, so
+/// each mipmap level halves the padding, keeping one texel at the lowest.
+pub(super) const ATLAS_PADDING: u32 = 1 << ATLAS_MIP_LEVELS;
+
+/// The highest quality that atlas images are sampled with, as anisotropic
+/// filtering reaches further out than the padding.
+pub(super) const ATLAS_MAX_SAMPLING: SamplingQuality = SamplingQuality::Trilinear;
+
+/// The size that an image takes up in a page, including its padding.
+fn padded_size(size: Size2D<u32>) -> Size2D<u32> {
+    let pad = |length: u32| length.next_multiple_of(ATLAS_PADDING) + ATLAS_PADDING * 2;
+    Size2D::new(pad(size.width), pad(size.height))
+}
+
+/// The location of an image inside an atlas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct AtlasEntry {
+    pub page: usize,
+
+    /// The area of the image within the page, excluding the padding, with
+    /// the origin at the top left.
+    pub rect: Rect<u32>,
+}
+
+impl AtlasEntry {
+    /// The area of the image within the page, including the padding.
+    pub fn padded_rect(&self) -> Rect<u32> {
+        let origin = self.rect.origin - Size2D::new(ATLAS_PADDING, ATLAS_PADDING);
+        Rect::new(origin, padded_size(self.rect.size))
+    }
+
+    /// The normalized texture coordinates of the image within the page.
+    pub fn uv_rect(&self) -> Rect<f32> {
+        self.rect.cast::<f32>().scale(1.0 / ATLAS_PAGE_SIZE as f32, 1.0 / ATLAS_PAGE_SIZE as f32)
+    }
+}
+
+/// The new layout of a page after compacting it. The backend must create a
+/// new, transparent page and copy every live entry from its old rectangle
+/// (the first) to its new one (the second).
+#[derive(Debug)]
+pub(super) struct Defragmentation {
+    pub page: usize,
+    pub moves: Vec<(Rect<u32>, Rect<u32>)>,
+}
+
+#[derive(Debug, Default)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    cursor: u32,
+}
+
+#[derive(Debug, Default)]
+struct PageLayout {
+    shelves: Vec<Shelf>,
+
+    /// The area of the live entries, including padding.
+    used_area: u64,
+}
+
+impl PageLayout {
+    fn allocate(&mut self, size: Size2D<u32>) -> Option<Point2D<u32>> {
+        // Prefer the tightest fitting shelf, to keep the waste low.
+        let shelf = self.shelves.iter_mut()
+            .filter(|shelf| shelf.height >= size.height && ATLAS_PAGE_SIZE - shelf.cursor >= size.width)
+            .min_by_key(|shelf| shelf.height);
+
+        let shelf = match shelf {
+            Some(shelf) => shelf,
+            None => {
+                let y = self.shelves.last().map_or(0, |shelf| shelf.y + shelf.height);
+                if ATLAS_PAGE_SIZE - y < size.height {
+                    return None;
+                }
+
+                self.shelves.push(Shelf {
+                    y,
+                    height: size.height,
+                    cursor: 0,
+                });
+                self.shelves.last_mut().unwrap()
+            }
+        };
+
+        let origin = Point2D::new(shelf.cursor, shelf.y);
+        shelf.cursor += size.width;
+        self.used_area += size.area() as u64;
+        Some(origin)
+    }
+
+    /// The area that is reserved by shelves, but no longer in use.
+    fn wasted_area(&self) -> u64 {
+        let reserved: u64 = self.shelves.iter()
+            .map(|shelf| shelf.cursor as u64 * shelf.height as u64)
+            .sum();
+        reserved - self.used_area
+    }
+}
+
+/// A shelf packer that places small images into shared pages, so that they
+/// can be drawn without switching textures.
+///
+/// [`Image`](crate::Image) handles only contain the ID of their entry; the
+/// location is looked up when drawing, so that handles stay valid when a page
+/// is compacted.
+#[derive(Debug, Default)]
+pub(super) struct AtlasAllocator {
+    pages: Vec<PageLayout>,
+    entries: HashMap<usize, AtlasEntry>,
+    id_counter: usize,
+}
+
+impl AtlasAllocator {
+    pub fn accepts(size: Size2D<u32>) -> bool {
+        !size.is_empty() && size.width <= ATLAS_MAX_IMAGE_SIZE && size.height <= ATLAS_MAX_IMAGE_SIZE
+    }
+
+    pub fn get(&self, id: ResourceId) -> Option<AtlasEntry> {
+        debug_assert_eq!(id.namespace(), ResourceNamespace::AtlasImage);
+        self.entries.get(&id.id()).copied()
+    }
+
+    /// Reserves space for an image. When the entry is on a page the backend
+    /// doesn't have yet, it must create a new, transparent page.
+    pub fn allocate(&mut self, size: Size2D<u32>) -> (ResourceId, AtlasEntry) {
+        debug_assert!(Self::accepts(size));
+        let padded = padded_size(size);
+
+        let placement = self.pages.iter_mut()
+            .enumerate()
+            .find_map(|(page, layout)| Some((page, layout.allocate(padded)?)));
+
+        let (page, origin) = placement.unwrap_or_else(|| {
+            let mut layout = PageLayout::default();
+            let origin = layout.allocate(padded).unwrap();
+            self.pages.push(layout);
+            (self.pages.len() - 1, origin)
+        });
+
+        let entry = AtlasEntry {
+            page,
+            rect: Rect::new(origin + Size2D::new(ATLAS_PADDING, ATLAS_PADDING), size),
+        };
+
+        let id = self.id_counter;
+        self.id_counter += 1;
+        self.entries.insert(id, entry);
+        (ResourceId::new(ResourceNamespace::AtlasImage, id), entry)
+    }
+
+    /// Releases the space of an image. When more than half of the reserved
+    /// space of the page is wasted afterwards, the page is compacted.
+    pub fn free(&mut self, id: ResourceId) -> Option<Defragmentation> {
+        let entry = self.entries.remove(&id.id())?;
+        let padded = padded_size(entry.rect.size);
+
+        let layout = &mut self.pages[entry.page];
+        layout.used_area -= padded.area() as u64;
+
+        if layout.used_area == 0 {
+            *layout = PageLayout::default();
+            return None;
+        }
+
+        if layout.wasted_area() <= layout.used_area {
+            return None;
+        }
+
+        self.defragment(entry.page)
+    }
+
+    fn defragment(&mut self, page: usize) -> Option<Defragmentation> {
+        let mut live: Vec<_> = self.entries.iter_mut()
+            .filter(|(_, entry)| entry.page == page)
+            .map(|(_, entry)| entry)
+            .collect();
+
+        // Tallest first gives the fewest, tightest shelves.
+        live.sort_by_key(|entry| std::cmp::Reverse(entry.rect.height()));
+
+        let mut layout = PageLayout::default();
+        let mut new_rects = Vec::with_capacity(live.len());
+        for entry in &live {
+            let padded = padded_size(entry.rect.size);
+            // Should never happen, but keep the old layout if it does.
+            let origin = layout.allocate(padded)?;
+            new_rects.push(Rect::new(origin + Size2D::new(ATLAS_PADDING, ATLAS_PADDING), entry.rect.size));
+        }
+
+        let moves = live.into_iter()
+            .zip(new_rects)
+            .map(|(entry, new_rect)| {
+                let old_rect = std::mem::replace(&mut entry.rect, new_rect);
+                (old_rect, new_rect)
+            })
+            .collect();
+
+        self.pages[page] = layout;
+        Some(Defragmentation {
+            page,
+            moves,
+        })
+    }
+}
+
+/// Writes `image` into the page at `entry`, clearing the padding around it.
+pub(super) fn write_entry(page: &mut RgbaImage, entry: AtlasEntry, image: &RgbaImage) -> RgbaImage {
+    let padded_rect = entry.padded_rect();
+    let mut padded = RgbaImage::new(padded_rect.width(), padded_rect.height());
+    imageops::replace(&mut padded, image, ATLAS_PADDING as i64, ATLAS_PADDING as i64);
+    imageops::replace(page, &padded, padded_rect.min_x() as i64, padded_rect.min_y() as i64);
+    padded
+}
+
+/// Builds the compacted page, see [`Defragmentation`].
+pub(super) fn apply_defragmentation(page: &RgbaImage, defragmentation: &Defragmentation) -> RgbaImage {
+    let mut compacted = RgbaImage::new(page.width(), page.height());
+    for (from, to) in &defragmentation.moves {
+        let view = page.view(from.min_x(), from.min_y(), from.width(), from.height());
+        imageops::replace(&mut compacted, &*view, to.min_x() as i64, to.min_y() as i64);
+    }
+    compacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_disjoint(entries: &[AtlasEntry]) {
+        let page = Rect::from_size(Size2D::new(ATLAS_PAGE_SIZE, ATLAS_PAGE_SIZE));
+        for (i, a) in entries.iter().enumerate() {
+            assert!(page.contains_rect(&a.padded_rect()), "{a:?}");
+            for b in &entries[i + 1..] {
+                assert!(a.page != b.page || !a.padded_rect().intersects(&b.padded_rect()), "{a:?} {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn accepts_small_images() {
+        assert!(AtlasAllocator::accepts(Size2D::new(1, 1)));
+        assert!(AtlasAllocator::accepts(Size2D::new(ATLAS_MAX_IMAGE_SIZE, ATLAS_MAX_IMAGE_SIZE)));
+        assert!(!AtlasAllocator::accepts(Size2D::new(ATLAS_MAX_IMAGE_SIZE + 1, 1)));
+        assert!(!AtlasAllocator::accepts(Size2D::new(0, 16)));
+    }
+
+    #[test]
+    fn allocations_do_not_overlap() {
+        let mut allocator = AtlasAllocator::default();
+        let entries: Vec<_> = (0..400)
+            .map(|i| allocator.allocate(Size2D::new(16 + i % 7 * 30, 16 + i % 5 * 40)).1)
+            .collect();
+
+        assert!(entries.iter().any(|entry| entry.page > 0), "expected multiple pages");
+        assert_disjoint(&entries);
+    }
+
+    #[test]
+    fn entries_are_aligned_for_the_mipmap_levels() {
+        let mut allocator = AtlasAllocator::default();
+        let entries: Vec<_> = (1..40).map(|i| allocator.allocate(Size2D::new(i, 41 - i)).1).collect();
+
+        for entry in &entries {
+            assert_eq!(entry.rect.origin.x % ATLAS_PADDING, 0, "{entry:?}");
+            assert_eq!(entry.rect.origin.y % ATLAS_PADDING, 0, "{entry:?}");
+        }
+        assert_disjoint(&entries);
+    }
+
+    #[test]
+    fn freeing_everything_resets_the_page() {
+        let mut allocator = AtlasAllocator::default();
+        let (id, first) = allocator.allocate(Size2D::new(32, 32));
+        assert!(allocator.free(id).is_none());
+        assert!(allocator.get(id).is_none());
+
+        let (_, second) = allocator.allocate(Size2D::new(32, 32));
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn defragmentation_compacts_live_entries() {
+        let mut allocator = AtlasAllocator::default();
+        let ids: Vec<_> = (0..64).map(|_| allocator.allocate(Size2D::new(64, 64)).0).collect();
+
+        let mut defragmentation = None;
+        for id in &ids[..60] {
+            defragmentation = defragmentation.or(allocator.free(*id));
+        }
+
+        let defragmentation = defragmentation.expect("the page should have been compacted");
+        assert_eq!(defragmentation.page, 0);
+        for (from, to) in &defragmentation.moves {
+            assert_eq!(from.size, to.size);
+        }
+
+        let live: Vec<_> = ids[60..].iter().map(|id| allocator.get(*id).unwrap()).collect();
+        assert_disjoint(&live);
+    }
+
+    #[test]
+    fn pixels_follow_their_entries() {
+        let mut allocator = AtlasAllocator::default();
+        let mut page = RgbaImage::from_pixel(ATLAS_PAGE_SIZE, ATLAS_PAGE_SIZE, image::Rgba([9; 4]));
+
+        let (first, entry) = allocator.allocate(Size2D::new(8, 8));
+        write_entry(&mut page, entry, &RgbaImage::from_pixel(8, 8, image::Rgba([1; 4])));
+        let (second, entry) = allocator.allocate(Size2D::new(4, 4));
+        let padded = write_entry(&mut page, entry, &RgbaImage::from_pixel(4, 4, image::Rgba([2; 4])));
+
+        // The padding is cleared.
+        assert_eq!(padded.dimensions(), (4 + ATLAS_PADDING * 2, 4 + ATLAS_PADDING * 2));
+        assert_eq!(page.get_pixel(entry.rect.min_x() - ATLAS_PADDING, entry.rect.min_y()).0, [0; 4]);
+        assert_eq!(page.get_pixel(entry.rect.min_x() - 1, entry.rect.min_y()).0, [0; 4]);
+
+        let defragmentation = allocator.defragment(0).unwrap();
+        let page = apply_defragmentation(&page, &defragmentation);
+
+        for (id, value) in [(first, 1), (second, 2)] {
+            let rect = allocator.get(id).unwrap().rect;
+            assert!(page.view(rect.min_x(), rect.min_y(), rect.width(), rect.height()).pixels().all(|(_, _, pixel)| pixel.0 == [value; 4]));
+        }
+    }
+}
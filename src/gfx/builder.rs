@@ -0,0 +1,271 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::{env::var, rc::Rc};
+
+use euclid::default::Size2D;
+use winit::{
+    dpi::PhysicalSize,
+    event_loop::EventLoop,
+    window::{Window, WindowAttributes},
+};
+
+use crate::{Color, Context, ContextCreationError, ContextImplementation, EventTy};
+
+#[cfg(feature = "gl")]
+use crate::GLContext;
+
+#[cfg(feature = "software")]
+use super::soft::SoftwareContext;
+
+use super::retained::Retained;
+
+/// A renderer that can paint into a window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    Gl,
+    Software,
+}
+
+impl Backend {
+    /// Whether the backend is compiled in, see the `gl` and `software`
+    /// features.
+    #[must_use]
+    pub const fn is_available(&self) -> bool {
+        match self {
+            Self::Gl => cfg!(feature = "gl"),
+            Self::Software => cfg!(feature = "software"),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gl" => Some(Self::Gl),
+            "software" => Some(Self::Software),
+            _ => None,
+        }
+    }
+}
+
+/// Configures the window and backend of a [`Context`].
+///
+/// The `ZINNEBEELD_CTX` environment variable (`gl` or `software`) moves that
+/// backend to the front of the preference, for debugging, keeping the others
+/// as fallbacks. By default OpenGL is preferred, falling back to software
+/// rendering when it isn't available.
+#[derive(Debug, Clone)]
+pub struct ContextBuilder {
+    pub(super) backends: Vec<Backend>,
+    pub(super) title: String,
+    pub(super) size: Size2D<u32>,
+    pub(super) min_size: Option<Size2D<u32>>,
+    pub(super) resizable: bool,
+    pub(super) decorations: bool,
+    pub(super) transparent: bool,
+    pub(super) vsync: bool,
+    pub(super) samples: u8,
+    pub(super) clear_color: Color,
+    pub(super) backend_switching: bool,
+}
+
+impl Default for ContextBuilder {
+    fn default() -> Self {
+        Self {
+            backends: vec![Backend::Gl, Backend::Software],
+            title: String::from("Zinnebeeld"),
+            size: Size2D::new(1600, 1200),
+            min_size: None,
+            resizable: true,
+            decorations: true,
+            transparent: false,
+            vsync: true,
+            samples: 0,
+            clear_color: Color::BLACK,
+            backend_switching: false,
+        }
+    }
+}
+
+impl ContextBuilder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The backends to try, most preferred first. Backends that aren't
+    /// compiled in are skipped.
+    #[must_use]
+    pub fn with_backends(mut self, backends: &[Backend]) -> Self {
+        self.backends = backends.to_vec();
+        self
+    }
+
+    #[must_use]
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// The initial size of the window, in physical pixels.
+    #[must_use]
+    pub const fn with_size(mut self, size: Size2D<u32>) -> Self {
+        self.size = size;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_min_size(mut self, size: Size2D<u32>) -> Self {
+        self.min_size = Some(size);
+        self
+    }
+
+    #[must_use]
+    pub const fn with_resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_decorations(mut self, decorations: bool) -> Self {
+        self.decorations = decorations;
+        self
+    }
+
+    /// Lets the desktop show through the window where the painted colors
+    /// aren't opaque. Combine with a transparent [`Self::with_clear_color`].
+    #[must_use]
+    pub const fn with_transparency(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        self
+    }
+
+    /// Waits for the vertical blank when presenting. Only used by the GL
+    /// backend.
+    #[must_use]
+    pub const fn with_vsync(mut self, vsync: bool) -> Self {
+        self.vsync = vsync;
+        self
+    }
+
+    /// The number of samples per pixel for multisample anti-aliasing, a power
+    /// of two or `0` to disable it. Only used by the GL backend.
+    #[must_use]
+    pub const fn with_samples(mut self, samples: u8) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// The color that every frame starts with.
+    #[must_use]
+    pub const fn with_clear_color(mut self, color: Color) -> Self {
+        self.clear_color = color;
+        self
+    }
+
+    /// Keeps a copy of every image, so that [`Context::switch_backend`] can
+    /// upload them again. This costs memory, and decoding files and copying
+    /// pixels when images are updated.
+    #[must_use]
+    pub const fn with_backend_switching(mut self, enabled: bool) -> Self {
+        self.backend_switching = enabled;
+        self
+    }
+
+    /// Creates the window with the first of the backends that is compiled in
+    /// and can be created. The failures of the preferred backends are logged
+    /// and available through [`Context::backend_info`].
+    pub fn build(self, event_loop: &EventLoop<EventTy>) -> Result<(Context, Rc<Window>), ContextCreationError> {
+        let backends = self.preferred_backends(var("ZINNEBEELD_CTX").ok().and_then(|name| Backend::from_name(&name)));
+
+        let mut fallback_reasons = Vec::new();
+        let mut last_error = ContextCreationError::NoBackend;
+
+        for backend in backends.into_iter().filter(Backend::is_available) {
+            let result = match backend {
+                #[cfg(feature = "gl")]
+                Backend::Gl => GLContext::new(event_loop, &self),
+                #[cfg(feature = "software")]
+                Backend::Software => SoftwareContext::new(event_loop, &self),
+                #[allow(unreachable_patterns)]
+                _ => unreachable!("the backend is available"),
+            };
+
+            match result {
+                Ok((inner, window)) => {
+                    let mut context = Context::with_implementation(inner);
+                    context.fallback_reasons = fallback_reasons;
+                    context.retained = Some(Retained::new(Rc::clone(&window), self));
+                    return Ok((context, window));
+                }
+
+                Err(error) => {
+                    log::warn!("failed to create the {backend:?} backend: {error}");
+                    fallback_reasons.push(format!("{backend:?}: {error}"));
+                    last_error = error;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// The backends in the order they are tried, with `first` before the
+    /// others.
+    fn preferred_backends(&self, first: Option<Backend>) -> Vec<Backend> {
+        let mut backends = self.backends.clone();
+        if let Some(first) = first {
+            backends.retain(|backend| *backend != first);
+            backends.insert(0, first);
+        }
+        backends
+    }
+
+    /// Creates the backend on the window of a context, for
+    /// [`Context::switch_backend`]. The backend must be available.
+    pub(super) fn create_on_window(
+        &self,
+        backend: Backend,
+        window: &Rc<Window>,
+    ) -> Result<Box<dyn ContextImplementation>, ContextCreationError> {
+        match backend {
+            #[cfg(feature = "gl")]
+            Backend::Gl => GLContext::with_window(Rc::clone(window), self),
+            #[cfg(feature = "software")]
+            Backend::Software => SoftwareContext::with_window(Rc::clone(window), self),
+            #[allow(unreachable_patterns)]
+            _ => unreachable!("the backend is available"),
+        }
+    }
+
+    pub(super) fn window_attributes(&self) -> WindowAttributes {
+        let mut attributes = Window::default_attributes()
+            .with_title(&self.title)
+            .with_inner_size(PhysicalSize::new(self.size.width, self.size.height))
+            .with_resizable(self.resizable)
+            .with_decorations(self.decorations)
+            .with_transparent(self.transparent);
+
+        if let Some(size) = self.min_size {
+            attributes = attributes.with_min_inner_size(PhysicalSize::new(size.width, size.height));
+        }
+
+        attributes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preferred_backend_goes_first() {
+        let builder = ContextBuilder::new();
+        assert_eq!(builder.preferred_backends(None), [Backend::Gl, Backend::Software]);
+        assert_eq!(builder.preferred_backends(Some(Backend::Software)), [Backend::Software, Backend::Gl]);
+        assert_eq!(builder.preferred_backends(Some(Backend::Gl)), [Backend::Gl, Backend::Software]);
+
+        // The override is tried even when it wasn't preferred.
+        let builder = ContextBuilder::new().with_backends(&[Backend::Gl]);
+        assert_eq!(builder.preferred_backends(Some(Backend::Software)), [Backend::Software, Backend::Gl]);
+    }
+}
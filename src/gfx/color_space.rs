@@ -0,0 +1,51 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use image::RgbaImage;
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+
+/// The RGB color space that pixels are stored in, once loaded into a
+/// [`Context`](crate::Context).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+    Bt2020,
+}
+
+impl ColorSpace {
+    fn profile(&self) -> ColorProfile {
+        match self {
+            Self::Srgb => ColorProfile::new_srgb(),
+            Self::DisplayP3 => ColorProfile::new_display_p3(),
+            Self::AdobeRgb => ColorProfile::new_adobe_rgb(),
+            Self::Bt2020 => ColorProfile::new_bt2020(),
+        }
+    }
+
+    /// Converts the pixels from the embedded ICC profile, or sRGB when there
+    /// isn't one, into this color space.
+    ///
+    /// Broken or non-RGB profiles are treated as sRGB, like browsers do.
+    pub(super) fn convert_from_icc(&self, image: &mut RgbaImage, icc: Option<&[u8]>) {
+        let source = icc
+            .and_then(|icc| ColorProfile::new_from_slice(icc).ok())
+            .filter(|profile| profile.color_space == DataColorSpace::Rgb);
+
+        let source = match source {
+            Some(profile) => profile,
+            None if *self == Self::Srgb => return,
+            None => ColorProfile::new_srgb(),
+        };
+
+        let Ok(transform) = source.create_transform_8bit(Layout::Rgba, &self.profile(), Layout::Rgba, TransformOptions::default()) else {
+            return;
+        };
+
+        let src = image.as_raw().clone();
+        // Both buffers have the same layout, so this can't fail.
+        _ = transform.transform(&src, image);
+    }
+}
@@ -0,0 +1,254 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use euclid::default::Size2D;
+use image::RgbaImage;
+
+use crate::{ImageLoadError, PixelFormat};
+
+/// A block-compression scheme of a [`CompressedImage`]. Every block encodes
+/// 4x4 pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFormat {
+    /// BC1 (DXT1) without alpha.
+    Bc1,
+    /// BC1 (DXT1) with 1-bit alpha.
+    Bc1a,
+    /// BC2 (DXT3)
+    Bc2,
+    /// BC3 (DXT5)
+    Bc3,
+    /// BC4 (RGTC1), a single channel.
+    Bc4,
+    /// BC5 (RGTC2), two channels.
+    Bc5,
+    /// BC6H, unsigned HDR. Decoded values are clamped to `0.0..=1.0`.
+    Bc6h,
+    /// BC7 (BPTC)
+    Bc7,
+    Etc2Rgb,
+    Etc2Rgba1,
+    Etc2Rgba8,
+}
+
+impl BlockFormat {
+    #[cfg(feature = "gl")]
+    pub(super) const ALL: [Self; 11] = [
+        Self::Bc1,
+        Self::Bc1a,
+        Self::Bc2,
+        Self::Bc3,
+        Self::Bc4,
+        Self::Bc5,
+        Self::Bc6h,
+        Self::Bc7,
+        Self::Etc2Rgb,
+        Self::Etc2Rgba1,
+        Self::Etc2Rgba8,
+    ];
+
+    #[must_use]
+    pub const fn bytes_per_block(&self) -> usize {
+        match self {
+            Self::Bc1 | Self::Bc1a | Self::Bc4 | Self::Etc2Rgb | Self::Etc2Rgba1 => 8,
+            Self::Bc2 | Self::Bc3 | Self::Bc5 | Self::Bc6h | Self::Bc7 | Self::Etc2Rgba8 => 16,
+        }
+    }
+
+    /// The number of bytes of a level of the given size.
+    #[must_use]
+    pub const fn level_size(&self, size: Size2D<u32>) -> usize {
+        let blocks_x = size.width.div_ceil(4) as usize;
+        let blocks_y = size.height.div_ceil(4) as usize;
+        blocks_x * blocks_y * self.bytes_per_block()
+    }
+
+    fn decode(&self, data: &[u8], width: usize, height: usize, output: &mut [u32]) -> Result<(), &'static str> {
+        use texture2ddecoder::*;
+        match self {
+            Self::Bc1 => decode_bc1(data, width, height, output),
+            Self::Bc1a => decode_bc1a(data, width, height, output),
+            Self::Bc2 => decode_bc2(data, width, height, output),
+            Self::Bc3 => decode_bc3(data, width, height, output),
+            Self::Bc4 => decode_bc4(data, width, height, output),
+            Self::Bc5 => decode_bc5(data, width, height, output),
+            Self::Bc6h => decode_bc6_unsigned(data, width, height, output),
+            Self::Bc7 => decode_bc7(data, width, height, output),
+            Self::Etc2Rgb => decode_etc2_rgb(data, width, height, output),
+            Self::Etc2Rgba1 => decode_etc2_rgba1(data, width, height, output),
+            Self::Etc2Rgba8 => decode_etc2_rgba8(data, width, height, output),
+        }
+    }
+}
+
+/// A pre-compressed texture as stored in a DDS or KTX2 file, including the
+/// mipmap levels that are present in the file.
+#[derive(Debug, Clone)]
+pub struct CompressedImage {
+    pub format: BlockFormat,
+    pub size: Size2D<u32>,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    pub(super) fn parse(bytes: &[u8]) -> Result<Self, ImageLoadError> {
+        #[cfg(feature = "dds")]
+        if bytes.starts_with(b"DDS ") {
+            return Self::parse_dds(bytes);
+        }
+
+        #[cfg(feature = "ktx2")]
+        if bytes.starts_with(b"\xABKTX 20\xBB\r\n\x1A\n") {
+            return Self::parse_ktx2(bytes);
+        }
+
+        _ = bytes;
+        Err(ImageLoadError::UnknownContainer)
+    }
+
+    #[cfg(feature = "dds")]
+    fn parse_dds(bytes: &[u8]) -> Result<Self, ImageLoadError> {
+        use ddsfile::{D3DFormat, Dds, DxgiFormat};
+
+        let dds = Dds::read(bytes)?;
+
+        let format = match (dds.get_dxgi_format(), dds.get_d3d_format()) {
+            (Some(DxgiFormat::BC1_UNorm | DxgiFormat::BC1_UNorm_sRGB), _) => BlockFormat::Bc1a,
+            (Some(DxgiFormat::BC2_UNorm | DxgiFormat::BC2_UNorm_sRGB), _) => BlockFormat::Bc2,
+            (Some(DxgiFormat::BC3_UNorm | DxgiFormat::BC3_UNorm_sRGB), _) => BlockFormat::Bc3,
+            (Some(DxgiFormat::BC4_UNorm), _) => BlockFormat::Bc4,
+            (Some(DxgiFormat::BC5_UNorm), _) => BlockFormat::Bc5,
+            (Some(DxgiFormat::BC6H_UF16), _) => BlockFormat::Bc6h,
+            (Some(DxgiFormat::BC7_UNorm | DxgiFormat::BC7_UNorm_sRGB), _) => BlockFormat::Bc7,
+            (None, Some(D3DFormat::DXT1)) => BlockFormat::Bc1a,
+            (None, Some(D3DFormat::DXT3)) => BlockFormat::Bc2,
+            (None, Some(D3DFormat::DXT5)) => BlockFormat::Bc3,
+            (dxgi, d3d) => {
+                let name = dxgi.map(|f| format!("{f:?}")).or(d3d.map(|f| format!("{f:?}")));
+                return Err(ImageLoadError::UnsupportedCompressedFormat(name.unwrap_or_default()));
+            }
+        };
+
+        let size = Size2D::new(dds.get_width(), dds.get_height());
+        let data = dds.get_data(0)?;
+        Self::split_levels(format, size, dds.get_num_mipmap_levels(), data)
+    }
+
+    #[cfg(feature = "ktx2")]
+    fn parse_ktx2(bytes: &[u8]) -> Result<Self, ImageLoadError> {
+        use ktx2::{Format, Reader};
+
+        let reader = Reader::new(bytes)?;
+        let header = reader.header();
+        if header.supercompression_scheme.is_some() {
+            return Err(ImageLoadError::Ktx2Supercompression);
+        }
+
+        let format = match header.format {
+            Some(Format::BC1_RGB_UNORM_BLOCK | Format::BC1_RGB_SRGB_BLOCK) => BlockFormat::Bc1,
+            Some(Format::BC1_RGBA_UNORM_BLOCK | Format::BC1_RGBA_SRGB_BLOCK) => BlockFormat::Bc1a,
+            Some(Format::BC2_UNORM_BLOCK | Format::BC2_SRGB_BLOCK) => BlockFormat::Bc2,
+            Some(Format::BC3_UNORM_BLOCK | Format::BC3_SRGB_BLOCK) => BlockFormat::Bc3,
+            Some(Format::BC4_UNORM_BLOCK) => BlockFormat::Bc4,
+            Some(Format::BC5_UNORM_BLOCK) => BlockFormat::Bc5,
+            Some(Format::BC6H_UFLOAT_BLOCK) => BlockFormat::Bc6h,
+            Some(Format::BC7_UNORM_BLOCK | Format::BC7_SRGB_BLOCK) => BlockFormat::Bc7,
+            Some(Format::ETC2_R8G8B8_UNORM_BLOCK | Format::ETC2_R8G8B8_SRGB_BLOCK) => BlockFormat::Etc2Rgb,
+            Some(Format::ETC2_R8G8B8A1_UNORM_BLOCK | Format::ETC2_R8G8B8A1_SRGB_BLOCK) => BlockFormat::Etc2Rgba1,
+            Some(Format::ETC2_R8G8B8A8_UNORM_BLOCK | Format::ETC2_R8G8B8A8_SRGB_BLOCK) => BlockFormat::Etc2Rgba8,
+            format => return Err(ImageLoadError::UnsupportedCompressedFormat(format!("{format:?}"))),
+        };
+
+        let size = Size2D::new(header.pixel_width, header.pixel_height.max(1));
+
+        // Every level contains all layers and faces, of which only the first
+        // one is used.
+        let levels = reader.levels()
+            .enumerate()
+            .map(|(index, level)| {
+                let level_size = format.level_size(mip_size(size, index as u32));
+                level.data.get(..level_size)
+                    .map(<[u8]>::to_vec)
+                    .ok_or(ImageLoadError::BlockDecode("level data is truncated"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            format,
+            size,
+            levels,
+        })
+    }
+
+    /// Splits the mip chain of a DDS file, in which levels are stored
+    /// back-to-back.
+    #[cfg(feature = "dds")]
+    fn split_levels(format: BlockFormat, size: Size2D<u32>, count: u32, mut data: &[u8]) -> Result<Self, ImageLoadError> {
+        let mut levels = Vec::new();
+        for index in 0..count.max(1) {
+            let level_size = format.level_size(mip_size(size, index));
+            let Some((level, rest)) = data.split_at_checked(level_size) else {
+                return Err(ImageLoadError::BlockDecode("level data is truncated"));
+            };
+
+            levels.push(level.to_vec());
+            data = rest;
+        }
+
+        Ok(Self {
+            format,
+            size,
+            levels,
+        })
+    }
+
+    /// Decodes the first level into pixels, for backends that cannot sample
+    /// the compressed data directly.
+    pub fn decode(&self) -> Result<RgbaImage, ImageLoadError> {
+        let (width, height) = (self.size.width as usize, self.size.height as usize);
+        let level = self.levels.first().ok_or(ImageLoadError::BlockDecode("the image has no levels"))?;
+        let mut pixels = vec![0u32; width * height];
+        self.format.decode(level, width, height, &mut pixels)
+            .map_err(ImageLoadError::BlockDecode)?;
+
+        // The decoder produces BGRA in little endian.
+        let bytes: Vec<u8> = pixels.into_iter().flat_map(u32::to_le_bytes).collect();
+        PixelFormat::Bgra8.to_rgba(self.size, &bytes)
+    }
+}
+
+/// The size of mipmap level `level`.
+#[cfg(any(feature = "gl", feature = "dds", feature = "ktx2"))]
+#[must_use]
+pub(super) fn mip_size(size: Size2D<u32>, level: u32) -> Size2D<u32> {
+    Size2D::new((size.width >> level).max(1), (size.height >> level).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_block() {
+        // A single BC1 block with red as the first color, used by all texels.
+        let image = CompressedImage {
+            format: BlockFormat::Bc1,
+            size: Size2D::new(4, 4),
+            levels: vec![vec![0x00, 0xF8, 0x00, 0x00, 0, 0, 0, 0]],
+        };
+
+        let pixels = image.decode().unwrap();
+        assert!(pixels.pixels().all(|pixel| pixel.0 == [0xFF, 0, 0, 0xFF]));
+    }
+
+    #[test]
+    fn decode_without_levels() {
+        let image = CompressedImage {
+            format: BlockFormat::Bc1,
+            size: Size2D::new(4, 4),
+            levels: Vec::new(),
+        };
+
+        assert!(matches!(image.decode(), Err(ImageLoadError::BlockDecode(_))));
+    }
+}
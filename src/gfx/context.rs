@@ -1,65 +1,928 @@
 // Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
-use std::{collections::HashMap, env::var, path::{Path, PathBuf}, rc::Rc};
+use std::{collections::HashMap, path::{Path, PathBuf}};
 
-use euclid::default::Size2D;
-use glium::winit::{event_loop::EventLoop, window::Window};
+use euclid::default::{Point2D, Rect, Size2D};
+use image::{Rgba32FImage, RgbaImage};
 
-use crate::{EventTy, GLContext, Image, ImageLoadError, Painter};
+use crate::{AnimatedImage, AnimationFrame, BlockFormat, ColorSpace, CompressedImage, EmbeddedImage, Image, ImageLoadError, ImageLoadOptions, NinePatch, NineSlice, Painter, PixelFormat, ResourceNamespace, SpriteGrid, SpriteSheet, StreamingImage};
 
-use super::{painter::PainterImplementation, soft::SoftwareContext};
+#[cfg(feature = "window")]
+use std::rc::Rc;
+
+#[cfg(feature = "window")]
+use winit::{
+    event_loop::{ActiveEventLoop, EventLoop},
+    window::{Window, WindowAttributes, WindowId},
+};
+
+#[cfg(feature = "window")]
+use crate::{Backend, ContextBuilder, ContextCreationError, EventTy};
+
+#[cfg(feature = "software")]
+use crate::PixelTarget;
+
+#[cfg(feature = "svg")]
+use crate::SvgDocument;
+
+#[cfg(feature = "pdf-export")]
+use crate::{PdfContext, PdfOutput};
+
+#[cfg(feature = "svg-export")]
+use crate::{SvgContext, SvgOutput};
+
+#[cfg(feature = "terminal")]
+use crate::{TerminalContext, TerminalMode};
+
+#[cfg(feature = "svg")]
+use super::svg;
+
+#[cfg(feature = "software")]
+use super::soft::PixelContext;
+
+#[cfg(feature = "window")]
+use super::retained::{Retained, RetainedPainter};
+
+use super::{nine_slice::DecodedNinePatch, painter::PainterImplementation, sprite::SpriteSheetDescription};
 
 pub trait ContextImplementation {
     fn resize(&mut self, size: Size2D<u32>);
 
-    fn load_image(&mut self, path: &Path) -> Result<Image, ImageLoadError>;
+    fn create_image(&mut self, image: RgbaImage) -> Result<Image, ImageLoadError>;
+
+    /// Replaces the pixels of `image` at `region` with `pixels`, which has the
+    /// same size as the region. The region is guaranteed to be within bounds.
+    fn update_image(&mut self, image: Image, region: Rect<u32>, pixels: RgbaImage) -> Result<(), ImageLoadError>;
+
+    /// Releases the memory of the image. The handle must not be used afterwards.
+    fn unload_image(&mut self, image: Image);
+
+    fn create_hdr_image(&mut self, image: Rgba32FImage) -> Result<Image, ImageLoadError>;
+
+    /// Uploads the compressed data as-is when the backend supports sampling
+    /// it, and decodes it otherwise.
+    fn create_compressed_image(&mut self, image: CompressedImage) -> Result<Image, ImageLoadError>;
+
+    /// Paints a frame, of which the coordinates are multiplied by
+    /// `scale_factor` to get the pixels of the target.
+    fn paint_frame(&self, scale_factor: f64, f: &mut dyn FnMut(&mut dyn PainterImplementation));
+
+    /// Paints into a new floating point target, instead of the window.
+    fn paint_offscreen_hdr(
+        &self,
+        size: Size2D<u32>,
+        f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<Rgba32FImage, ImageLoadError>;
+
+    /// Paints a frame into memory of the program with the software renderer,
+    /// instead of the window. Other backends don't support this.
+    #[cfg(feature = "software")]
+    fn paint_pixels(
+        &self,
+        target: &mut dyn PixelTarget,
+        scale_factor: f64,
+        f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<(), ImageLoadError> {
+        _ = (target, scale_factor, f);
+        Err(ImageLoadError::Unsupported("only the software backends can paint into pixels"))
+    }
+
+    /// Describes the backend, leaving the fallback reasons empty.
+    fn backend_info(&self) -> BackendInfo;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Creates a window that is compatible with the backend, and attaches it.
+    #[cfg(feature = "window")]
+    fn create_window(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        attributes: WindowAttributes,
+    ) -> Result<Rc<Window>, ContextCreationError> {
+        let window = Rc::new(event_loop.create_window(attributes)?);
+        self.attach_window(Rc::clone(&window))?;
+        Ok(window)
+    }
+
+    /// Creates a surface for another window, which shares the resources.
+    /// Backends without windows don't support this.
+    #[cfg(feature = "window")]
+    fn attach_window(&mut self, window: Rc<Window>) -> Result<(), ContextCreationError> {
+        _ = window;
+        Err(ContextCreationError::NoWindow)
+    }
+
+    #[cfg(feature = "window")]
+    fn detach_window(&mut self, id: WindowId) {
+        _ = id;
+    }
+
+    /// Like [`Self::resize`], for an attached window.
+    #[cfg(feature = "window")]
+    fn resize_window(&mut self, id: WindowId, size: Size2D<u32>) {
+        _ = (id, size);
+    }
+
+    /// Like [`Self::paint_frame`], for an attached window.
+    #[cfg(feature = "window")]
+    fn paint_window(&self, id: WindowId, scale_factor: f64, f: &mut dyn FnMut(&mut dyn PainterImplementation)) {
+        _ = (id, scale_factor, f);
+    }
+}
 
-    fn paint_frame(&self, f: &mut dyn FnMut(&mut dyn PainterImplementation));
+/// Describes the backend that a [`Context`] paints with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendInfo {
+    /// A short name, e.g. `OpenGL` or `software`.
+    pub name: &'static str,
+
+    /// Details such as the OpenGL renderer and version, when known.
+    pub description: Option<String>,
+
+    /// Why each of the backends that were preferred over this one couldn't
+    /// be created.
+    pub fallback_reasons: Vec<String>,
+}
+
+impl BackendInfo {
+    pub(super) const fn new(name: &'static str, description: Option<String>) -> Self {
+        Self {
+            name,
+            description,
+            fallback_reasons: Vec::new(),
+        }
+    }
+}
+
+/// What the backend of a [`Context`] supports, to choose between strategies
+/// that depend on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The largest width and height of an image, in pixels.
+    pub max_texture_size: u32,
+
+    /// The formats that images can be created from.
+    pub pixel_formats: Vec<PixelFormat>,
+
+    /// The largest number of samples per pixel for multisample anti-aliasing,
+    /// or `0` when it isn't supported. See [`ContextBuilder::with_samples`].
+    pub max_samples: u8,
+
+    /// How the painted colors can be combined with the ones below them.
+    pub blend_modes: Vec<BlendMode>,
+
+    /// Whether HDR images keep their floating point values. Otherwise, they're
+    /// tone mapped to 8 bits or [`Context::load_hdr_image`] fails, depending
+    /// on the backend.
+    pub float_textures: bool,
+
+    /// The formats that are sampled without decoding them first. Other
+    /// formats are decoded when the image is loaded.
+    pub compressed_formats: Vec<BlockFormat>,
+
+    /// Whether painting is done by a GPU, rather than by the CPU.
+    pub hardware_accelerated: bool,
+}
+
+impl Capabilities {
+    /// The fixed feature set of the software and export backends, which paint
+    /// on the CPU. Their images are limited by memory, and by sizes having to
+    /// fit in an `i32`, like those of PNG files. Compressed images are decoded
+    /// when they're loaded.
+    #[cfg(any(feature = "software", feature = "svg-export", feature = "pdf-export"))]
+    pub(super) fn cpu() -> Self {
+        Self {
+            max_texture_size: i32::MAX as u32,
+            pixel_formats: PixelFormat::ALL.to_vec(),
+            max_samples: 0,
+            blend_modes: vec![BlendMode::SourceOver],
+            float_textures: true,
+            compressed_formats: Vec::new(),
+            hardware_accelerated: false,
+        }
+    }
+
+    /// Documents embed 8-bit images, so HDR images are tone mapped when
+    /// they're written.
+    #[cfg(any(feature = "svg-export", feature = "pdf-export"))]
+    pub(super) fn export() -> Self {
+        Self {
+            float_textures: false,
+            ..Self::cpu()
+        }
+    }
+}
+
+/// How a painted color is combined with the color below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BlendMode {
+    /// Composites the color over the one below it, according to its alpha.
+    SourceOver,
+}
+
+/// Where the pixels of an image came from.
+#[derive(Clone)]
+pub(super) enum ImageSource {
+    /// Reloaded from the file instead of keeping a copy of the pixels.
+    Path(PathBuf, ImageLoadOptions),
+    Pixels(RgbaImage),
+    Hdr(Rgba32FImage),
+    Compressed(CompressedImage),
+}
+
+impl ImageSource {
+    /// Empty images are rejected here, so that the backends don't have to
+    /// handle them.
+    pub fn create(self, inner: &mut dyn ContextImplementation) -> Result<Image, ImageLoadError> {
+        let non_empty = |size: Size2D<u32>| if size.is_empty() { Err(ImageLoadError::EmptyImage) } else { Ok(()) };
+
+        match self {
+            Self::Path(path, options) => {
+                let pixels = Image::load(&path, &options)?;
+                non_empty(pixels.dimensions().into())?;
+                inner.create_image(pixels)
+            }
+            Self::Pixels(pixels) => {
+                non_empty(pixels.dimensions().into())?;
+                inner.create_image(pixels)
+            }
+            Self::Hdr(pixels) => {
+                non_empty(pixels.dimensions().into())?;
+                inner.create_hdr_image(pixels)
+            }
+            Self::Compressed(image) => {
+                non_empty(image.size)?;
+                if image.levels.is_empty() {
+                    return Err(ImageLoadError::BlockDecode("the image has no levels"));
+                }
+
+                inner.create_compressed_image(image)
+            }
+        }
+    }
 }
 
 pub struct Context {
     inner: Box<dyn ContextImplementation>,
-    image_cache: HashMap<PathBuf, Image>,
+    image_cache: HashMap<(PathBuf, ImageLoadOptions), Image>,
+    /// Keyed by the address and length of the embedded bytes, as the names are
+    /// relative to the file that included them.
+    embedded_image_cache: HashMap<(usize, usize, ImageLoadOptions), Image>,
+    working_color_space: ColorSpace,
+    /// Only used without a window, see [`Self::scale_factor`].
+    scale_factor: f64,
+    pub(super) fallback_reasons: Vec<String>,
+
+    /// The window and image sources, for [`Self::switch_backend`].
+    #[cfg(feature = "window")]
+    pub(super) retained: Option<Retained>,
 }
 
 impl Context {
-    pub fn new(event_loop: &EventLoop<EventTy>) -> (Self, Rc<Window>) {
-        let ctx = var("ZINNEBEELD_CTX").ok().unwrap_or_default();
-        let (inner, window) = match ctx.as_str() {
-            "software" => SoftwareContext::new(event_loop),
-            _ => GLContext::new(event_loop),
-        };
+    /// Creates a window with the default [`ContextBuilder`] configuration.
+    #[cfg(feature = "window")]
+    pub fn new(event_loop: &EventLoop<EventTy>) -> Result<(Self, Rc<Window>), ContextCreationError> {
+        ContextBuilder::new().build(event_loop)
+    }
+
+    /// Creates a context that writes what is painted to an SVG document of
+    /// `size` pixels, instead of showing it in a window.
+    #[cfg(feature = "svg-export")]
+    pub fn new_svg(size: Size2D<u32>) -> (Self, SvgOutput) {
+        let (inner, output) = SvgContext::new(size);
+        (Self::with_implementation(inner), output)
+    }
+
+    /// Creates a context that adds a page of `size` points to a PDF document
+    /// for every [`Self::paint`], instead of showing it in a window.
+    #[cfg(feature = "pdf-export")]
+    pub fn new_pdf(size: Size2D<u32>) -> (Self, PdfOutput) {
+        let (inner, output) = PdfContext::new(size);
+        (Self::with_implementation(inner), output)
+    }
+
+    /// Creates a context that writes every [`Self::paint`] to the terminal,
+    /// sized to fit it, instead of showing it in a window.
+    #[cfg(feature = "terminal")]
+    pub fn new_terminal(mode: TerminalMode) -> Self {
+        Self::with_implementation(TerminalContext::new(mode))
+    }
+
+    /// Creates a context that paints with the software renderer into memory
+    /// owned by the program, e.g. a framebuffer, instead of a window. The
+    /// painted size follows [`PixelTarget::size`].
+    #[cfg(feature = "software")]
+    pub fn from_pixels(target: impl PixelTarget + 'static) -> Self {
+        Self::with_implementation(PixelContext::new(Box::new(target)))
+    }
 
-        let this = Self {
+    pub(super) fn with_implementation(inner: Box<dyn ContextImplementation>) -> Self {
+        Self {
             inner,
             image_cache: HashMap::new(),
-        };
+            embedded_image_cache: HashMap::new(),
+            working_color_space: ColorSpace::default(),
+            scale_factor: 1.0,
+            fallback_reasons: Vec::new(),
+            #[cfg(feature = "window")]
+            retained: None,
+        }
+    }
+
+    /// Recreates the context with another backend on the same windows. All
+    /// images are uploaded again from the copies the context keeps, so their
+    /// handles stay valid. This must be enabled with
+    /// [`ContextBuilder::with_backend_switching`]. When this fails, the current
+    /// backend is kept.
+    #[cfg(feature = "window")]
+    pub fn switch_backend(&mut self, backend: Backend) -> Result<(), ContextCreationError> {
+        let retained = self.retained.as_mut().ok_or(ContextCreationError::NoWindow)?;
+        if !retained.keeps_images() {
+            return Err(ContextCreationError::SwitchingDisabled);
+        }
+        if !backend.is_available() {
+            return Err(ContextCreationError::NoBackend);
+        }
+
+        let (first, others) = retained.windows.split_first().ok_or(ContextCreationError::NoWindow)?;
+        let mut inner = retained.builder.create_on_window(backend, first)?;
+        for window in others {
+            inner.attach_window(Rc::clone(window))?;
+        }
+
+        retained.restore(inner.as_mut())?;
+
+        self.inner = inner;
+        self.fallback_reasons.clear();
+        Ok(())
+    }
+
+    /// The backend that is painting, and why the preferred backends weren't
+    /// used, if it is a fallback.
+    #[must_use]
+    pub fn backend_info(&self) -> BackendInfo {
+        BackendInfo {
+            fallback_reasons: self.fallback_reasons.clone(),
+            ..self.inner.backend_info()
+        }
+    }
+
+    #[must_use]
+    pub fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    /// The number of physical pixels per logical pixel, e.g. `2.0` on a HiDPI
+    /// display. With a window, this is always the current one of the window
+    /// the context was created with.
+    #[must_use]
+    pub fn scale_factor(&self) -> f64 {
+        #[cfg(feature = "window")]
+        if let Some(window) = self.retained.as_ref().and_then(|retained| retained.windows.first()) {
+            return window.scale_factor();
+        }
+
+        self.scale_factor
+    }
 
-        (this, window)
+    /// Sets the scale factor of contexts without a window. For pixel targets,
+    /// this paints at a higher resolution. Documents are resolution
+    /// independent, so it doesn't apply to them. Windows report their own.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor;
     }
 
+    /// The color space that loaded images are converted to.
+    #[must_use]
+    pub const fn working_color_space(&self) -> ColorSpace {
+        self.working_color_space
+    }
+
+    /// Changes the color space that subsequently loaded images are converted to.
+    pub fn set_working_color_space(&mut self, color_space: ColorSpace) {
+        self.working_color_space = color_space;
+    }
+
+    fn default_load_options(&self) -> ImageLoadOptions {
+        ImageLoadOptions {
+            color_space: Some(self.working_color_space),
+            ..Default::default()
+        }
+    }
+
+    /// Loads an image, applying its EXIF orientation and converting it to the
+    /// working color space.
+    ///
+    /// When the scale factor is above 1, the `@2x` or `@3x` variant next to
+    /// the file is loaded instead if it exists, e.g. `icon@2x.png` for
+    /// `icon.png`. Its [`Image::logical_size`] is that of the original.
     pub fn load_image(&mut self, path: &Path) -> Result<Image, ImageLoadError> {
-        if let Some(img) = self.image_cache.get(path) {
-            return Ok(img.clone());
+        self.load_image_with_options(path, self.default_load_options())
+    }
+
+    pub fn load_image_with_options(&mut self, path: &Path, options: ImageLoadOptions) -> Result<Image, ImageLoadError> {
+        let Some((path, scale)) = scaled_variant(path, self.scale_factor()) else {
+            return self.load_image_file(path, options);
+        };
+
+        let image = self.load_image_file(&path, options)?;
+        Ok(Image {
+            scale: scale as f32,
+            ..image
+        })
+    }
+
+    /// Loads exactly the file at `path`, e.g. for sprite sheets of which the
+    /// frames are described in its pixels.
+    fn load_image_file(&mut self, path: &Path, options: ImageLoadOptions) -> Result<Image, ImageLoadError> {
+        let key = (path.to_path_buf(), options);
+        if let Some(img) = self.image_cache.get(&key) {
+            return Ok(*img);
         }
 
-        let img = self.inner.load_image(path)?;
-        self.image_cache.insert(path.to_path_buf(), img.clone());
+        let img = self.create(ImageSource::Path(path.to_path_buf(), options))?;
+        self.image_cache.insert(key, img);
         Ok(img)
     }
 
+    /// Decodes an image from an in-memory encoded file. The format is guessed
+    /// from the contents.
+    pub fn load_image_from_bytes(&mut self, bytes: &[u8]) -> Result<Image, ImageLoadError> {
+        self.load_image_from_bytes_with_options(bytes, self.default_load_options())
+    }
+
+    pub fn load_image_from_bytes_with_options(&mut self, bytes: &[u8], options: ImageLoadOptions) -> Result<Image, ImageLoadError> {
+        self.create(ImageSource::Pixels(Image::load_from_bytes(bytes, &options)?))
+    }
+
+    /// Loads an image that was embedded using [`include_image!`](crate::include_image).
+    pub fn load_embedded_image(&mut self, embedded: &EmbeddedImage) -> Result<Image, ImageLoadError> {
+        let options = self.default_load_options();
+        let key = (embedded.bytes.as_ptr() as usize, embedded.bytes.len(), options);
+        if let Some(img) = self.embedded_image_cache.get(&key) {
+            return Ok(*img);
+        }
+
+        let img = self.load_image_from_bytes_with_options(embedded.bytes, options)?;
+        self.embedded_image_cache.insert(key, img);
+        Ok(img)
+    }
+
+    /// Loads an image as linear floating point, preserving the values outside
+    /// of the `0.0..=1.0` range of e.g. OpenEXR and Radiance HDR files. Use
+    /// [`Image::with_exposure`] and [`Image::with_tone_mapping`] to control how
+    /// it is displayed.
+    pub fn load_hdr_image(&mut self, path: &Path) -> Result<Image, ImageLoadError> {
+        self.create(ImageSource::Hdr(Image::load_hdr(path)?))
+    }
+
+    /// Loads a block-compressed DDS or KTX2 file.
+    pub fn load_compressed_image(&mut self, path: &Path) -> Result<Image, ImageLoadError> {
+        self.load_compressed_image_from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn load_compressed_image_from_bytes(&mut self, bytes: &[u8]) -> Result<Image, ImageLoadError> {
+        self.create(ImageSource::Compressed(CompressedImage::parse(bytes)?))
+    }
+
+    /// Loads all frames of an animated GIF, APNG or WebP file.
+    pub fn load_animated_image(&mut self, path: &Path) -> Result<AnimatedImage, ImageLoadError> {
+        self.load_animated_image_from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn load_animated_image_from_bytes(&mut self, bytes: &[u8]) -> Result<AnimatedImage, ImageLoadError> {
+        let (frames, loop_count) = AnimatedImage::decode(bytes)?;
+        let size = Size2D::from(frames[0].0.dimensions());
+
+        let frames = frames.into_iter()
+            .map(|(image, delay)| {
+                Ok(AnimationFrame {
+                    image: self.create(ImageSource::Pixels(image))?,
+                    delay,
+                })
+            })
+            .collect::<Result<_, ImageLoadError>>()?;
+
+        Ok(AnimatedImage {
+            size,
+            frames,
+            loop_count,
+        })
+    }
+
+    /// Loads a sprite sheet described by an Aseprite or TexturePacker JSON
+    /// file. The image is loaded relative to the JSON file.
+    pub fn load_sprite_sheet(&mut self, path: &Path) -> Result<SpriteSheet, ImageLoadError> {
+        let description = SpriteSheetDescription::parse(&std::fs::read(path)?)?;
+
+        let image_path = path.parent().unwrap_or(Path::new("")).join(&description.image);
+        let image = self.load_image_file(&image_path, self.default_load_options())?;
+
+        let bounds = Rect::from_size(image.size());
+        if !description.frames.iter().all(|frame| bounds.contains_rect(&frame.rect)) {
+            return Err(ImageLoadError::RegionOutOfBounds);
+        }
+
+        Ok(SpriteSheet {
+            image,
+            frames: description.frames,
+            tags: description.tags,
+        })
+    }
+
+    /// Loads a sprite sheet of equally sized frames.
+    pub fn load_sprite_sheet_from_grid(&mut self, path: &Path, grid: SpriteGrid) -> Result<SpriteSheet, ImageLoadError> {
+        let image = self.load_image_file(path, self.default_load_options())?;
+        Ok(SpriteSheet::from_grid(image, grid))
+    }
+
+    /// Loads an Android `.9.png` file, of which the border describes how the
+    /// image is stretched.
+    pub fn load_nine_patch(&mut self, path: &Path) -> Result<NinePatch, ImageLoadError> {
+        let decoded = DecodedNinePatch::decode(Image::load(path, &self.default_load_options())?)?;
+
+        Ok(NinePatch {
+            image: self.create(ImageSource::Pixels(decoded.image))?,
+            slice: NineSlice::new(decoded.insets),
+            padding: decoded.padding,
+        })
+    }
+
+    /// Parses an SVG file. Text is converted to paths using the system fonts.
+    #[cfg(feature = "svg")]
+    pub fn load_svg(&mut self, path: &Path) -> Result<SvgDocument, ImageLoadError> {
+        SvgDocument::parse(&std::fs::read(path)?, path.parent(), svg::system_fonts())
+    }
+
+    #[cfg(feature = "svg")]
+    pub fn load_svg_from_bytes(&mut self, bytes: &[u8]) -> Result<SvgDocument, ImageLoadError> {
+        SvgDocument::parse(bytes, None, svg::system_fonts())
+    }
+
+    /// Renders the document into a new image of `size` pixels, for documents
+    /// that are drawn often at the same size.
+    #[cfg(feature = "svg")]
+    pub fn rasterize_svg(&mut self, svg: &SvgDocument, size: Size2D<u32>) -> Result<Image, ImageLoadError> {
+        self.create(ImageSource::Pixels(svg.rasterize(size)))
+    }
+
+    /// Creates an image from raw, tightly packed pixel data.
+    pub fn create_image(&mut self, size: Size2D<u32>, pixels: &[u8], format: PixelFormat) -> Result<Image, ImageLoadError> {
+        self.create(ImageSource::Pixels(format.to_rgba(size, pixels)?))
+    }
+
+    /// Releases an image. Any copies of the handle, including the ones in the
+    /// image cache, become invalid.
+    pub fn unload_image(&mut self, image: Image) {
+        self.image_cache.retain(|_, cached| cached.id != image.id);
+        self.embedded_image_cache.retain(|_, cached| cached.id != image.id);
+        if let Some(resolved) = self.resolve(image) {
+            self.inner.unload_image(resolved);
+        }
+
+        #[cfg(feature = "window")]
+        if let Some(retained) = &mut self.retained {
+            retained.remove(image);
+        }
+    }
+
+    /// Overwrites the pixels of an existing image, without reallocating it.
+    /// When `region` is `None`, the whole image is replaced.
+    pub fn update_image(
+        &mut self,
+        image: Image,
+        region: Option<Rect<u32>>,
+        pixels: &[u8],
+        format: PixelFormat,
+    ) -> Result<(), ImageLoadError> {
+        let bounds = Rect::new(Point2D::zero(), image.size());
+        let region = region.unwrap_or(bounds);
+        if !bounds.contains_rect(&region) {
+            return Err(ImageLoadError::RegionOutOfBounds);
+        }
+
+        let resolved = self.resolve(image).ok_or(ImageLoadError::UnknownImage)?;
+
+        if !matches!(resolved.id.namespace(), ResourceNamespace::Image | ResourceNamespace::AtlasImage) {
+            return Err(ImageLoadError::ImageNotUpdatable);
+        }
+
+        if region.is_empty() {
+            return Ok(());
+        }
+
+        let pixels = format.to_rgba(region.size, pixels)?;
+
+        // The retained copy is decoded first, so a failure leaves both as is.
+        #[cfg(feature = "window")]
+        if let Some(retained) = self.retained.as_mut().filter(|retained| retained.keeps_images()) {
+            retained.update(image, region, &pixels)?;
+        }
+
+        self.inner.update_image(resolved, region, pixels)
+    }
+
+    /// Creates a double-buffered image for content that changes every frame.
+    pub fn create_streaming_image(&mut self, size: Size2D<u32>) -> Result<StreamingImage, ImageLoadError> {
+        let front = self.create(ImageSource::Pixels(RgbaImage::new(size.width, size.height)))?;
+        let back = self.create(ImageSource::Pixels(RgbaImage::new(size.width, size.height)))?;
+        Ok(StreamingImage {
+            images: [front, back],
+            front: 0,
+        })
+    }
+
+    /// Uploads a complete new frame into the back buffer of the streaming
+    /// image, and makes it the current one.
+    pub fn update_streaming_image(
+        &mut self,
+        image: &mut StreamingImage,
+        pixels: &[u8],
+        format: PixelFormat,
+    ) -> Result<(), ImageLoadError> {
+        self.update_image(image.back(), None, pixels, format)?;
+        image.swap();
+        Ok(())
+    }
+
     pub fn paint<F: FnMut(&mut Painter)>(&self, mut f: F) {
-        self.inner.paint_frame(&mut |painter| {
-            let mut painter = Painter {
+        let scale_factor = self.scale_factor();
+        self.inner.paint_frame(scale_factor, &mut |painter| self.with_painter(painter, scale_factor, &mut f));
+    }
+
+    /// Paints a single frame into pixels that are only borrowed, e.g. a
+    /// [`PixelBuffer`](crate::PixelBuffer), instead of the window or the
+    /// target of [`Self::from_pixels`]. Only the software backends support
+    /// this, and the target must fit its size and stride.
+    #[cfg(feature = "software")]
+    pub fn paint_pixels<F: FnMut(&mut Painter)>(&self, target: &mut dyn PixelTarget, mut f: F) -> Result<(), ImageLoadError> {
+        let scale_factor = self.scale_factor();
+        self.inner.paint_pixels(target, scale_factor, &mut |painter| self.with_painter(painter, scale_factor, &mut f))
+    }
+
+    /// Paints into an offscreen floating point image of the given size, e.g.
+    /// for HDR post-processing or export. Colors are stored linearly with
+    /// straight alpha, like the images of [`ImageSource::Hdr`], and the
+    /// coordinates are in pixels of the image.
+    pub fn paint_hdr<F: FnMut(&mut Painter)>(&self, size: Size2D<u32>, mut f: F) -> Result<Rgba32FImage, ImageLoadError> {
+        self.inner.paint_offscreen_hdr(size, &mut |painter| self.with_painter(painter, 1.0, &mut f))
+    }
+
+    /// Call this on `WindowEvent::Resized`, with the size in physical pixels.
+    pub fn resize(&mut self, size: Size2D<u32>) {
+        self.inner.resize(size);
+    }
+
+    /// Opens another window, e.g. a tool palette or an inspector, which paints
+    /// with the same backend and can use the same images. Paint it with
+    /// [`Self::paint_window`], and route its events by [`Window::id`].
+    #[cfg(feature = "window")]
+    pub fn create_window(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        attributes: WindowAttributes,
+    ) -> Result<Rc<Window>, ContextCreationError> {
+        let retained = self.retained.as_mut().ok_or(ContextCreationError::NoWindow)?;
+
+        let window = self.inner.create_window(event_loop, attributes)?;
+        retained.windows.push(Rc::clone(&window));
+        Ok(window)
+    }
+
+    /// Releases the surface of the window, after which dropping the last
+    /// handle to it closes it.
+    #[cfg(feature = "window")]
+    pub fn close_window(&mut self, id: WindowId) {
+        self.inner.detach_window(id);
+
+        if let Some(retained) = &mut self.retained {
+            retained.windows.retain(|window| window.id() != id);
+        }
+    }
+
+    /// Paints any of the windows of the context, at the current scale factor
+    /// of that window.
+    #[cfg(feature = "window")]
+    pub fn paint_window<F: FnMut(&mut Painter)>(&self, id: WindowId, mut f: F) {
+        let Some(window) = self.retained.as_ref().and_then(|retained| retained.window(id)) else {
+            return;
+        };
+
+        let scale_factor = window.scale_factor();
+
+        self.inner.paint_window(id, scale_factor, &mut |painter| self.with_painter(painter, scale_factor, &mut f));
+    }
+
+    /// Like [`Self::resize`], for any of the windows of the context.
+    #[cfg(feature = "window")]
+    pub fn resize_window(&mut self, id: WindowId, size: Size2D<u32>) {
+        self.inner.resize_window(id, size);
+    }
+
+    /// Creates the image on the backend, keeping the source when the backend
+    /// can be switched.
+    fn create(&mut self, source: ImageSource) -> Result<Image, ImageLoadError> {
+        #[cfg(feature = "window")]
+        if let Some(retained) = self.retained.as_mut().filter(|retained| retained.keeps_images()) {
+            let image = source.clone().create(self.inner.as_mut())?;
+            return Ok(retained.insert(image, source));
+        }
+
+        source.create(self.inner.as_mut())
+    }
+
+    /// The handle of the image as the backend knows it, or `None` if it's
+    /// unknown.
+    fn resolve(&self, image: Image) -> Option<Image> {
+        #[cfg(feature = "window")]
+        if let Some(retained) = &self.retained {
+            return retained.resolve(image);
+        }
+
+        Some(image)
+    }
+
+    fn with_painter<F: FnMut(&mut Painter)>(&self, painter: &mut dyn PainterImplementation, scale_factor: f64, f: &mut F) {
+        let scale_factor = scale_factor as f32;
+
+        #[cfg(feature = "window")]
+        if let Some(retained) = &self.retained {
+            let mut painter = RetainedPainter {
                 inner: painter,
+                retained,
             };
 
-            f(&mut painter);
+            return f(&mut Painter {
+                inner: &mut painter,
+                scale_factor,
+            });
+        }
+
+        f(&mut Painter {
+            inner: painter,
+            scale_factor,
         });
     }
+}
 
-    pub fn resize(&mut self, size: Size2D<u32>) {
-        self.inner.resize(size);
+/// The highest of the `@2x` and `@3x` variants of the file that is useful at
+/// the scale factor and exists, with its scale.
+fn scaled_variant(path: &Path, scale_factor: f64) -> Option<(PathBuf, u32)> {
+    (2..=scale_factor.ceil().min(3.0) as u32).rev().find_map(|scale| {
+        let variant = variant_path(path, scale)?;
+        variant.is_file().then_some((variant, scale))
+    })
+}
+
+/// The path of the `@{scale}x` variant, before the last extension, e.g.
+/// `icon.v2@2x.png` for `icon.v2.png`.
+fn variant_path(path: &Path, scale: u32) -> Option<PathBuf> {
+    let mut name = path.file_stem()?.to_os_string();
+    name.push(format!("@{scale}x"));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+
+    Some(path.with_file_name(name))
+}
+
+#[cfg(all(test, feature = "software"))]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{Color, PixelBuffer, TargetFormat};
+
+    #[test]
+    fn empty_images_are_rejected() {
+        let mut context = Context::from_pixels(RgbaImage::new(1, 1));
+        let result = context.create_image(Size2D::new(0, 4), &[], PixelFormat::Rgba8);
+        assert!(matches!(result, Err(ImageLoadError::EmptyImage)));
+    }
+
+    #[test]
+    fn updated_images_are_sampled_with_new_mipmaps() {
+        let target = Rc::new(RefCell::new(RgbaImage::new(4, 4)));
+        let mut context = Context::from_pixels(Rc::clone(&target));
+
+        let size = Size2D::new(512, 512);
+        let black = [0, 0, 0, 0xFF].repeat(512 * 512);
+        let image = context.create_image(size, &black, PixelFormat::Rgba8).unwrap();
+        let rect = Rect::from_size(Size2D::new(4.0, 4.0));
+
+        context.paint(|painter| painter.paint_filled_rect(rect, image));
+        assert_eq!(target.borrow().get_pixel(1, 1).0, [0, 0, 0, 0xFF]);
+
+        context.update_image(image, None, &[0xFF; 512 * 512 * 4], PixelFormat::Rgba8).unwrap();
+        context.paint(|painter| painter.paint_filled_rect(rect, image));
+        assert_eq!(target.borrow().get_pixel(1, 1).0, [0xFF; 4]);
+    }
+
+    #[test]
+    fn unloaded_images_are_not_updated() {
+        let mut context = Context::from_pixels(RgbaImage::new(1, 1));
+        for size in [Size2D::new(2, 2), Size2D::new(512, 2)] {
+            let image = context.create_image(size, &vec![0; size.area() as usize * 4], PixelFormat::Rgba8).unwrap();
+            context.unload_image(image);
+
+            let result = context.update_image(image, None, &vec![0; size.area() as usize * 4], PixelFormat::Rgba8);
+            assert!(matches!(result, Err(ImageLoadError::UnknownImage)), "{result:?}");
+        }
+    }
+
+    #[test]
+    fn atlas_images_are_sampled_with_mipmaps() {
+        let target = Rc::new(RefCell::new(RgbaImage::new(2, 2)));
+        let mut context = Context::from_pixels(Rc::clone(&target));
+
+        // A white dot in the corner of every 4x4 block, which bilinear
+        // filtering of the base level misses.
+        let pixels: Vec<u8> = (0..8 * 8).flat_map(|i| if i % 4 == 0 && i / 8 % 4 == 0 { [0xFF; 4] } else { [0, 0, 0, 0xFF] }).collect();
+        let image = context.create_image(Size2D::new(8, 8), &pixels, PixelFormat::Rgba8).unwrap();
+        assert_eq!(image.id.namespace(), ResourceNamespace::AtlasImage);
+
+        context.paint(|painter| painter.paint_filled_rect(Rect::from_size(Size2D::new(2.0, 2.0)), image));
+        for pixel in target.borrow().pixels() {
+            assert!((0x0E..=0x12).contains(&pixel.0[0]) && pixel.0[3] == 0xFF, "{pixel:?}");
+        }
+    }
+
+    #[test]
+    fn borrowed_pixels_are_painted() {
+        let context = Context::from_pixels(RgbaImage::new(1, 1));
+
+        // Five pixels per row, with one pixel of padding.
+        let mut pixels = vec![0; 6 * 3];
+        let mut target = PixelBuffer::new(&mut pixels, Size2D::new(5, 3), 6 * 4, TargetFormat::Bgra8);
+        context.paint_pixels(&mut target, |painter| {
+            painter.paint_filled_rect(Rect::from_size(Size2D::new(5.0, 3.0)), Color::new(0xFF, 0, 0, 0xFF));
+        }).unwrap();
+
+        for row in pixels.chunks(6) {
+            assert_eq!(row, [0xFFFF_0000, 0xFFFF_0000, 0xFFFF_0000, 0xFFFF_0000, 0xFFFF_0000, 0]);
+        }
+    }
+
+    #[test]
+    fn invalid_targets_are_not_painted() {
+        struct Truncated(Vec<u8>);
+
+        impl PixelTarget for Truncated {
+            fn size(&self) -> Size2D<u32> {
+                Size2D::new(4, 4)
+            }
+
+            fn format(&self) -> TargetFormat {
+                TargetFormat::Rgba8
+            }
+
+            fn with_pixels(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+                f(&mut self.0);
+            }
+        }
+
+        // The frame is skipped, instead of panicking.
+        let context = Context::from_pixels(Truncated(vec![0; 4 * 4 * 4 - 1]));
+        context.paint(|_| panic!("painted into a truncated target"));
+
+        let mut pixels = vec![0; 4 * 4];
+        let too_small = context.paint_pixels(&mut PixelBuffer::new(&mut pixels, Size2D::new(4, 5), 16, TargetFormat::Rgba8), |_| ());
+        assert!(matches!(too_small, Err(ImageLoadError::InvalidPixelTarget(_))));
+
+        let narrow_stride = context.paint_pixels(&mut PixelBuffer::new(&mut pixels, Size2D::new(4, 4), 12, TargetFormat::Rgba8), |_| ());
+        assert!(matches!(narrow_stride, Err(ImageLoadError::InvalidPixelTarget(_))));
+
+        let padded = context.paint_pixels(&mut PixelBuffer::new(&mut pixels, Size2D::new(3, 4), 16, TargetFormat::Rgba8), |_| ());
+        assert!(padded.is_ok());
+    }
+
+    #[test]
+    fn variant_paths_insert_before_the_last_extension() {
+        assert_eq!(variant_path(Path::new("res/icon.png"), 2), Some(PathBuf::from("res/icon@2x.png")));
+        assert_eq!(variant_path(Path::new("res/icon.v2.png"), 3), Some(PathBuf::from("res/icon.v2@3x.png")));
+        assert_eq!(variant_path(Path::new("v1.0/icon"), 2), Some(PathBuf::from("v1.0/icon@2x")));
+        assert_eq!(variant_path(Path::new(".hidden"), 2), Some(PathBuf::from(".hidden@2x")));
+    }
+
+    #[test]
+    #[cfg(feature = "png")]
+    fn embedded_images_are_keyed_by_their_length() {
+        let mut png = Vec::new();
+        RgbaImage::new(1, 1).write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let len = png.len();
+        png.extend_from_slice(&[0; 16]);
+
+        // A sub-slice of another embedded file starts at the same address.
+        let bytes: &'static [u8] = png.leak();
+        let short = EmbeddedImage { name: "short.png", bytes: &bytes[..len] };
+        let long = EmbeddedImage { name: "long.png", bytes };
+
+        let mut context = Context::from_pixels(RgbaImage::new(1, 1));
+        let short = context.load_embedded_image(&short).unwrap();
+        let long = context.load_embedded_image(&long).unwrap();
+        assert_ne!(short.id, long.id);
     }
 }
@@ -0,0 +1,87 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Backends that write the painted commands to a document, instead of
+//! rasterizing them.
+
+#[cfg(feature = "pdf-export")]
+mod pdf;
+#[cfg(feature = "svg-export")]
+mod svg;
+
+#[cfg(feature = "pdf-export")]
+pub use pdf::{PdfContext, PdfOutput};
+#[cfg(feature = "svg-export")]
+pub use svg::{SvgContext, SvgOutput};
+
+use euclid::default::Rect;
+use image::{Rgba32FImage, RgbaImage};
+
+use crate::{Color, Image, ImageLoadError, ResourceManager, ResourceNamespace};
+
+/// The images of a document backend, kept on the CPU until they're written.
+pub(super) struct ExportResources {
+    images: ResourceManager<RgbaImage>,
+    hdr_images: ResourceManager<Rgba32FImage>,
+}
+
+impl ExportResources {
+    pub fn new() -> Self {
+        Self {
+            images: ResourceManager::new(ResourceNamespace::Image),
+            hdr_images: ResourceManager::new(ResourceNamespace::HdrImage),
+        }
+    }
+
+    pub fn create_image(&self, image: RgbaImage) -> Image {
+        let size = image.dimensions().into();
+        Image::new(self.images.add(image), size)
+    }
+
+    pub fn create_hdr_image(&self, image: Rgba32FImage) -> Image {
+        let size = image.dimensions().into();
+        Image::new(self.hdr_images.add(image), size)
+    }
+
+    pub fn update_image(&self, image: Image, region: Rect<u32>, pixels: RgbaImage) -> Result<(), ImageLoadError> {
+        if !self.images.contains(image.id) {
+            return Err(ImageLoadError::UnknownImage);
+        }
+
+        self.images.with_mut(image.id, |dest| {
+            image::imageops::replace(dest, &pixels, region.min_x() as i64, region.min_y() as i64);
+        });
+        Ok(())
+    }
+
+    pub fn unload_image(&self, image: Image) {
+        if image.id.namespace() == ResourceNamespace::HdrImage {
+            self.hdr_images.remove(image.id);
+        } else {
+            self.images.remove(image.id);
+        }
+    }
+
+    /// The 8-bit sRGB pixels of the image, with the tone mapping of the
+    /// handle applied to HDR images.
+    pub fn pixels(&self, image: Image) -> RgbaImage {
+        let mut pixels = None;
+
+        if image.id.namespace() == ResourceNamespace::HdrImage {
+            self.hdr_images.with(image.id, |hdr| {
+                let mut img = RgbaImage::new(hdr.width(), hdr.height());
+                for (dest, source) in img.pixels_mut().zip(hdr.pixels()) {
+                    let [r, g, b, a] = source.0;
+                    let map = |value: f32| image.tone_mapping.apply(value, image.exposure);
+                    let color = Color::from_linear([map(r), map(g), map(b), a]);
+                    dest.0 = [color.red(), color.green(), color.blue(), color.alpha()];
+                }
+                pixels = Some(img);
+            });
+        } else {
+            self.images.with(image.id, |img| pixels = Some(img.clone()));
+        }
+
+        pixels.unwrap()
+    }
+}
@@ -0,0 +1,457 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::{cell::RefCell, collections::{BTreeSet, HashMap}, path::Path, rc::Rc};
+
+use euclid::default::{Rect, Size2D};
+use image::{Rgba32FImage, RgbaImage};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect as PdfRect, Ref};
+
+use crate::{
+    gfx::painter::PainterImplementation,
+    BackendInfo,
+    Capabilities,
+    CompressedImage,
+    ContextImplementation,
+    Image,
+    ImageLoadError,
+    Material,
+    ResourceId,
+    SamplingQuality,
+    ToneMapping,
+};
+
+use super::ExportResources;
+
+/// The pages painted with a [`PdfContext`] so far.
+#[derive(Debug, Clone, Default)]
+pub struct PdfOutput {
+    document: Rc<RefCell<PdfDocument>>,
+}
+
+impl PdfOutput {
+    #[must_use]
+    pub fn page_count(&self) -> usize {
+        self.document.borrow().pages.len()
+    }
+
+    /// Removes all pages, e.g. to start a new document.
+    pub fn clear(&self) {
+        *self.document.borrow_mut() = PdfDocument::default();
+    }
+
+    /// Writes the pages to a PDF file.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.document.borrow().write()
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+}
+
+/// A [`Context`](crate::Context) that adds a PDF page for every frame that
+/// is painted, with one point per pixel.
+pub struct PdfContext {
+    size: Size2D<u32>,
+    resources: ExportResources,
+    output: PdfOutput,
+}
+
+impl PdfContext {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(size: Size2D<u32>) -> (Box<dyn ContextImplementation>, PdfOutput) {
+        let output = PdfOutput::default();
+
+        let this = Self {
+            size,
+            resources: ExportResources::new(),
+            output: output.clone(),
+        };
+
+        (Box::new(this), output)
+    }
+}
+
+impl ContextImplementation for PdfContext {
+    /// Changes the size of the pages painted afterwards.
+    fn resize(&mut self, size: Size2D<u32>) {
+        self.size = size;
+    }
+
+    fn create_image(&mut self, image: RgbaImage) -> Result<Image, ImageLoadError> {
+        Ok(self.resources.create_image(image))
+    }
+
+    fn update_image(&mut self, image: Image, region: Rect<u32>, pixels: RgbaImage) -> Result<(), ImageLoadError> {
+        // The pages that were already painted keep the old pixels.
+        self.output.document.borrow_mut().forget_image(image.id);
+        self.resources.update_image(image, region, pixels)
+    }
+
+    fn unload_image(&mut self, image: Image) {
+        self.output.document.borrow_mut().forget_image(image.id);
+        self.resources.unload_image(image);
+    }
+
+    fn create_hdr_image(&mut self, image: Rgba32FImage) -> Result<Image, ImageLoadError> {
+        Ok(self.resources.create_hdr_image(image))
+    }
+
+    fn create_compressed_image(&mut self, image: CompressedImage) -> Result<Image, ImageLoadError> {
+        self.create_image(image.decode()?)
+    }
+
+    /// Documents are resolution independent, so the scale factor doesn't
+    /// apply.
+    fn paint_frame(&self, _: f64, f: &mut dyn FnMut(&mut dyn PainterImplementation)) {
+        let mut document = self.output.document.borrow_mut();
+        let mut painter = PdfPainter {
+            size: self.size.cast(),
+            resources: &self.resources,
+            document: &mut document,
+            content: Content::new(),
+            images: BTreeSet::new(),
+            alphas: BTreeSet::new(),
+        };
+
+        f(&mut painter);
+
+        let page = PdfPage {
+            size: self.size,
+            content: painter.content.finish().into_vec(),
+            images: painter.images,
+            alphas: painter.alphas,
+        };
+        document.pages.push(page);
+    }
+
+    fn paint_offscreen_hdr(
+        &self,
+        _size: Size2D<u32>,
+        _f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<Rgba32FImage, ImageLoadError> {
+        Err(ImageLoadError::Unsupported("the PDF backend can't render to pixels"))
+    }
+
+    fn backend_info(&self) -> BackendInfo {
+        BackendInfo::new("PDF", None)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::export()
+    }
+}
+
+struct PdfPainter<'a> {
+    size: Size2D<f32>,
+    resources: &'a ExportResources,
+    document: &'a mut PdfDocument,
+    content: Content,
+    images: BTreeSet<usize>,
+    alphas: BTreeSet<u8>,
+}
+
+impl PdfPainter<'_> {
+    /// Converts the top-down `rect` to the bottom-up coordinates of PDF.
+    fn to_pdf(&self, rect: Rect<f32>) -> PdfRect {
+        let y = self.size.height - rect.max_y();
+        PdfRect::new(rect.min_x(), y, rect.max_x(), y + rect.height())
+    }
+
+    fn add_image(&mut self, key: Option<ImageKey>, image: PdfImage) -> usize {
+        let index = self.document.images.len();
+        self.document.images.push(image);
+        if let Some(key) = key {
+            self.document.image_keys.insert(key, index);
+        }
+        index
+    }
+
+    /// Draws the image XObject, mapping the normalized `source` onto `rect`.
+    fn draw_image(&mut self, index: usize, rect: Rect<f32>, source: Rect<f32>) {
+        self.images.insert(index);
+
+        // The image space of an XObject is the unit square, so scale it to
+        // the size the whole image would have, and clip to the region.
+        let width = rect.width() / source.width();
+        let height = rect.height() / source.height();
+        let x = rect.min_x() - source.min_x() * width;
+        let y = rect.min_y() - source.min_y() * height;
+        let full = self.to_pdf(Rect::new((x, y).into(), Size2D::new(width, height)));
+        let clip = self.to_pdf(rect);
+
+        let name = image_name(index);
+        self.content.save_state();
+        self.content.rect(clip.x1, clip.y1, clip.x2 - clip.x1, clip.y2 - clip.y1)
+            .clip_nonzero()
+            .end_path();
+        self.content.transform([width, 0.0, 0.0, height, full.x1, full.y1])
+            .x_object(Name(name.as_bytes()));
+        self.content.restore_state();
+    }
+}
+
+impl PainterImplementation for PdfPainter<'_> {
+    fn paint_filled_rect(&mut self, rect: Rect<f32>, brush: Material) {
+        match brush {
+            Material::Color(color) => {
+                let rect = self.to_pdf(rect);
+                let [r, g, b] = [color.red(), color.green(), color.blue()].map(|v| v as f32 / 255.0);
+
+                self.content.save_state();
+                if color.alpha() != 255 {
+                    self.alphas.insert(color.alpha());
+                    self.content.set_parameters(Name(alpha_name(color.alpha()).as_bytes()));
+                }
+                self.content.set_fill_rgb(r, g, b)
+                    .rect(rect.x1, rect.y1, rect.x2 - rect.x1, rect.y2 - rect.y1)
+                    .fill_nonzero();
+                self.content.restore_state();
+            }
+            Material::Image(image) => {
+                self.paint_image_region(rect, image, Rect::from_size(Size2D::new(1.0, 1.0)));
+            }
+        }
+    }
+
+    fn paint_image_region(&mut self, rect: Rect<f32>, image: Image, source: Rect<f32>) {
+        let key = (image.id, image.exposure.to_bits(), image.tone_mapping);
+        let index = match self.document.image_keys.get(&key) {
+            Some(index) => *index,
+            None => {
+                let pixels = self.resources.pixels(image);
+                let interpolate = image.sampling != SamplingQuality::Nearest;
+                self.add_image(Some(key), PdfImage { pixels, interpolate })
+            }
+        };
+
+        self.draw_image(index, rect, source);
+    }
+
+    /// PDF has no equivalent of SVG, so the document is embedded as an image
+    /// of twice the size of `rect`, which is sharp enough for print.
+    #[cfg(feature = "svg")]
+    fn paint_svg(&mut self, rect: Rect<f32>, svg: &crate::SvgDocument) {
+        let size = (rect.size * 2.0).round().cast::<u32>();
+        if size.is_empty() {
+            return;
+        }
+
+        let index = self.add_image(None, PdfImage {
+            pixels: svg.rasterize(size),
+            interpolate: true,
+        });
+        self.draw_image(index, rect, Rect::from_size(Size2D::new(1.0, 1.0)));
+    }
+}
+
+/// Identifies an image as it was drawn; HDR images are tone mapped per handle.
+type ImageKey = (ResourceId, u32, ToneMapping);
+
+#[derive(Debug, Default)]
+struct PdfDocument {
+    pages: Vec<PdfPage>,
+    images: Vec<PdfImage>,
+
+    /// Images that are drawn on multiple pages are only embedded once.
+    image_keys: HashMap<ImageKey, usize>,
+}
+
+#[derive(Debug)]
+struct PdfPage {
+    size: Size2D<u32>,
+    content: Vec<u8>,
+
+    /// Indices into [`PdfDocument::images`].
+    images: BTreeSet<usize>,
+
+    /// The fill opacities used, each of which needs a graphics state.
+    alphas: BTreeSet<u8>,
+}
+
+#[derive(Debug)]
+struct PdfImage {
+    pixels: RgbaImage,
+    interpolate: bool,
+}
+
+impl PdfDocument {
+    fn forget_image(&mut self, id: ResourceId) {
+        self.image_keys.retain(|key, _| key.0 != id);
+    }
+
+    fn write(&self) -> Vec<u8> {
+        let mut pdf = Pdf::new();
+        let mut next_ref = Ref::new(1);
+        let mut alloc = || next_ref.bump();
+
+        let catalog_id = alloc();
+        let page_tree_id = alloc();
+
+        let image_ids: Vec<Ref> = self.images.iter()
+            .map(|image| write_image(&mut pdf, &mut alloc, image))
+            .collect();
+
+        let mut page_ids = Vec::with_capacity(self.pages.len());
+        for page in &self.pages {
+            let page_id = alloc();
+            let content_id = alloc();
+            page_ids.push(page_id);
+
+            let alpha_ids: Vec<(u8, Ref)> = page.alphas.iter().map(|alpha| (*alpha, alloc())).collect();
+            for (alpha, id) in &alpha_ids {
+                pdf.ext_graphics(*id).non_stroking_alpha(*alpha as f32 / 255.0);
+            }
+
+            pdf.stream(content_id, &compress_to_vec_zlib(&page.content, 6))
+                .filter(Filter::FlateDecode);
+
+            let mut writer = pdf.page(page_id);
+            writer.parent(page_tree_id)
+                .media_box(PdfRect::new(0.0, 0.0, page.size.width as f32, page.size.height as f32))
+                .contents(content_id);
+
+            let mut resources = writer.resources();
+            let mut x_objects = resources.x_objects();
+            for index in &page.images {
+                x_objects.pair(Name(image_name(*index).as_bytes()), image_ids[*index]);
+            }
+            x_objects.finish();
+
+            let mut states = resources.ext_g_states();
+            for (alpha, id) in &alpha_ids {
+                states.pair(Name(alpha_name(*alpha).as_bytes()), *id);
+            }
+        }
+
+        pdf.pages(page_tree_id)
+            .kids(page_ids.iter().copied())
+            .count(page_ids.len() as i32);
+        pdf.catalog(catalog_id).pages(page_tree_id);
+
+        pdf.finish()
+    }
+}
+
+/// Writes the color channels as an image XObject, and the alpha channel as
+/// its soft mask when the image isn't opaque.
+fn write_image(pdf: &mut Pdf, alloc: &mut impl FnMut() -> Ref, image: &PdfImage) -> Ref {
+    let (width, height) = image.pixels.dimensions();
+    let id = alloc();
+
+    let is_opaque = image.pixels.pixels().all(|pixel| pixel.0[3] == 255);
+    let mask_id = (!is_opaque).then(&mut *alloc);
+
+    if let Some(mask_id) = mask_id {
+        let alpha: Vec<u8> = image.pixels.pixels().map(|pixel| pixel.0[3]).collect();
+        let data = compress_to_vec_zlib(&alpha, 6);
+
+        let mut mask = pdf.image_xobject(mask_id, &data);
+        mask.width(width as i32)
+            .height(height as i32)
+            .bits_per_component(8)
+            .interpolate(image.interpolate)
+            .filter(Filter::FlateDecode);
+        mask.color_space().device_gray();
+    }
+
+    let rgb: Vec<u8> = image.pixels.pixels().flat_map(|pixel| [pixel.0[0], pixel.0[1], pixel.0[2]]).collect();
+    let data = compress_to_vec_zlib(&rgb, 6);
+
+    let mut xobject = pdf.image_xobject(id, &data);
+    xobject.width(width as i32)
+        .height(height as i32)
+        .bits_per_component(8)
+        .interpolate(image.interpolate)
+        .filter(Filter::FlateDecode);
+    xobject.color_space().device_rgb();
+    if let Some(mask_id) = mask_id {
+        xobject.s_mask(mask_id);
+    }
+
+    id
+}
+
+fn image_name(index: usize) -> String {
+    format!("Im{index}")
+}
+
+fn alpha_name(alpha: u8) -> String {
+    format!("Ga{alpha}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    /// The dictionaries are written as plain text, only the streams are
+    /// compressed.
+    fn text(bytes: &[u8]) -> String {
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+
+    #[test]
+    fn every_frame_is_a_page() {
+        let (mut context, output) = PdfContext::new(Size2D::new(16, 8));
+        context.paint_frame(1.0, &mut |_| {});
+        context.resize(Size2D::new(32, 24));
+        context.paint_frame(1.0, &mut |_| {});
+
+        assert_eq!(output.page_count(), 2);
+        let pdf = text(&output.to_bytes());
+        assert!(pdf.starts_with("%PDF-"));
+        assert!(pdf.contains("/Count 2"), "{pdf}");
+        assert!(pdf.contains("/MediaBox [0 0 16 8]"), "{pdf}");
+        assert!(pdf.contains("/MediaBox [0 0 32 24]"), "{pdf}");
+
+        output.clear();
+        assert_eq!(output.page_count(), 0);
+    }
+
+    #[test]
+    fn translucent_images_have_a_soft_mask() {
+        let (mut context, output) = PdfContext::new(Size2D::new(16, 8));
+        let opaque = context.create_image(RgbaImage::from_pixel(2, 2, image::Rgba([0xFF; 4]))).unwrap();
+        let translucent = context.create_image(RgbaImage::from_pixel(2, 2, image::Rgba([0xFF, 0, 0, 0x80]))).unwrap();
+
+        context.paint_frame(1.0, &mut |painter| {
+            painter.paint_filled_rect(Rect::from_size(Size2D::new(8.0, 8.0)), Material::Image(opaque));
+            painter.paint_filled_rect(Rect::from_size(Size2D::new(8.0, 8.0)), Material::Image(translucent));
+            painter.paint_filled_rect(Rect::from_size(Size2D::new(4.0, 4.0)), Material::Image(translucent));
+        });
+
+        // Drawing an image twice embeds it once.
+        let pdf = text(&output.to_bytes());
+        assert_eq!(pdf.matches("/Subtype /Image").count(), 3, "{pdf}");
+        assert_eq!(pdf.matches("/SMask").count(), 1, "{pdf}");
+        assert!(pdf.contains("/Im0") && pdf.contains("/Im1"), "{pdf}");
+    }
+
+    #[test]
+    fn translucent_fills_use_a_graphics_state() {
+        let (context, output) = PdfContext::new(Size2D::new(16, 8));
+        context.paint_frame(1.0, &mut |painter| {
+            painter.paint_filled_rect(Rect::from_size(Size2D::new(8.0, 8.0)), Material::Color(Color::new(0xFF, 0, 0, 0xFF)));
+            painter.paint_filled_rect(Rect::from_size(Size2D::new(8.0, 8.0)), Material::Color(Color::new(0, 0, 0xFF, 0x80)));
+        });
+
+        let pdf = text(&output.to_bytes());
+        assert!(pdf.contains("/ExtGState"), "{pdf}");
+        assert!(pdf.contains("/Ga128"), "{pdf}");
+        assert!(pdf.contains("/ca 0.5019608"), "{pdf}");
+        assert!(!pdf.contains("/Ga255"), "{pdf}");
+    }
+
+    #[test]
+    fn hdr_painting_is_unsupported() {
+        let (context, _) = PdfContext::new(Size2D::new(16, 8));
+        assert!(!context.capabilities().float_textures);
+
+        let result = context.paint_offscreen_hdr(Size2D::new(4, 4), &mut |_| {});
+        assert!(matches!(result, Err(ImageLoadError::Unsupported(_))));
+    }
+}
@@ -0,0 +1,301 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::{cell::RefCell, collections::HashMap, fmt::Write, io::Cursor, path::Path, rc::Rc};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use euclid::default::{Rect, Size2D};
+use image::{ImageFormat, Rgba32FImage, RgbaImage};
+
+use crate::{
+    gfx::painter::PainterImplementation,
+    BackendInfo,
+    Capabilities,
+    Color,
+    CompressedImage,
+    ContextImplementation,
+    Image,
+    ImageLoadError,
+    Material,
+    ResourceId,
+    SamplingQuality,
+    ToneMapping,
+};
+
+use super::ExportResources;
+
+/// The document written by the last [`Context::paint`](crate::Context::paint)
+/// of an [`SvgContext`].
+#[derive(Debug, Clone, Default)]
+pub struct SvgOutput {
+    document: Rc<RefCell<String>>,
+}
+
+impl SvgOutput {
+    #[must_use]
+    pub fn document(&self) -> String {
+        self.document.borrow().clone()
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.document.borrow().as_bytes())
+    }
+}
+
+/// A [`Context`](crate::Context) that writes every frame as an SVG document,
+/// with one user unit per pixel. Images are embedded as PNG data URIs.
+pub struct SvgContext {
+    size: Size2D<u32>,
+    resources: ExportResources,
+    output: SvgOutput,
+}
+
+impl SvgContext {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(size: Size2D<u32>) -> (Box<dyn ContextImplementation>, SvgOutput) {
+        let output = SvgOutput::default();
+
+        let this = Self {
+            size,
+            resources: ExportResources::new(),
+            output: output.clone(),
+        };
+
+        (Box::new(this), output)
+    }
+}
+
+impl ContextImplementation for SvgContext {
+    fn resize(&mut self, size: Size2D<u32>) {
+        self.size = size;
+    }
+
+    fn create_image(&mut self, image: RgbaImage) -> Result<Image, ImageLoadError> {
+        Ok(self.resources.create_image(image))
+    }
+
+    fn update_image(&mut self, image: Image, region: Rect<u32>, pixels: RgbaImage) -> Result<(), ImageLoadError> {
+        self.resources.update_image(image, region, pixels)
+    }
+
+    fn unload_image(&mut self, image: Image) {
+        self.resources.unload_image(image);
+    }
+
+    fn create_hdr_image(&mut self, image: Rgba32FImage) -> Result<Image, ImageLoadError> {
+        Ok(self.resources.create_hdr_image(image))
+    }
+
+    fn create_compressed_image(&mut self, image: CompressedImage) -> Result<Image, ImageLoadError> {
+        self.create_image(image.decode()?)
+    }
+
+    /// Documents are resolution independent, so the scale factor doesn't
+    /// apply.
+    fn paint_frame(&self, _: f64, f: &mut dyn FnMut(&mut dyn PainterImplementation)) {
+        let mut painter = SvgPainter {
+            resources: &self.resources,
+            defs: String::new(),
+            body: String::new(),
+            defined_images: HashMap::new(),
+        };
+
+        f(&mut painter);
+
+        *self.output.document.borrow_mut() = painter.finish(self.size);
+    }
+
+    fn paint_offscreen_hdr(
+        &self,
+        _size: Size2D<u32>,
+        _f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<Rgba32FImage, ImageLoadError> {
+        Err(ImageLoadError::Unsupported("the SVG backend can't render to pixels"))
+    }
+
+    fn backend_info(&self) -> BackendInfo {
+        BackendInfo::new("SVG", None)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::export()
+    }
+}
+
+struct SvgPainter<'r> {
+    resources: &'r ExportResources,
+    defs: String,
+    body: String,
+
+    /// The `id` of the `<image>` in the `<defs>` of every image that was
+    /// drawn, so that images drawn multiple times are only embedded once.
+    /// HDR images are keyed by their tone mapping as well.
+    defined_images: HashMap<(ResourceId, u32, ToneMapping), String>,
+}
+
+impl SvgPainter<'_> {
+    fn finish(self, size: Size2D<u32>) -> String {
+        let mut document = String::new();
+        _ = writeln!(
+            document,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            w = size.width,
+            h = size.height,
+        );
+
+        if !self.defs.is_empty() {
+            _ = writeln!(document, "<defs>\n{}</defs>", self.defs);
+        }
+
+        document.push_str(&self.body);
+        document.push_str("</svg>\n");
+        document
+    }
+
+    /// Embeds the image in the `<defs>` if it wasn't yet, and returns its `id`.
+    fn define_image(&mut self, image: Image) -> String {
+        let key = (image.id, image.exposure.to_bits(), image.tone_mapping);
+        if let Some(id) = self.defined_images.get(&key) {
+            return id.clone();
+        }
+
+        let id = format!("image{}", self.defined_images.len());
+        let size = image.size();
+        _ = writeln!(
+            self.defs,
+            r#"<image id="{id}" width="{}" height="{}" href="{}"/>"#,
+            size.width,
+            size.height,
+            png_data_uri(&self.resources.pixels(image)),
+        );
+
+        self.defined_images.insert(key, id.clone());
+        id
+    }
+}
+
+impl PainterImplementation for SvgPainter<'_> {
+    fn paint_filled_rect(&mut self, rect: Rect<f32>, brush: Material) {
+        match brush {
+            Material::Color(color) => {
+                _ = writeln!(
+                    self.body,
+                    r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"{}/>"#,
+                    rect.min_x(),
+                    rect.min_y(),
+                    rect.width(),
+                    rect.height(),
+                    hex(color),
+                    opacity("fill-opacity", color),
+                );
+            }
+            Material::Image(image) => {
+                self.paint_image_region(rect, image, Rect::from_size(Size2D::new(1.0, 1.0)));
+            }
+        }
+    }
+
+    /// The region is selected with the `viewBox` of a nested `<svg>`, which
+    /// clips everything outside of it.
+    fn paint_image_region(&mut self, rect: Rect<f32>, image: Image, source: Rect<f32>) {
+        let id = self.define_image(image);
+        let size = image.size().cast::<f32>();
+        let source = source.scale(size.width, size.height);
+
+        let rendering = match image.sampling {
+            SamplingQuality::Nearest => r#" style="image-rendering:pixelated""#,
+            _ => "",
+        };
+
+        _ = writeln!(
+            self.body,
+            r##"<svg x="{}" y="{}" width="{}" height="{}" viewBox="{} {} {} {}" preserveAspectRatio="none"{rendering}><use href="#{id}"/></svg>"##,
+            rect.min_x(),
+            rect.min_y(),
+            rect.width(),
+            rect.height(),
+            source.min_x(),
+            source.min_y(),
+            source.width(),
+            source.height(),
+        );
+    }
+
+    /// The document is embedded as is, so it stays a vector graphic.
+    #[cfg(feature = "svg")]
+    fn paint_svg(&mut self, rect: Rect<f32>, svg: &crate::SvgDocument) {
+        _ = writeln!(
+            self.body,
+            r#"<image x="{}" y="{}" width="{}" height="{}" preserveAspectRatio="none" href="data:image/svg+xml;base64,{}"/>"#,
+            rect.min_x(),
+            rect.min_y(),
+            rect.width(),
+            rect.height(),
+            STANDARD.encode(svg.to_svg_string()),
+        );
+    }
+}
+
+fn hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.red(), color.green(), color.blue())
+}
+
+/// The opacity attribute for translucent colors, or nothing when opaque.
+fn opacity(attribute: &str, color: Color) -> String {
+    if color.alpha() == 255 {
+        return String::new();
+    }
+
+    format!(r#" {attribute}="{}""#, color.alpha() as f32 / 255.0)
+}
+
+fn png_data_uri(image: &RgbaImage) -> String {
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .expect("encoding PNG in memory shouldn't fail");
+
+    format!("data:image/png;base64,{}", STANDARD.encode(png))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rects_and_images_are_written() {
+        let (mut context, output) = SvgContext::new(Size2D::new(16, 8));
+        let pixels = RgbaImage::from_pixel(2, 1, image::Rgba([0, 0xFF, 0, 0xFF]));
+        let image = context.create_image(pixels.clone()).unwrap();
+
+        context.paint_frame(1.0, &mut |painter| {
+            painter.paint_filled_rect(Rect::from_size(Size2D::new(8.0, 4.0)), Material::Color(Color::new(0xFF, 0, 0, 0x80)));
+            painter.paint_filled_rect(Rect::new((8.0, 0.0).into(), Size2D::new(8.0, 8.0)), Material::Image(image));
+        });
+        let document = output.document();
+
+        assert!(document.contains(r##"<rect x="0" y="0" width="8" height="4" fill="#ff0000" fill-opacity="0.5019608"/>"##), "{document}");
+        assert!(document.contains(r##"<use href="#image0"/>"##), "{document}");
+
+        let prefix = r#"href="data:image/png;base64,"#;
+        let start = document.find(prefix).expect("the image should be embedded") + prefix.len();
+        let end = start + document[start..].find('"').unwrap();
+        let png = STANDARD.decode(&document[start..end]).unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().to_rgba8(), pixels);
+
+        #[cfg(feature = "svg")]
+        {
+            let tree = resvg::usvg::Tree::from_str(&document, &resvg::usvg::Options::default()).unwrap();
+            assert_eq!(tree.size(), resvg::usvg::Size::from_wh(16.0, 8.0).unwrap());
+            assert_eq!(tree.root().children().len(), 2);
+        }
+    }
+
+    #[test]
+    fn hdr_painting_is_unsupported() {
+        let (context, _) = SvgContext::new(Size2D::new(16, 8));
+        assert!(!context.capabilities().float_textures);
+
+        let result = context.paint_offscreen_hdr(Size2D::new(4, 4), &mut |_| {});
+        assert!(matches!(result, Err(ImageLoadError::Unsupported(_))));
+    }
+}
@@ -1,67 +1,477 @@
 // Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
-use std::{path::Path, rc::Rc};
+use std::{cell::{Cell, RefCell}, collections::HashMap, num::NonZero, rc::Rc};
 
-use euclid::default::Size2D;
-use glium::{backend::glutin::SimpleWindowBuilder, glutin::surface::WindowSurface, texture::RawImage2d, uniforms::{AsUniformValue, UniformValue}, winit::{event_loop::EventLoop, window::Window}, Display, Texture2d};
+use euclid::default::{Rect, Size2D};
+use glium::{
+    glutin::{
+        config::{Config, ConfigTemplateBuilder},
+        context::{ContextAttributesBuilder, NotCurrentContext},
+        display::{Display as GlutinDisplay, DisplayApiPreference, GetGlDisplay},
+        prelude::*,
+        surface::{SurfaceAttributesBuilder, SwapInterval, WindowSurface},
+    },
+    texture::{CompressedMipmapsOption, CompressedTexture2d, MipmapsOption, RawImage2d, UncompressedFloatFormat},
+    uniforms::{AsUniformValue, UniformValue},
+    Rect as GLRect,
+    framebuffer::SimpleFrameBuffer,
+    CapabilitiesSource,
+    Display,
+    Texture2d,
+};
+use image::{Rgba32FImage, RgbaImage};
 use painter::GLPainter;
+use winit::{
+    event_loop::{ActiveEventLoop, EventLoop},
+    raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawDisplayHandle, RawWindowHandle},
+    window::{Window, WindowAttributes, WindowId},
+};
 
 use crate::{
+    BackendInfo,
+    BlendMode,
+    BlockFormat,
+    Capabilities,
     Color,
+    CompressedImage,
+    ContextBuilder,
+    ContextCreationError,
     ContextImplementation,
     EventTy,
     Image,
     ImageLoadError,
+    PixelFormat,
     ResourceManager,
     ResourceNamespace,
+    SamplingQuality,
 };
 
-use super::painter::PainterImplementation;
+use super::{
+    atlas::{apply_defragmentation, write_entry, AtlasAllocator, ATLAS_MIP_LEVELS, ATLAS_PAGE_SIZE},
+    compressed::mip_size,
+    painter::PainterImplementation,
+};
 
 mod painter;
 
+/// The fields are dropped in order, so the resources are released before the
+/// contexts, and those before the window.
 pub struct GLContext {
-    display: Display<WindowSurface>,
     resources: Rc<GLResources>,
+
+    /// The display of the first window, with which the resources are created.
+    display: Display<WindowSurface>,
+
+    windows: HashMap<WindowId, Display<WindowSurface>>,
+    shared: SharedContext,
+    clear_color: Color,
+
+    /// The first window, which is kept for the surface of [`Self::display`]
+    /// when it is detached.
+    window: Rc<Window>,
 }
 
 impl GLContext {
-    pub fn new(event_loop: &EventLoop<EventTy>) -> (Box<dyn ContextImplementation>, Rc<Window>) {
-        let (window, display) = SimpleWindowBuilder::new()
-            .with_inner_size(1600, 1200)
-            .with_title("Zinnebeeld")
-            .build(&event_loop);
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        event_loop: &EventLoop<EventTy>,
+        builder: &ContextBuilder,
+    ) -> Result<(Box<dyn ContextImplementation>, Rc<Window>), ContextCreationError> {
+        let (window, config) = create_window(event_loop, builder)?;
+        let window = Rc::new(window);
 
-        let this = Self {
-            display,
+        Ok((Self::with_config(Rc::clone(&window), config, builder)?, window))
+    }
+
+    /// Creates the context on an existing window, e.g. one that the software
+    /// renderer painted into.
+    pub fn with_window(window: Rc<Window>, builder: &ContextBuilder) -> Result<Box<dyn ContextImplementation>, ContextCreationError> {
+        let config = find_config(&window, builder)?;
+        Self::with_config(window, config, builder)
+    }
+
+    fn with_config(window: Rc<Window>, config: Config, builder: &ContextBuilder) -> Result<Box<dyn ContextImplementation>, ContextCreationError> {
+        let shared = SharedContext::new(config, &window, builder.vsync)?;
+        let display = shared.create_display(&window)?;
+
+        Ok(Box::new(Self {
             resources: Rc::new(GLResources::new()),
+            windows: HashMap::from([(window.id(), display.clone())]),
+            display,
+            shared,
+            clear_color: builder.clear_color,
+            window,
+        }))
+    }
+}
+
+fn config_template(builder: &ContextBuilder) -> ConfigTemplateBuilder {
+    let template = ConfigTemplateBuilder::new().with_transparency(builder.transparent);
+    if builder.samples > 1 {
+        return template.with_multisampling(builder.samples);
+    }
+    template
+}
+
+/// Like glium's `SimpleWindowBuilder`, but with the transparency, multisampling
+/// and vsync of the builder, and without panicking when there is no suitable
+/// OpenGL implementation or configuration.
+fn create_window(event_loop: &EventLoop<EventTy>, builder: &ContextBuilder) -> Result<(Window, Config), ContextCreationError> {
+    // WGL only finds configs with every feature for an existing window.
+    if cfg!(target_os = "windows") {
+        #[allow(deprecated)]
+        let window = event_loop.create_window(builder.window_attributes())?;
+        let config = find_config(&window, builder)?;
+        return Ok((window, config));
+    }
+
+    let display = create_display(event_loop.display_handle()?.as_raw(), None)?;
+    let config = pick_config(&display, builder, None)?;
+
+    // Sets the visual of the config on X11, and drops the transparency if the
+    // config doesn't support it.
+    let window = glutin_winit::finalize_window(event_loop, builder.window_attributes(), &config)?;
+    Ok((window, config))
+}
+
+/// Unlike [`create_window`], the window already exists, so its visual can't
+/// be chosen to match the config.
+fn find_config(window: &Window, builder: &ContextBuilder) -> Result<Config, ContextCreationError> {
+    let handle = window.window_handle()?.as_raw();
+    let display = create_display(window.display_handle()?.as_raw(), Some(handle))?;
+    pick_config(&display, builder, Some(handle))
+}
+
+/// EGL is used on X11 and Wayland, as it doesn't require the visual of the
+/// window to match the config.
+fn create_display(raw_display: RawDisplayHandle, handle: Option<RawWindowHandle>) -> Result<GlutinDisplay, ContextCreationError> {
+    #[cfg(target_os = "windows")]
+    let preference = DisplayApiPreference::Wgl(handle);
+    #[cfg(target_os = "macos")]
+    let preference = DisplayApiPreference::Cgl;
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let preference = DisplayApiPreference::Egl;
+
+    _ = handle;
+
+    // SAFETY: the display handle outlives the windows, which the context
+    //         keeps alive for as long as this display.
+    Ok(unsafe { GlutinDisplay::new(raw_display, preference) }?)
+}
+
+/// The first config that matches the builder. When there is none, e.g.
+/// because the multisampling or transparency isn't supported, the first
+/// config without them is used instead.
+fn pick_config(display: &GlutinDisplay, builder: &ContextBuilder, handle: Option<RawWindowHandle>) -> Result<Config, ContextCreationError> {
+    let relaxed = ConfigTemplateBuilder::new();
+
+    for template in [config_template(builder), relaxed] {
+        let template = match handle {
+            Some(handle) => template.compatible_with_native_window(handle),
+            None => template,
+        };
+
+        // SAFETY: the template only refers to the window, which outlives the
+        //         configs.
+        if let Some(config) = unsafe { display.find_configs(template.build()) }.ok().and_then(|mut configs| configs.next()) {
+            return Ok(config);
+        }
+    }
+
+    Err(ContextCreationError::Gl("no suitable OpenGL configuration".into()))
+}
+
+/// Creates the contexts of the windows, which all share their objects, so
+/// that the resources can be used in every window.
+struct SharedContext {
+    config: Config,
+
+    /// Never made current, but every context shares with it, so windows can
+    /// be added after the others are closed.
+    root: NotCurrentContext,
+
+    vsync: bool,
+}
+
+impl SharedContext {
+    fn new(config: Config, window: &Window, vsync: bool) -> Result<Self, ContextCreationError> {
+        let attributes = ContextAttributesBuilder::new().build(Some(window.window_handle()?.as_raw()));
+
+        // SAFETY: the handle is only used while creating the context, to find
+        //         the pixel format of the window on WGL.
+        let root = unsafe { config.display().create_context(&config, &attributes) }?;
+
+        Ok(Self {
+            config,
+            root,
+            vsync,
+        })
+    }
+
+    fn create_display(&self, window: &Window) -> Result<Display<WindowSurface>, ContextCreationError> {
+        let handle = window.window_handle()?.as_raw();
+        let size = window.inner_size();
+        let (Some(width), Some(height)) = (NonZero::new(size.width), NonZero::new(size.height)) else {
+            return Err(ContextCreationError::Gl("the window has no area".into()));
         };
+        let attributes = SurfaceAttributesBuilder::<WindowSurface>::new().build(handle, width, height);
+        let context_attributes = ContextAttributesBuilder::new().with_sharing(&self.root).build(Some(handle));
+
+        // SAFETY: the handle stays valid as long as the window, which outlives
+        //         the display.
+        let surface = unsafe { self.config.display().create_window_surface(&self.config, &attributes) }?;
+        let context = unsafe { self.config.display().create_context(&self.config, &context_attributes) }?
+            .make_current(&surface)?;
 
-        (Box::new(this), Rc::new(window))
+        let interval = if self.vsync {
+            SwapInterval::Wait(NonZero::new(1).unwrap())
+        } else {
+            SwapInterval::DontWait
+        };
+
+        // Not every platform lets the interval be changed, which isn't fatal.
+        _ = surface.set_swap_interval(&context, interval);
+
+        Ok(Display::from_context_surface(context, surface)?)
     }
 }
 
 impl ContextImplementation for GLContext {
     fn resize(&mut self, size: Size2D<u32>) {
-        self.display.resize((size.width, size.height));
+        self.resize_window(self.window.id(), size);
     }
 
-    fn load_image(&mut self, path: &Path) -> Result<Image, ImageLoadError> {
-        let (img, size) = Image::load(path)?;
+    fn create_image(&mut self, img: RgbaImage) -> Result<Image, ImageLoadError> {
         let dimensions = img.dimensions();
+        let size = Size2D::from(dimensions);
+
+        if AtlasAllocator::accepts(size) {
+            let mut atlas = self.resources.atlas.borrow_mut();
+            let (id, entry) = atlas.allocator.allocate(size);
+            if entry.page == atlas.pages.len() {
+                match GLAtlasPage::new(&self.display) {
+                    Ok(page) => atlas.pages.push(page),
+                    Err(e) => {
+                        atlas.allocator.free(id);
+                        return Err(e);
+                    }
+                }
+            }
+
+            let page = &mut atlas.pages[entry.page];
+            let padded = write_entry(&mut page.pixels, entry, &img);
+            page.write(entry.padded_rect(), padded);
+            return Ok(Image::new(id, size));
+        }
 
         let img = RawImage2d::from_raw_rgba_reversed(&img.into_raw(), dimensions);
-        let texture = glium::texture::Texture2d::new(&self.display, img)?;
-        let id = self.resources.images.add(texture);
-        Ok(Image {
-            id,
-            size,
-        })
+        let texture = Texture2d::with_mipmaps(&self.display, img, MipmapsOption::AutoGeneratedMipmaps)?;
+        let id = self.resources.images.add(MipmappedTexture::new(texture));
+        Ok(Image::new(id, size))
+    }
+
+    fn update_image(&mut self, image: Image, region: Rect<u32>, pixels: RgbaImage) -> Result<(), ImageLoadError> {
+        if image.id.namespace() == ResourceNamespace::AtlasImage {
+            let mut atlas = self.resources.atlas.borrow_mut();
+            let entry = atlas.allocator.get(image.id).ok_or(ImageLoadError::UnknownImage)?;
+            let region = region.translate(entry.rect.origin.to_vector());
+
+            let page = &mut atlas.pages[entry.page];
+            image::imageops::replace(&mut page.pixels, &pixels, region.min_x() as i64, region.min_y() as i64);
+            page.write(region, pixels);
+            return Ok(());
+        }
+
+        if !self.resources.images.contains(image.id) {
+            return Err(ImageLoadError::UnknownImage);
+        }
+
+        // Textures are uploaded upside down, see `create_image`.
+        let rect = GLRect {
+            left: region.min_x(),
+            bottom: image.size.height - region.max_y(),
+            width: region.width(),
+            height: region.height(),
+        };
+
+        let dimensions = pixels.dimensions();
+        let pixels = RawImage2d::from_raw_rgba_reversed(&pixels.into_raw(), dimensions);
+        self.resources.images.with(image.id, |texture| texture.write(rect, pixels));
+        Ok(())
+    }
+
+    fn unload_image(&mut self, image: Image) {
+        match image.id.namespace() {
+            ResourceNamespace::Image => {
+                self.resources.images.remove(image.id);
+            }
+
+            ResourceNamespace::HdrImage => {
+                self.resources.hdr_images.remove(image.id);
+            }
+
+            ResourceNamespace::CompressedImage => {
+                self.resources.compressed_images.remove(image.id);
+            }
+
+            ResourceNamespace::AtlasImage => {
+                let mut atlas = self.resources.atlas.borrow_mut();
+                if let Some(defragmentation) = atlas.allocator.free(image.id) {
+                    let page = &mut atlas.pages[defragmentation.page];
+                    page.pixels = apply_defragmentation(&page.pixels, &defragmentation);
+                    let full = Rect::from_size(Size2D::new(ATLAS_PAGE_SIZE, ATLAS_PAGE_SIZE));
+                    page.write(full, page.pixels.clone());
+                }
+            }
+        }
     }
 
-    fn paint_frame(&self, f: &mut dyn FnMut(&mut dyn PainterImplementation)) {
-        let mut painter = GLPainter::new(self.display.clone(), Rc::clone(&self.resources));
+    fn create_hdr_image(&mut self, img: Rgba32FImage) -> Result<Image, ImageLoadError> {
+        let dimensions = img.dimensions();
+        let size = Size2D::from(dimensions);
+
+        let img = RawImage2d::from_raw_rgba_reversed(&img.into_raw(), dimensions);
+        let texture = Texture2d::with_format(&self.display, img, UncompressedFloatFormat::F32F32F32F32, MipmapsOption::AutoGeneratedMipmaps)?;
+        let id = self.resources.hdr_images.add(texture);
+        Ok(Image::new(id, size))
+    }
+
+    fn create_compressed_image(&mut self, image: CompressedImage) -> Result<Image, ImageLoadError> {
+        let format = gl_compressed_format(image.format)
+            .filter(|format| format.is_supported(&self.display));
+        let Some(format) = format else {
+            return self.create_image(image.decode()?);
+        };
+
+        let mipmaps = match image.levels.len() {
+            1 => CompressedMipmapsOption::NoMipmap,
+            count => CompressedMipmapsOption::EmptyMipmapsMax(count as u32 - 1),
+        };
+
+        let texture = CompressedTexture2d::with_compressed_data(
+            &self.display,
+            &image.levels[0],
+            image.size.width,
+            image.size.height,
+            format,
+            mipmaps,
+        )?;
+
+        for (level, data) in image.levels.iter().enumerate().skip(1) {
+            let size = mip_size(image.size, level as u32);
+            let rect = GLRect {
+                left: 0,
+                bottom: 0,
+                width: size.width,
+                height: size.height,
+            };
+
+            texture.mipmap(level as u32).unwrap()
+                .write_compressed_data(rect, data, size.width, size.height, format)
+                .map_err(|()| ImageLoadError::BlockDecode("failed to upload mipmap level"))?;
+        }
+
+        let id = self.resources.compressed_images.add(texture);
+        Ok(Image::new(id, image.size))
+    }
+
+    fn paint_frame(&self, scale_factor: f64, f: &mut dyn FnMut(&mut dyn PainterImplementation)) {
+        self.paint_window(self.window.id(), scale_factor, f);
+    }
+
+    fn paint_offscreen_hdr(
+        &self,
+        size: Size2D<u32>,
+        f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<Rgba32FImage, ImageLoadError> {
+        let texture = Texture2d::empty_with_format(
+            &self.display,
+            UncompressedFloatFormat::F32F32F32F32,
+            MipmapsOption::NoMipmap,
+            size.width,
+            size.height,
+        )?;
+
+        let target = SimpleFrameBuffer::new(&self.display, &texture).map_err(|_| ImageLoadError::FramebufferCreation)?;
+        let mut painter = GLPainter::new(target, self.display.clone(), Rc::clone(&self.resources), Color::BLACK);
+        f(&mut painter);
+        drop(painter);
+
+        // SAFETY: RGBA32F is a color-renderable format since OpenGL 3.0, which
+        //         our shaders (#version 140) already require.
+        let pixels = unsafe { texture.unchecked_read::<RawImage2d<f32>, (f32, f32, f32, f32)>() };
+
+        // OpenGL stores the rows bottom-up.
+        let mut img = Rgba32FImage::from_raw(size.width, size.height, pixels.data.into_owned()).unwrap();
+        image::imageops::flip_vertical_in_place(&mut img);
+        Ok(img)
+    }
+
+    fn backend_info(&self) -> BackendInfo {
+        BackendInfo::new("OpenGL", Some(format!(
+            "{} ({})",
+            self.display.get_opengl_renderer_string(),
+            self.display.get_opengl_version_string(),
+        )))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let capabilities = self.display.get_capabilities();
+
+        Capabilities {
+            max_texture_size: capabilities.max_texture_size as u32,
+            pixel_formats: PixelFormat::ALL.to_vec(),
+            max_samples: self.shared.config.num_samples(),
+            blend_modes: vec![BlendMode::SourceOver],
+            float_textures: UncompressedFloatFormat::F32F32F32F32.is_supported(&self.display),
+            compressed_formats: BlockFormat::ALL.into_iter()
+                .filter(|format| gl_compressed_format(*format).is_some_and(|format| format.is_supported(&self.display)))
+                .collect(),
+            hardware_accelerated: !is_software_renderer(self.display.get_opengl_renderer_string()),
+        }
+    }
+
+    /// The window is created with the visual of the configuration, which X11
+    /// requires for the contexts to be compatible.
+    fn create_window(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        attributes: WindowAttributes,
+    ) -> Result<Rc<Window>, ContextCreationError> {
+        let window = Rc::new(glutin_winit::finalize_window(event_loop, attributes, &self.shared.config)?);
+        self.attach_window(Rc::clone(&window))?;
+        Ok(window)
+    }
+
+    fn attach_window(&mut self, window: Rc<Window>) -> Result<(), ContextCreationError> {
+        let display = self.shared.create_display(&window)?;
+        self.windows.insert(window.id(), display);
+        Ok(())
+    }
+
+    /// The first window can't be closed, because the resources belong to its
+    /// context, so it is hidden instead.
+    fn detach_window(&mut self, id: WindowId) {
+        self.windows.remove(&id);
+
+        if id == self.window.id() {
+            self.window.set_visible(false);
+        }
+    }
+
+    fn resize_window(&mut self, id: WindowId, size: Size2D<u32>) {
+        if let Some(display) = self.windows.get(&id) {
+            display.resize((size.width, size.height));
+        }
+    }
+
+    fn paint_window(&self, id: WindowId, scale_factor: f64, f: &mut dyn FnMut(&mut dyn PainterImplementation)) {
+        let Some(display) = self.windows.get(&id) else {
+            return;
+        };
+
+        let mut painter = GLPainter::for_frame(display.clone(), Rc::clone(&self.resources), self.clear_color, scale_factor as f32);
 
         f(&mut painter);
 
@@ -69,15 +479,115 @@ impl ContextImplementation for GLContext {
     }
 }
 
+/// Whether the renderer is one of the common OpenGL implementations that run
+/// on the CPU, e.g. Mesa's llvmpipe in virtual machines.
+fn is_software_renderer(renderer: &str) -> bool {
+    ["llvmpipe", "softpipe", "swrast", "SwiftShader", "Software Rasterizer", "GDI Generic"]
+        .iter()
+        .any(|name| renderer.contains(name))
+}
+
 struct GLResources {
-    images: ResourceManager<Texture2d>,
+    images: ResourceManager<MipmappedTexture>,
+    hdr_images: ResourceManager<Texture2d>,
+    compressed_images: ResourceManager<CompressedTexture2d>,
+    atlas: RefCell<GLAtlas>,
 }
 
 impl GLResources {
     pub fn new() -> Self {
         Self {
             images: ResourceManager::new(ResourceNamespace::Image),
+            hdr_images: ResourceManager::new(ResourceNamespace::HdrImage),
+            compressed_images: ResourceManager::new(ResourceNamespace::CompressedImage),
+            atlas: RefCell::new(GLAtlas::default()),
+        }
+    }
+}
+
+/// A texture created with generated mipmaps, of which the mipmaps are
+/// regenerated lazily, as images that are updated every frame or are never
+/// scaled down would otherwise pay for them needlessly.
+struct MipmappedTexture {
+    texture: Texture2d,
+    stale: Cell<bool>,
+}
+
+impl MipmappedTexture {
+    fn new(texture: Texture2d) -> Self {
+        Self {
+            texture,
+            stale: Cell::new(false),
+        }
+    }
+
+    /// Writes to the base level, leaving the other levels stale.
+    fn write(&self, rect: GLRect, pixels: RawImage2d<'_, u8>) {
+        self.texture.write(rect, pixels);
+        self.stale.set(true);
+    }
+
+    /// The texture, with the mipmaps regenerated if `sampling` uses them.
+    fn prepare(&self, sampling: SamplingQuality) -> &Texture2d {
+        if sampling.uses_mipmaps() && self.stale.replace(false) {
+            // SAFETY: the texture was created with generated mipmaps, so
+            //         the storage for the levels exists.
+            unsafe { self.texture.generate_mipmaps() };
         }
+
+        &self.texture
+    }
+}
+
+#[derive(Default)]
+struct GLAtlas {
+    allocator: AtlasAllocator,
+    pages: Vec<GLAtlasPage>,
+}
+
+/// The mipmaps stop at [`ATLAS_MIP_LEVELS`], where the padding is one texel.
+struct GLAtlasPage {
+    texture: MipmappedTexture,
+
+    /// A copy of the texture, top-down, used to compact the page.
+    pixels: RgbaImage,
+}
+
+impl GLAtlasPage {
+    fn new(display: &Display<WindowSurface>) -> Result<Self, ImageLoadError> {
+        let pixels = RgbaImage::new(ATLAS_PAGE_SIZE, ATLAS_PAGE_SIZE);
+        let raw = RawImage2d::from_raw_rgba(pixels.as_raw().clone(), pixels.dimensions());
+        let texture = Texture2d::with_mipmaps(display, raw, MipmapsOption::AutoGeneratedMipmapsMax(ATLAS_MIP_LEVELS))?;
+        Ok(Self { texture: MipmappedTexture::new(texture), pixels })
+    }
+
+    /// Uploads `pixels` to the top-down `region` of the texture.
+    fn write(&self, region: Rect<u32>, pixels: RgbaImage) {
+        let rect = GLRect {
+            left: region.min_x(),
+            bottom: ATLAS_PAGE_SIZE - region.max_y(),
+            width: region.width(),
+            height: region.height(),
+        };
+
+        let dimensions = pixels.dimensions();
+        let pixels = RawImage2d::from_raw_rgba_reversed(&pixels.into_raw(), dimensions);
+        self.texture.write(rect, pixels);
+    }
+}
+
+/// The formats that can be sampled directly. BC4 and BC5 are decoded instead,
+/// as OpenGL would expose them as red/green instead of luminance, and ETC2
+/// and BC6H aren't supported by glium.
+fn gl_compressed_format(format: BlockFormat) -> Option<glium::texture::CompressedFormat> {
+    use glium::texture::CompressedFormat;
+    match format {
+        BlockFormat::Bc1 => Some(CompressedFormat::S3tcDxt1NoAlpha),
+        BlockFormat::Bc1a => Some(CompressedFormat::S3tcDxt1Alpha),
+        BlockFormat::Bc2 => Some(CompressedFormat::S3tcDxt3Alpha),
+        BlockFormat::Bc3 => Some(CompressedFormat::S3tcDxt5Alpha),
+        BlockFormat::Bc7 => Some(CompressedFormat::BptcUnorm4),
+        _ => None,
     }
 }
 
@@ -3,74 +3,237 @@
 
 use std::rc::Rc;
 
-use euclid::default::{Rect, Size2D, Transform3D, Vector3D};
-use glium::{glutin::surface::WindowSurface, uniform, Display, Frame, Surface};
+use euclid::default::{Point2D, Rect, Size2D, Transform3D, Vector3D};
+use glium::{
+    glutin::surface::WindowSurface,
+    uniform,
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler},
+    Display,
+    Frame,
+    Surface,
+};
 
-use crate::{gfx::painter::PainterImplementation, Material, Mesh, ShaderPrograms};
+use crate::{gfx::{atlas::ATLAS_MAX_SAMPLING, painter::PainterImplementation}, Color, Image, Material, Mesh, ResourceNamespace, SamplingQuality, ShaderPrograms};
 
 use super::GLResources;
 
-pub struct GLPainter {
-    target: Frame,
+/// The normalized source rectangle covering the whole texture.
+const FULL_TEX_RECT: Rect<f32> = Rect::new(Point2D::new(0.0, 0.0), Size2D::new(1.0, 1.0));
+
+pub struct GLPainter<S: Surface> {
+    target: S,
     target_size: Size2D<f32>,
+    scale_factor: f32,
+    /// Whether the target holds sRGB encoded colors, as opposed to the linear
+    /// ones of an HDR target.
+    srgb_output: bool,
     display: Display<WindowSurface>,
     resources: Rc<GLResources>,
 }
 
-impl GLPainter {
-    pub fn new(display: Display<WindowSurface>, resources: Rc<GLResources>) -> Self {
-        let mut target = display.draw();
-        target.clear_color(0.0, 0.0, 0.0, 1.0);
+impl GLPainter<Frame> {
+    pub fn for_frame(display: Display<WindowSurface>, resources: Rc<GLResources>, clear_color: Color, scale_factor: f32) -> Self {
+        let target = display.draw();
+        Self {
+            scale_factor,
+            srgb_output: true,
+            ..Self::new(target, display, resources, clear_color)
+        }
+    }
+
+    pub fn finish(self) {
+        self.target.finish().unwrap();
+    }
+}
 
-        let (width, height) = display.get_framebuffer_dimensions();
+impl<S: Surface> GLPainter<S> {
+    /// The target is cleared to `clear_color`, premultiplied for transparent
+    /// windows. Coordinates are in pixels of the target.
+    pub fn new(mut target: S, display: Display<WindowSurface>, resources: Rc<GLResources>, clear_color: Color) -> Self {
+        let alpha = clear_color.alpha() as f32 / 255.0;
+        let channel = |value: u8| value as f32 / 255.0 * alpha;
+        target.clear_color(channel(clear_color.red()), channel(clear_color.green()), channel(clear_color.blue()), alpha);
+
+        let (width, height) = target.get_dimensions();
         Self {
             target,
             target_size: Size2D::new(width as _, height as _),
+            scale_factor: 1.0,
+            srgb_output: false,
             display,
             resources,
         }
     }
-
-    pub fn finish(self) {
-        self.target.finish().unwrap();
-    }
 }
 
-impl PainterImplementation for GLPainter {
-    fn paint_filled_rect(&mut self, rect: Rect<f32>, brush: Material) {
+impl<S: Surface> GLPainter<S> {
+    fn matrix(&self, rect: Rect<f32>) -> [[f32; 4]; 4] {
+        let rect = rect.scale(self.scale_factor, self.scale_factor);
         let x_scale = rect.width() / self.target_size.width;
         let y_scale = rect.height() / self.target_size.height;
 
-        let matrix = Transform3D::identity()
+        Transform3D::identity()
             .then_translate(Vector3D::new(rect.min_x() / rect.width(), -rect.min_y() / rect.height(), 0.0))
             .then_scale(x_scale, y_scale, 1.0)
             .then_translate(Vector3D::new(x_scale / 2.0 - 1.0, 1.0 - y_scale / 2.0, 0.0))
-            .to_arrays();
+            .to_arrays()
+    }
+}
 
+impl<S: Surface> PainterImplementation for GLPainter<S> {
+    fn paint_filled_rect(&mut self, rect: Rect<f32>, brush: Material) {
         match brush {
             Material::Color(color) => {
                 let mesh = Mesh::new_square(&self.display);
                 let program = ShaderPrograms::create_solid_color(&self.display);
 
                 let uniforms = uniform! {
-                    matrix: matrix,
+                    matrix: self.matrix(rect),
                     color: color,
                 };
                 mesh.draw(&mut self.target, &program, &uniforms);
             }
-            Material::Image(image) => {
-                let mesh = Mesh::new_textured_square(&self.display);
+            Material::Image(image) => self.paint_image_region(rect, image, FULL_TEX_RECT),
+        };
+    }
+
+    fn paint_image_region(&mut self, rect: Rect<f32>, image: Image, source: Rect<f32>) {
+        let matrix = self.matrix(rect);
+
+        match image.id.namespace() {
+            ResourceNamespace::HdrImage => {
+                let mesh = Mesh::new_textured_square(&self.display, false);
+                let program = ShaderPrograms::create_hdr(&self.display);
+
+                self.resources.hdr_images.with(image.id, |tex| {
+                    let uniforms = uniform! {
+                        matrix: matrix,
+                        tex: configure_sampler(tex.sampled(), image.sampling),
+                        tex_rect: bottom_up(source),
+                        exposure: image.exposure,
+                        tone_mapping: image.tone_mapping.as_shader_value(),
+                        srgb_output: self.srgb_output,
+                    };
+
+                    mesh.draw(&mut self.target, &program, &uniforms);
+                });
+            }
+            ResourceNamespace::CompressedImage => {
+                let mesh = Mesh::new_textured_square(&self.display, true);
+                let program = ShaderPrograms::create_textured(&self.display);
+
+                self.resources.compressed_images.with(image.id, |tex| {
+                    // Mipmaps can't be generated for compressed textures, so
+                    // only the levels from the file are available.
+                    let sampling = if tex.get_mipmap_levels() > 1 {
+                        image.sampling
+                    } else {
+                        image.sampling.min(SamplingQuality::Linear)
+                    };
+
+                    let uniforms = uniform! {
+                        matrix: matrix,
+                        tex: configure_sampler(tex.sampled(), sampling),
+                        tex_rect: top_down(source),
+                    };
+
+                    mesh.draw(&mut self.target, &program, &uniforms);
+                });
+            }
+            ResourceNamespace::AtlasImage => {
+                let mesh = Mesh::new_textured_square(&self.display, false);
+                let program = ShaderPrograms::create_textured(&self.display);
+
+                let atlas = self.resources.atlas.borrow();
+                let Some(entry) = atlas.allocator.get(image.id) else {
+                    return;
+                };
+
+                let uv = entry.uv_rect();
+                let source = Rect::new(
+                    uv.origin + source.origin.to_vector().component_mul(uv.size.to_vector()),
+                    Size2D::new(source.width() * uv.width(), source.height() * uv.height()),
+                );
+
+                let sampling = image.sampling.min(ATLAS_MAX_SAMPLING);
+                let uniforms = uniform! {
+                    matrix: matrix,
+                    tex: configure_sampler(atlas.pages[entry.page].texture.prepare(sampling).sampled(), sampling),
+                    tex_rect: bottom_up(source),
+                };
+
+                mesh.draw(&mut self.target, &program, &uniforms);
+            }
+            ResourceNamespace::Image => {
+                let mesh = Mesh::new_textured_square(&self.display, false);
                 let program = ShaderPrograms::create_textured(&self.display);
 
                 self.resources.images.with(image.id, |tex| {
                     let uniforms = uniform! {
                         matrix: matrix,
-                        tex: tex,
+                        tex: configure_sampler(tex.prepare(image.sampling).sampled(), image.sampling),
+                        tex_rect: bottom_up(source),
                     };
 
                     mesh.draw(&mut self.target, &program, &uniforms);
                 });
             }
+        }
+    }
+
+    /// The document is rendered at the size of `rect` in pixels of the target.
+    #[cfg(feature = "svg")]
+    fn paint_svg(&mut self, rect: Rect<f32>, svg: &crate::SvgDocument) {
+        let size = (rect.size * self.scale_factor).round().cast::<u32>();
+        if size.is_empty() {
+            return;
+        }
+
+        let pixels = svg.rasterize(size);
+        let pixels = glium::texture::RawImage2d::from_raw_rgba_reversed(pixels.as_raw(), pixels.dimensions());
+        let Ok(texture) = glium::Texture2d::new(&self.display, pixels) else {
+            return;
+        };
+
+        let mesh = Mesh::new_textured_square(&self.display, false);
+        let program = ShaderPrograms::create_textured(&self.display);
+        let uniforms = uniform! {
+            matrix: self.matrix(rect),
+            tex: texture.sampled()
+                .minify_filter(MinifySamplerFilter::Linear)
+                .magnify_filter(MagnifySamplerFilter::Linear),
+            tex_rect: bottom_up(FULL_TEX_RECT),
         };
+
+        mesh.draw(&mut self.target, &program, &uniforms);
+    }
+}
+
+/// The `tex_rect` uniform for textures whose first row is at the top, see
+/// [`Mesh::new_textured_square`].
+fn top_down(source: Rect<f32>) -> [f32; 4] {
+    [source.min_x(), source.min_y(), source.width(), source.height()]
+}
+
+/// The `tex_rect` uniform for textures that were uploaded bottom-up.
+fn bottom_up(source: Rect<f32>) -> [f32; 4] {
+    [source.min_x(), 1.0 - source.max_y(), source.width(), source.height()]
+}
+
+fn configure_sampler<T>(sampler: Sampler<'_, T>, quality: SamplingQuality) -> Sampler<'_, T> {
+    match quality {
+        SamplingQuality::Nearest => sampler
+            .minify_filter(MinifySamplerFilter::Nearest)
+            .magnify_filter(MagnifySamplerFilter::Nearest),
+        SamplingQuality::Linear => sampler
+            .minify_filter(MinifySamplerFilter::Linear)
+            .magnify_filter(MagnifySamplerFilter::Linear),
+        SamplingQuality::Trilinear => sampler
+            .minify_filter(MinifySamplerFilter::LinearMipmapLinear)
+            .magnify_filter(MagnifySamplerFilter::Linear),
+        SamplingQuality::Anisotropic => sampler
+            .minify_filter(MinifySamplerFilter::LinearMipmapLinear)
+            .magnify_filter(MagnifySamplerFilter::Linear)
+            .anisotropy(16),
     }
 }
@@ -0,0 +1,87 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use crate::Color;
+
+/// The operator that maps the unbounded range of an HDR image into the
+/// displayable `0.0..=1.0` range.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ToneMapping {
+    /// Values are clamped.
+    #[default]
+    None,
+
+    /// `x / (1 + x)`
+    Reinhard,
+
+    /// Krzysztof Narkowicz' fit of the ACES filmic curve.
+    Aces,
+}
+
+impl ToneMapping {
+    /// The value passed to the `tone_mapping` uniform of the HDR shader.
+    #[cfg(feature = "gl")]
+    pub(super) const fn as_shader_value(&self) -> i32 {
+        match self {
+            Self::None => 0,
+            Self::Reinhard => 1,
+            Self::Aces => 2,
+        }
+    }
+
+    /// Applies the exposure (in stops) and the tone mapping operator to a
+    /// linear color channel.
+    #[must_use]
+    pub fn apply(&self, value: f32, exposure: f32) -> f32 {
+        let x = value * exposure.exp2();
+        match self {
+            Self::None => x,
+            Self::Reinhard => x / (1.0 + x),
+            Self::Aces => ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0),
+        }
+    }
+}
+
+#[must_use]
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[must_use]
+pub fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl Color {
+    /// Converts a linear, straight alpha color to an sRGB encoded one.
+    #[must_use]
+    pub fn from_linear(rgba: [f32; 4]) -> Self {
+        let encode = |v: f32| (linear_to_srgb(v) * 255.0).round() as u8;
+        Self::new(
+            encode(rgba[0]),
+            encode(rgba[1]),
+            encode(rgba[2]),
+            (rgba[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+        )
+    }
+
+    #[must_use]
+    pub fn to_linear(&self) -> [f32; 4] {
+        let decode = |v: u8| srgb_to_linear(v as f32 / 255.0);
+        [
+            decode(self.red()),
+            decode(self.green()),
+            decode(self.blue()),
+            self.alpha() as f32 / 255.0,
+        ]
+    }
+}
@@ -1,12 +1,12 @@
 // Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
-use std::{fs::File, io::BufReader, path::Path};
+use std::{fs::File, io::{BufRead, BufReader, Cursor, Seek}, path::Path};
 
 use euclid::default::Size2D;
-use image::{ImageReader, RgbaImage};
+use image::{DynamicImage, ImageDecoder, ImageReader, Rgba, Rgba32FImage, RgbaImage};
 
-use crate::{ImageLoadError, ResourceId};
+use crate::{srgb_to_linear, ColorSpace, ImageLoadError, ResourceId, ResourceNamespace, ToneMapping};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color {
@@ -79,24 +79,292 @@ impl Color {
 pub struct Image {
     pub(super) size: Size2D<u32>,
     pub(super) id: ResourceId,
+    pub(super) exposure: f32,
+    pub(super) tone_mapping: ToneMapping,
+    pub(super) sampling: SamplingQuality,
+
+    /// The number of pixels per logical pixel, e.g. `2.0` for `@2x` files.
+    pub(super) scale: f32,
 }
 
 impl Image {
+    pub(super) const fn new(id: ResourceId, size: Size2D<u32>) -> Self {
+        Self {
+            size,
+            id,
+            exposure: 0.0,
+            tone_mapping: ToneMapping::None,
+            sampling: SamplingQuality::Trilinear,
+            scale: 1.0,
+        }
+    }
+
+    /// The size in pixels of the file.
     #[must_use]
     pub const fn size(&self) -> Size2D<u32> {
         self.size
     }
 
-    pub(super) fn load(path: &Path) -> Result<(RgbaImage, Size2D<u32>), ImageLoadError> {
+    /// The size at which the image is painted sharply, in logical pixels.
+    /// This is smaller than [`Self::size`] for `@2x` and `@3x` variants, see
+    /// [`Context::load_image`](crate::Context::load_image).
+    #[must_use]
+    pub fn logical_size(&self) -> Size2D<f32> {
+        self.size.cast::<f32>() / self.scale
+    }
+
+    /// Whether the image is stored as linear floating point, see
+    /// [`Context::load_hdr_image`](crate::Context::load_hdr_image).
+    #[must_use]
+    pub fn is_hdr(&self) -> bool {
+        self.id.namespace() == ResourceNamespace::HdrImage
+    }
+
+    /// Scales the samples by `2^exposure` before tone mapping. Only affects
+    /// HDR images.
+    #[must_use]
+    pub const fn with_exposure(mut self, exposure: f32) -> Self {
+        self.exposure = exposure;
+        self
+    }
+
+    /// Only affects HDR images.
+    #[must_use]
+    pub const fn with_tone_mapping(mut self, tone_mapping: ToneMapping) -> Self {
+        self.tone_mapping = tone_mapping;
+        self
+    }
+
+    /// Changes how the image is filtered when it is drawn at a different size.
+    #[must_use]
+    pub const fn with_sampling(mut self, sampling: SamplingQuality) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    pub(super) fn load(path: &Path, options: &ImageLoadOptions) -> Result<RgbaImage, ImageLoadError> {
         let reader = BufReader::new(File::open(path)?);
-        let img = ImageReader::new(reader)
+        Self::decode(ImageReader::new(reader), options)
+    }
+
+    pub(super) fn load_from_bytes(bytes: &[u8], options: &ImageLoadOptions) -> Result<RgbaImage, ImageLoadError> {
+        Self::decode(ImageReader::new(Cursor::new(bytes)), options)
+    }
+
+    fn decode<R: BufRead + Seek>(reader: ImageReader<R>, options: &ImageLoadOptions) -> Result<RgbaImage, ImageLoadError> {
+        let mut decoder = reader
                 .with_guessed_format()?
-                .decode()?
-                .to_rgba8();
-        let dimensions = img.dimensions();
-        let size = Size2D::from(dimensions);
-        Ok((img, size))
+                .into_decoder()?;
+
+        let orientation = decoder.orientation()?;
+        let icc = decoder.icc_profile()?;
+
+        let mut img = DynamicImage::from_decoder(decoder)?;
+        if options.apply_orientation {
+            img.apply_orientation(orientation);
+        }
+
+        let mut img = img.to_rgba8();
+        if let Some(color_space) = options.color_space {
+            color_space.convert_from_icc(&mut img, icc.as_deref());
+        }
+
+        Ok(img)
+    }
+
+    /// Loads an image into linear floating point. Floating point formats
+    /// (OpenEXR, Radiance HDR) are already linear, and integer formats
+    /// (e.g. 16-bit PNG) are assumed to be sRGB encoded.
+    pub(super) fn load_hdr(path: &Path) -> Result<Rgba32FImage, ImageLoadError> {
+        let reader = ImageReader::new(BufReader::new(File::open(path)?));
+        let mut decoder = reader
+                .with_guessed_format()?
+                .into_decoder()?;
+        let orientation = decoder.orientation()?;
+
+        let mut img = DynamicImage::from_decoder(decoder)?;
+        img.apply_orientation(orientation);
+
+        let is_linear = matches!(img, DynamicImage::ImageRgb32F(..) | DynamicImage::ImageRgba32F(..));
+        let mut img = img.to_rgba32f();
+        if !is_linear {
+            for pixel in img.pixels_mut() {
+                for channel in &mut pixel.0[..3] {
+                    *channel = srgb_to_linear(*channel);
+                }
+            }
+        }
+
+        Ok(img)
+    }
+}
+
+/// A hint for the filtering that is applied when an [`Image`] is scaled.
+/// Higher qualities are more expensive, especially in software.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SamplingQuality {
+    /// Point sampling, which is ideal for pixel art.
+    Nearest,
+
+    /// Bilinear filtering of the full-size image, which aliases when the
+    /// image is drawn at less than half its size.
+    Linear,
+
+    /// Bilinear filtering of the two nearest mipmap levels, blended together.
+    #[default]
+    Trilinear,
+
+    /// Like [`Self::Trilinear`], but multiple samples are taken along the
+    /// direction that is scaled down the most, keeping non-uniformly scaled
+    /// images sharp.
+    Anisotropic,
+}
+
+impl SamplingQuality {
+    /// Whether the lower mipmap levels are sampled.
+    #[cfg(any(feature = "gl", feature = "software"))]
+    pub(super) const fn uses_mipmaps(&self) -> bool {
+        matches!(self, Self::Trilinear | Self::Anisotropic)
+    }
+}
+
+/// Controls how the metadata of an image file is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageLoadOptions {
+    /// Rotate and flip the image according to its EXIF orientation.
+    pub apply_orientation: bool,
+
+    /// The color space to convert the pixels to, using the embedded ICC
+    /// profile. `None` keeps the pixel values as they are stored in the file.
+    pub color_space: Option<ColorSpace>,
+}
+
+impl ImageLoadOptions {
+    /// Loads the pixels exactly as they are stored in the file.
+    pub const RAW: Self = Self {
+        apply_orientation: false,
+        color_space: None,
+    };
+}
+
+impl Default for ImageLoadOptions {
+    fn default() -> Self {
+        Self {
+            apply_orientation: true,
+            color_space: Some(ColorSpace::default()),
+        }
+    }
+}
+
+/// An image that is updated every frame, e.g. from a video or camera source.
+///
+/// Two textures are kept: the front one is drawn, whilst the back one is
+/// written to by [`Context::update_streaming_image`](crate::Context::update_streaming_image).
+/// After an update the two are swapped, so that uploading never stalls on a
+/// texture that is still in use by the previous frame.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamingImage {
+    pub(super) images: [Image; 2],
+    pub(super) front: usize,
+}
+
+impl StreamingImage {
+    #[must_use]
+    pub const fn size(&self) -> Size2D<u32> {
+        self.images[0].size
+    }
+
+    /// The image containing the most recently uploaded frame.
+    #[must_use]
+    pub const fn current(&self) -> Image {
+        self.images[self.front]
     }
+
+    pub(super) const fn back(&self) -> Image {
+        self.images[1 - self.front]
+    }
+
+    pub(super) fn swap(&mut self) {
+        self.front = 1 - self.front;
+    }
+}
+
+/// The layout of the pixels passed to [`Context::create_image`](crate::Context::create_image).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 8-bit red, green, blue and alpha, in that order.
+    Rgba8,
+
+    /// 8-bit blue, green, red and alpha, in that order.
+    Bgra8,
+
+    /// 8-bit red, green and blue, in that order. The image will be opaque.
+    Rgb8,
+
+    /// A single 8-bit luminance channel. The image will be opaque.
+    Gray8,
+
+    /// 8-bit luminance and alpha, in that order.
+    GrayAlpha8,
+}
+
+impl PixelFormat {
+    pub(super) const ALL: [Self; 5] = [Self::Rgba8, Self::Bgra8, Self::Rgb8, Self::Gray8, Self::GrayAlpha8];
+
+    #[must_use]
+    pub const fn bytes_per_pixel(&self) -> usize {
+        match self {
+            Self::Rgba8 | Self::Bgra8 => 4,
+            Self::Rgb8 => 3,
+            Self::Gray8 => 1,
+            Self::GrayAlpha8 => 2,
+        }
+    }
+
+    pub(super) fn to_rgba(self, size: Size2D<u32>, pixels: &[u8]) -> Result<RgbaImage, ImageLoadError> {
+        let expected = size.width as usize * size.height as usize * self.bytes_per_pixel();
+        if pixels.len() != expected {
+            return Err(ImageLoadError::InvalidBufferSize {
+                expected,
+                actual: pixels.len(),
+            });
+        }
+
+        if self == Self::Rgba8 {
+            return Ok(RgbaImage::from_raw(size.width, size.height, pixels.to_vec()).unwrap());
+        }
+
+        let mut pixels = pixels.chunks_exact(self.bytes_per_pixel());
+        Ok(RgbaImage::from_fn(size.width, size.height, |_, _| {
+            let p = pixels.next().unwrap();
+            match self {
+                Self::Rgba8 => Rgba([p[0], p[1], p[2], p[3]]),
+                Self::Bgra8 => Rgba([p[2], p[1], p[0], p[3]]),
+                Self::Rgb8 => Rgba([p[0], p[1], p[2], 0xFF]),
+                Self::Gray8 => Rgba([p[0], p[0], p[0], 0xFF]),
+                Self::GrayAlpha8 => Rgba([p[0], p[0], p[0], p[1]]),
+            }
+        }))
+    }
+}
+
+/// An encoded image that is compiled into the executable, see [`include_image!`](crate::include_image).
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddedImage {
+    pub name: &'static str,
+    pub bytes: &'static [u8],
+}
+
+/// Embeds the image file at the given path (relative to the current file) into
+/// the executable, producing an [`EmbeddedImage`].
+#[macro_export]
+macro_rules! include_image {
+    ($path:literal) => {
+        $crate::EmbeddedImage {
+            name: $path,
+            bytes: include_bytes!($path),
+        }
+    };
 }
 
 #[derive(Debug, Clone)]
@@ -116,3 +384,49 @@ impl From<Image> for Material {
         Self::Image(value)
     }
 }
+
+impl From<&StreamingImage> for Material {
+    fn from(value: &StreamingImage) -> Self {
+        Self::Image(value.current())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_rgba_expands_every_format() {
+        let size = Size2D::new(2, 1);
+        let cases: [(PixelFormat, &[u8]); 5] = [
+            (PixelFormat::Rgba8, &[1, 2, 3, 4, 5, 6, 7, 8]),
+            (PixelFormat::Bgra8, &[3, 2, 1, 4, 7, 6, 5, 8]),
+            (PixelFormat::Rgb8, &[1, 2, 3, 5, 6, 7]),
+            (PixelFormat::Gray8, &[1, 5]),
+            (PixelFormat::GrayAlpha8, &[1, 4, 5, 8]),
+        ];
+
+        for (format, pixels) in cases {
+            let image = format.to_rgba(size, pixels).unwrap();
+            let expected: [u8; 8] = match format {
+                PixelFormat::Rgba8 | PixelFormat::Bgra8 => [1, 2, 3, 4, 5, 6, 7, 8],
+                PixelFormat::Rgb8 => [1, 2, 3, 0xFF, 5, 6, 7, 0xFF],
+                PixelFormat::Gray8 => [1, 1, 1, 0xFF, 5, 5, 5, 0xFF],
+                PixelFormat::GrayAlpha8 => [1, 1, 1, 4, 5, 5, 5, 8],
+            };
+            assert_eq!(image.as_raw().as_slice(), &expected, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn to_rgba_rejects_wrong_length() {
+        for format in PixelFormat::ALL {
+            let pixels = vec![0; 4 * format.bytes_per_pixel() - 1];
+            let result = format.to_rgba(Size2D::new(2, 2), &pixels);
+            assert!(matches!(
+                result,
+                Err(ImageLoadError::InvalidBufferSize { expected, actual }) if expected == actual + 1
+            ), "{format:?}");
+        }
+    }
+}
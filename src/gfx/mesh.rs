@@ -1,7 +1,7 @@
 // Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
-use glium::{glutin::surface::WindowSurface, index::{IndicesSource, NoIndices, PrimitiveType}, uniforms::Uniforms, Display, Frame, IndexBuffer, Program, Surface, VertexBuffer};
+use glium::{Blend, DrawParameters, glutin::surface::WindowSurface, index::{IndicesSource, NoIndices, PrimitiveType}, uniforms::Uniforms, Display, IndexBuffer, Program, Surface, VertexBuffer};
 
 use crate::Vertex;
 
@@ -31,17 +31,20 @@ impl Mesh {
 
         Self {
             vbo: MeshVertexBuffer::Normal(vertex_buffer),
-            ibo: MeshIndices::Buffer(indices),
+            ibo: MeshIndices::Buffer(Box::new(indices)),
         }
     }
 
-    pub fn new_textured_square(display: &Display<WindowSurface>) -> Self {
+    /// When `flip_y` is set, the first row of the texture is at the top, which
+    /// is the case for textures that couldn't be uploaded bottom-up.
+    pub fn new_textured_square(display: &Display<WindowSurface>, flip_y: bool) -> Self {
         let val = 0.5;
+        let (top, bottom) = if flip_y { (0.0, 1.0) } else { (1.0, 0.0) };
         let vertices = [
-            TexturedVertex { position: [ val,  val], tex_coords: [ 1.0,  1.0] },
-            TexturedVertex { position: [ val, -val], tex_coords: [ 1.0, -1.0] },
-            TexturedVertex { position: [-val, -val], tex_coords: [-1.0, -1.0] },
-            TexturedVertex { position: [-val,  val], tex_coords: [-1.0,  1.0] },
+            TexturedVertex { position: [ val,  val], tex_coords: [1.0, top] },
+            TexturedVertex { position: [ val, -val], tex_coords: [1.0, bottom] },
+            TexturedVertex { position: [-val, -val], tex_coords: [0.0, bottom] },
+            TexturedVertex { position: [-val,  val], tex_coords: [0.0, top] },
         ];
         let indices = &[
             0, 1, 2,
@@ -53,19 +56,25 @@ impl Mesh {
 
         Self {
             vbo: MeshVertexBuffer::Textured(vertex_buffer),
-            ibo: MeshIndices::Buffer(indices),
+            ibo: MeshIndices::Buffer(Box::new(indices)),
         }
     }
 
-    pub fn draw<U>(&self, target: &mut Frame, program: &Program, uniforms: &U)
-            where U: Uniforms {
+    /// Draws the mesh, blending it over the target with straight alpha.
+    pub fn draw<S, U>(&self, target: &mut S, program: &Program, uniforms: &U)
+            where S: Surface, U: Uniforms {
+        let parameters = DrawParameters {
+            blend: Blend::alpha_blending(),
+            ..Default::default()
+        };
+
         match &self.vbo {
             MeshVertexBuffer::Normal(vbo) => {
-                target.draw(vbo, &self.ibo, &program, uniforms, &Default::default()).unwrap();
+                target.draw(vbo, &self.ibo, program, uniforms, &parameters).unwrap();
             }
 
             MeshVertexBuffer::Textured(vbo) => {
-                target.draw(vbo, &self.ibo, &program, uniforms, &Default::default()).unwrap();
+                target.draw(vbo, &self.ibo, program, uniforms, &parameters).unwrap();
             }
         }
     }
@@ -77,7 +86,7 @@ pub enum MeshVertexBuffer {
 }
 
 pub enum MeshIndices {
-    Buffer(IndexBuffer<u16>),
+    Buffer(Box<IndexBuffer<u16>>),
     NoIndicies(NoIndices),
 }
 
@@ -90,7 +99,7 @@ impl MeshIndices {
 impl<'a> From<&'a MeshIndices> for IndicesSource<'a> {
     fn from(indices: &'a MeshIndices) -> Self {
         match indices {
-            MeshIndices::Buffer(buf) => buf.into(),
+            MeshIndices::Buffer(buf) => buf.as_ref().into(),
             MeshIndices::NoIndicies(ibo) => ibo.into(),
         }
     }
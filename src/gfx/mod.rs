@@ -1,23 +1,69 @@
 // Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
+mod animated;
+#[cfg(any(feature = "gl", feature = "software"))]
+mod atlas;
+#[cfg(feature = "window")]
+mod builder;
+mod color_space;
+mod compressed;
 mod context;
+#[cfg(any(feature = "svg-export", feature = "pdf-export"))]
+mod export;
+mod hdr;
 mod material;
+#[cfg(feature = "gl")]
 mod mesh;
+mod nine_slice;
 mod painter;
+#[cfg(feature = "window")]
+mod retained;
+#[cfg(feature = "gl")]
 mod shader;
+mod sprite;
+#[cfg(feature = "svg")]
+mod svg;
+#[cfg(feature = "gl")]
 mod vertex;
 
+#[cfg(feature = "gl")]
 mod gl;
+#[cfg(feature = "software")]
 mod soft;
 
 pub use self::{
+    animated::*,
+    color_space::ColorSpace,
+    compressed::{BlockFormat, CompressedImage},
     context::*,
+    hdr::*,
     material::*,
-    mesh::Mesh,
+    nine_slice::{NinePatch, NineSlice, SliceFill},
     painter::Painter,
-    shader::ShaderPrograms,
-    vertex::*,
+    sprite::{AnimationDirection, AnimationTag, SpriteFrame, SpriteGrid, SpriteSheet, DEFAULT_FRAME_DURATION},
+};
 
+#[cfg(feature = "gl")]
+pub use self::{
     gl::GLContext,
+    mesh::Mesh,
+    shader::ShaderPrograms,
+    vertex::*,
 };
+
+#[cfg(feature = "software")]
+pub use self::soft::{PixelBuffer, PixelTarget, TargetFormat};
+
+#[cfg(feature = "window")]
+pub use self::builder::{Backend, ContextBuilder};
+
+#[cfg(feature = "svg")]
+pub use self::svg::SvgDocument;
+
+#[cfg(feature = "pdf-export")]
+pub use self::export::{PdfContext, PdfOutput};
+#[cfg(feature = "svg-export")]
+pub use self::export::{SvgContext, SvgOutput};
+#[cfg(feature = "terminal")]
+pub use self::soft::{TerminalContext, TerminalMode};
@@ -0,0 +1,249 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use euclid::default::{Point2D, Rect, SideOffsets2D, Size2D};
+use image::{imageops, Rgba, RgbaImage};
+
+use crate::{Image, ImageLoadError};
+
+/// How the edges or the center of a nine-slice image fill their area.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SliceFill {
+    #[default]
+    Stretch,
+
+    /// Repeats the slice at its original size, cutting off the last one.
+    Tile,
+}
+
+/// Divides an image into a 3×3 grid. The corners are never scaled, the edges
+/// are scaled along one axis and the center along both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NineSlice {
+    /// The size of the borders, in pixels of the image.
+    pub insets: SideOffsets2D<u32>,
+    pub edges: SliceFill,
+    pub center: SliceFill,
+}
+
+impl NineSlice {
+    #[must_use]
+    pub const fn new(insets: SideOffsets2D<u32>) -> Self {
+        Self {
+            insets,
+            edges: SliceFill::Stretch,
+            center: SliceFill::Stretch,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_edges(self, edges: SliceFill) -> Self {
+        Self { edges, ..self }
+    }
+
+    #[must_use]
+    pub const fn with_center(self, center: SliceFill) -> Self {
+        Self { center, ..self }
+    }
+
+    /// Splits `rect` and the image of `image_size` into the nine parts,
+    /// yielding the source rectangle in pixels, the destination rectangle
+    /// and how the part should be filled horizontally and vertically. Empty
+    /// parts are skipped.
+    pub(super) fn parts(&self, image_size: Size2D<u32>, rect: Rect<f32>) -> Vec<(Rect<u32>, Rect<f32>, [SliceFill; 2])> {
+        let insets = self.insets;
+        let columns = axis(image_size.width, insets.left, insets.right, rect.min_x(), rect.width());
+        let rows = axis(image_size.height, insets.top, insets.bottom, rect.min_y(), rect.height());
+
+        let mut parts = Vec::with_capacity(9);
+        for (row, &(src_y, src_height, dst_y, dst_height)) in rows.iter().enumerate() {
+            for (column, &(src_x, src_width, dst_x, dst_width)) in columns.iter().enumerate() {
+                if src_width == 0 || src_height == 0 || dst_width <= 0.0 || dst_height <= 0.0 {
+                    continue;
+                }
+
+                let fill = match (column, row) {
+                    (1, 1) => [self.center, self.center],
+                    (1, _) => [self.edges, SliceFill::Stretch],
+                    (_, 1) => [SliceFill::Stretch, self.edges],
+                    _ => [SliceFill::Stretch, SliceFill::Stretch],
+                };
+
+                parts.push((
+                    Rect::new(Point2D::new(src_x, src_y), Size2D::new(src_width, src_height)),
+                    Rect::new(Point2D::new(dst_x, dst_y), Size2D::new(dst_width, dst_height)),
+                    fill,
+                ));
+            }
+        }
+
+        parts
+    }
+}
+
+impl From<SideOffsets2D<u32>> for NineSlice {
+    fn from(value: SideOffsets2D<u32>) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Splits an axis into the (source start, source length, destination start,
+/// destination length) of the three slices. When the destination is smaller
+/// than both insets, the insets are shrunk proportionally.
+fn axis(size: u32, start_inset: u32, end_inset: u32, dst_start: f32, dst_length: f32) -> [(u32, u32, f32, f32); 3] {
+    let start_inset = start_inset.min(size);
+    let end_inset = end_inset.min(size - start_inset);
+    let middle = size - start_inset - end_inset;
+
+    let insets = (start_inset + end_inset) as f32;
+    let scale = if insets > dst_length { dst_length / insets } else { 1.0 };
+    let dst_start_inset = start_inset as f32 * scale;
+    let dst_end_inset = end_inset as f32 * scale;
+    let dst_middle = dst_length - dst_start_inset - dst_end_inset;
+
+    [
+        (0, start_inset, dst_start, dst_start_inset),
+        (start_inset, middle, dst_start + dst_start_inset, dst_middle),
+        (start_inset + middle, end_inset, dst_start + dst_start_inset + dst_middle, dst_end_inset),
+    ]
+}
+
+/// An image loaded from an Android `.9.png` file.
+#[derive(Debug, Clone, Copy)]
+pub struct NinePatch {
+    pub image: Image,
+    pub slice: NineSlice,
+
+    /// The area around the content, as marked by the bottom and right
+    /// border, if present.
+    pub padding: Option<SideOffsets2D<u32>>,
+}
+
+/// The contents of a `.9.png` file, with the one pixel border removed.
+pub(super) struct DecodedNinePatch {
+    pub image: RgbaImage,
+    pub insets: SideOffsets2D<u32>,
+    pub padding: Option<SideOffsets2D<u32>>,
+}
+
+impl DecodedNinePatch {
+    /// Reads the markers from the border. Only the first and last marker of
+    /// every side are used, multiple stretchable regions aren't supported.
+    pub fn decode(img: RgbaImage) -> Result<Self, ImageLoadError> {
+        let (width, height) = img.dimensions();
+        let inner = Size2D::new(width.saturating_sub(2), height.saturating_sub(2));
+        if inner.is_empty() {
+            return Err(ImageLoadError::InvalidNinePatch("image has no pixels inside its border"));
+        }
+
+        let is_marker = |x: u32, y: u32| *img.get_pixel(x, y) == Rgba([0, 0, 0, 255]);
+        let span = |markers: Vec<bool>| {
+            let first = markers.iter().position(|marked| *marked)?;
+            let last = markers.iter().rposition(|marked| *marked)?;
+            Some((first as u32, (markers.len() - 1 - last) as u32))
+        };
+
+        let top = span((1..width - 1).map(|x| is_marker(x, 0)).collect());
+        let left = span((1..height - 1).map(|y| is_marker(0, y)).collect());
+        let bottom = span((1..width - 1).map(|x| is_marker(x, height - 1)).collect());
+        let right = span((1..height - 1).map(|y| is_marker(width - 1, y)).collect());
+
+        let (Some((left_inset, right_inset)), Some((top_inset, bottom_inset))) = (top, left) else {
+            return Err(ImageLoadError::InvalidNinePatch("missing stretch markers"));
+        };
+
+        let padding = match (bottom, right) {
+            (Some((left, right)), Some((top, bottom))) => Some(SideOffsets2D::new(top, right, bottom, left)),
+            _ => None,
+        };
+
+        Ok(Self {
+            image: imageops::crop_imm(&img, 1, 1, inner.width, inner.height).to_image(),
+            insets: SideOffsets2D::new(top_inset, right_inset, bottom_inset, left_inset),
+            padding,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKER: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+    /// A 6×5 image with 4×3 pixels of content, stretching the second and third
+    /// column and the second row.
+    fn nine_patch(padding: bool) -> RgbaImage {
+        let mut img = RgbaImage::from_pixel(6, 5, Rgba([255; 4]));
+        for x in 0..6 {
+            img.put_pixel(x, 0, Rgba([0; 4]));
+            img.put_pixel(x, 4, Rgba([0; 4]));
+        }
+        for y in 0..5 {
+            img.put_pixel(0, y, Rgba([0; 4]));
+            img.put_pixel(5, y, Rgba([0; 4]));
+        }
+
+        img.put_pixel(2, 0, MARKER);
+        img.put_pixel(3, 0, MARKER);
+        img.put_pixel(0, 2, MARKER);
+
+        if padding {
+            img.put_pixel(2, 4, MARKER);
+            img.put_pixel(5, 1, MARKER);
+            img.put_pixel(5, 2, MARKER);
+        }
+
+        img
+    }
+
+    #[test]
+    fn decode_markers() {
+        let decoded = DecodedNinePatch::decode(nine_patch(false)).unwrap();
+        assert_eq!(decoded.image.dimensions(), (4, 3));
+        assert!(decoded.image.pixels().all(|pixel| *pixel == Rgba([255; 4])));
+        assert_eq!(decoded.insets, SideOffsets2D::new(1, 1, 1, 1));
+        assert_eq!(decoded.padding, None);
+
+        let decoded = DecodedNinePatch::decode(nine_patch(true)).unwrap();
+        assert_eq!(decoded.padding, Some(SideOffsets2D::new(0, 2, 1, 1)));
+    }
+
+    #[test]
+    fn reject_invalid_nine_patches() {
+        for (width, height) in [(0, 0), (2, 8), (8, 2)] {
+            let result = DecodedNinePatch::decode(RgbaImage::new(width, height));
+            assert!(matches!(result, Err(ImageLoadError::InvalidNinePatch(_))), "{width}x{height}");
+        }
+
+        let result = DecodedNinePatch::decode(RgbaImage::new(8, 8));
+        assert!(matches!(result, Err(ImageLoadError::InvalidNinePatch("missing stretch markers"))));
+    }
+
+    #[test]
+    fn parts_keep_corners() {
+        let slice = NineSlice::new(SideOffsets2D::new(2, 2, 2, 2)).with_center(SliceFill::Tile);
+        let parts = slice.parts(Size2D::new(8, 8), Rect::new(Point2D::new(10.0, 10.0), Size2D::new(20.0, 12.0)));
+
+        assert_eq!(parts.len(), 9);
+        let (source, destination, fill) = parts[0];
+        assert_eq!(source, Rect::new(Point2D::new(0, 0), Size2D::new(2, 2)));
+        assert_eq!(destination, Rect::new(Point2D::new(10.0, 10.0), Size2D::new(2.0, 2.0)));
+        assert_eq!(fill, [SliceFill::Stretch; 2]);
+
+        let (source, destination, fill) = parts[4];
+        assert_eq!(source, Rect::new(Point2D::new(2, 2), Size2D::new(4, 4)));
+        assert_eq!(destination, Rect::new(Point2D::new(12.0, 12.0), Size2D::new(16.0, 8.0)));
+        assert_eq!(fill, [SliceFill::Tile; 2]);
+    }
+
+    #[test]
+    fn parts_shrink_insets_proportionally() {
+        let slice = NineSlice::new(SideOffsets2D::new(0, 6, 0, 2));
+        let parts = slice.parts(Size2D::new(10, 1), Rect::from_size(Size2D::new(4.0, 1.0)));
+
+        // The center is skipped, as it has no room left.
+        let widths: Vec<_> = parts.iter().map(|(_, destination, _)| destination.width()).collect();
+        assert_eq!(widths, [1.0, 3.0]);
+    }
+}
@@ -1,19 +1,138 @@
 // Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
-use euclid::default::Rect;
-use crate::Material;
+use std::time::Duration;
+
+use euclid::default::{Point2D, Rect, Size2D};
+use crate::{AnimatedImage, Image, Material, NineSlice, SliceFill, SpriteSheet};
+
+#[cfg(feature = "svg")]
+use crate::SvgDocument;
 
 pub trait PainterImplementation {
     fn paint_filled_rect(&mut self, rect: Rect<f32>, brush: Material);
+
+    /// Paints the part of `image` at `source`, in normalized coordinates with
+    /// the origin at the top left, stretched over `rect`.
+    fn paint_image_region(&mut self, rect: Rect<f32>, image: Image, source: Rect<f32>);
+
+    /// Renders the document at the resolution of the target.
+    #[cfg(feature = "svg")]
+    fn paint_svg(&mut self, rect: Rect<f32>, svg: &SvgDocument);
 }
 
+/// Paints in logical pixels, see [`Context::scale_factor`](crate::Context::scale_factor).
 pub struct Painter<'pi> {
     pub(super) inner: &'pi mut dyn PainterImplementation,
+    pub(super) scale_factor: f32,
 }
 
 impl<'pi> Painter<'pi> {
+    /// The number of physical pixels per logical pixel of the target.
+    #[must_use]
+    pub const fn scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    /// Rounds the point to the nearest physical pixel.
+    #[must_use]
+    pub fn snap_point(&self, point: Point2D<f32>) -> Point2D<f32> {
+        (point * self.scale_factor).round() / self.scale_factor
+    }
+
+    /// Rounds the edges of the rectangle to the nearest physical pixels, so
+    /// that they are painted sharply instead of blending with their
+    /// neighbours.
+    #[must_use]
+    pub fn snap_rect(&self, rect: Rect<f32>) -> Rect<f32> {
+        let min = self.snap_point(rect.min());
+        let max = self.snap_point(rect.max());
+        Rect::new(min, (max - min).to_size())
+    }
+
     pub fn paint_filled_rect(&mut self, rect: Rect<f32>, brush: impl Into<Material>) {
         self.inner.paint_filled_rect(rect, brush.into())
     }
+
+    /// Paints the frame of the animation that is visible at `time`, relative
+    /// to the start of the animation.
+    pub fn paint_animated_image(&mut self, rect: Rect<f32>, image: &AnimatedImage, time: Duration) {
+        self.paint_filled_rect(rect, image.frame_at(time))
+    }
+
+    /// Paints a frame of the sprite sheet, see [`SpriteSheet::frame_index`]
+    /// and [`SpriteSheet::frame_at`]. The `rect` covers the untrimmed frame.
+    pub fn draw_sprite(&mut self, sheet: &SpriteSheet, frame: usize, rect: Rect<f32>) {
+        // Only grids can have empty frames, as parsing rejects them.
+        let Some(frame) = sheet.frame(frame).filter(|frame| !frame.source_size.is_empty()) else {
+            return;
+        };
+
+        let (frame_rect, offset) = (frame.rect.cast::<f32>(), frame.offset.cast::<f32>());
+        let x_scale = rect.width() / frame.source_size.width as f32;
+        let y_scale = rect.height() / frame.source_size.height as f32;
+
+        let rect = Rect::new(
+            Point2D::new(rect.min_x() + offset.x * x_scale, rect.min_y() + offset.y * y_scale),
+            Size2D::new(frame_rect.width() * x_scale, frame_rect.height() * y_scale),
+        );
+
+        let image_size = sheet.image.size().cast::<f32>();
+        let source = frame_rect.scale(1.0 / image_size.width, 1.0 / image_size.height);
+
+        self.inner.paint_image_region(rect, sheet.image, source);
+    }
+
+    /// Paints the document stretched over `rect`. It is rendered again for
+    /// every call; use [`Context::rasterize_svg`](crate::Context::rasterize_svg)
+    /// to cache it as an [`Image`].
+    #[cfg(feature = "svg")]
+    pub fn draw_svg(&mut self, svg: &SvgDocument, rect: Rect<f32>) {
+        self.inner.paint_svg(rect, svg)
+    }
+
+    /// Paints `image` over `rect`, keeping the corners at their original
+    /// size, see [`NineSlice`]. The insets are in pixels of the image, which
+    /// are smaller than logical pixels for `@2x` images.
+    pub fn draw_nine_slice(&mut self, image: Image, insets: impl Into<NineSlice>, rect: Rect<f32>) {
+        if image.size().is_empty() {
+            return;
+        }
+
+        let image_size = image.size().cast::<f32>();
+        let scale = image.scale;
+
+        for (source, destination, [fill_x, fill_y]) in insets.into().parts(image.size(), rect.scale(scale, scale)) {
+            let source = source.cast::<f32>();
+            let destination = destination.scale(1.0 / scale, 1.0 / scale);
+            let tile_width = if fill_x == SliceFill::Tile { source.width() / scale } else { destination.width() };
+            let tile_height = if fill_y == SliceFill::Tile { source.height() / scale } else { destination.height() };
+
+            let mut y = destination.min_y();
+            while y < destination.max_y() {
+                let height = tile_height.min(destination.max_y() - y);
+
+                let mut x = destination.min_x();
+                while x < destination.max_x() {
+                    let width = tile_width.min(destination.max_x() - x);
+
+                    // Cut off the part of the source that doesn't fit.
+                    let source = Rect::new(
+                        source.origin,
+                        Size2D::new(source.width() * width / tile_width, source.height() * height / tile_height),
+                    );
+
+                    self.inner.paint_image_region(
+                        Rect::new(Point2D::new(x, y), Size2D::new(width, height)),
+                        image,
+                        source.scale(1.0 / image_size.width, 1.0 / image_size.height),
+                    );
+
+                    x += width;
+                }
+
+                y += height;
+            }
+        }
+    }
 }
@@ -0,0 +1,196 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! [`Context::switch_backend`](crate::Context::switch_backend) recreates every
+//! image on the new backend, which gives them new IDs. The context therefore
+//! hands out its own IDs, and translates them to the ones of the backend.
+
+use std::{collections::HashMap, rc::Rc};
+
+use euclid::default::Rect;
+use image::{imageops, RgbaImage};
+use winit::window::{Window, WindowId};
+
+use crate::{ContextBuilder, ContextImplementation, Image, ImageLoadError, Material, ResourceId};
+
+#[cfg(feature = "svg")]
+use crate::SvgDocument;
+
+use super::{context::ImageSource, painter::PainterImplementation};
+
+impl ImageSource {
+    /// Replaces a file or compressed data by the decoded pixels, so that
+    /// updates can be applied to them.
+    fn decode(&mut self) -> Result<(), ImageLoadError> {
+        let decoded = match self {
+            Self::Path(path, options) => Image::load(path, options)?,
+            Self::Compressed(image) => image.decode()?,
+            Self::Pixels(_) => return Ok(()),
+            Self::Hdr(_) => return Err(ImageLoadError::ImageNotUpdatable),
+        };
+
+        *self = Self::Pixels(decoded);
+        Ok(())
+    }
+}
+
+/// The windows of a [`Context`](crate::Context), with the images needed to
+/// move to another backend when switching is enabled.
+pub(super) struct Retained {
+    /// The open windows, starting with the one the context was created with.
+    pub windows: Vec<Rc<Window>>,
+    pub builder: ContextBuilder,
+
+    /// The images by the ID the context handed out, with the ID of the backend.
+    /// Only kept with [`ContextBuilder::with_backend_switching`].
+    images: HashMap<ResourceId, (ResourceId, ImageSource)>,
+    id_counter: usize,
+}
+
+impl Retained {
+    pub fn new(window: Rc<Window>, builder: ContextBuilder) -> Self {
+        Self {
+            windows: vec![window],
+            builder,
+            images: HashMap::new(),
+            id_counter: 0,
+        }
+    }
+
+    pub fn window(&self, id: WindowId) -> Option<&Rc<Window>> {
+        self.windows.iter().find(|window| window.id() == id)
+    }
+
+    /// Whether the images are kept, and their IDs translated.
+    pub fn keeps_images(&self) -> bool {
+        self.builder.backend_switching
+    }
+
+    /// Keeps the source of an image that was just created by the backend,
+    /// and returns the handle for the application.
+    pub fn insert(&mut self, image: Image, source: ImageSource) -> Image {
+        let id = ResourceId::new(image.id.namespace(), self.id_counter);
+        self.id_counter += 1;
+
+        self.images.insert(id, (image.id, source));
+        Image { id, ..image }
+    }
+
+    /// The handle as the backend knows it, or `None` for images that were
+    /// unloaded or come from another context.
+    pub fn resolve(&self, image: Image) -> Option<Image> {
+        if !self.keeps_images() {
+            return Some(image);
+        }
+
+        self.images.get(&image.id).map(|(id, _)| Image { id: *id, ..image })
+    }
+
+    pub fn update(&mut self, image: Image, region: Rect<u32>, pixels: &RgbaImage) -> Result<(), ImageLoadError> {
+        let (_, source) = self.images.get_mut(&image.id).ok_or(ImageLoadError::UnknownImage)?;
+
+        source.decode()?;
+        if let ImageSource::Pixels(retained) = source {
+            imageops::replace(retained, pixels, region.min_x() as i64, region.min_y() as i64);
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, image: Image) {
+        self.images.remove(&image.id);
+    }
+
+    /// Creates all images on `inner`. The IDs are only replaced when all of
+    /// them succeed, so the old backend stays usable otherwise.
+    pub fn restore(&mut self, inner: &mut dyn ContextImplementation) -> Result<(), ImageLoadError> {
+        let restored = self.images.iter()
+            .map(|(id, (_, source))| Ok((*id, source.clone().create(inner)?.id)))
+            .collect::<Result<Vec<_>, ImageLoadError>>()?;
+
+        for (id, backend_id) in restored {
+            self.images.get_mut(&id).unwrap().0 = backend_id;
+        }
+        Ok(())
+    }
+}
+
+/// Translates the images that are painted to the IDs of the backend.
+pub(super) struct RetainedPainter<'p> {
+    pub inner: &'p mut dyn PainterImplementation,
+    pub retained: &'p Retained,
+}
+
+impl PainterImplementation for RetainedPainter<'_> {
+    fn paint_filled_rect(&mut self, rect: Rect<f32>, brush: Material) {
+        let brush = match brush {
+            Material::Image(image) => match self.retained.resolve(image) {
+                Some(image) => Material::Image(image),
+                None => return,
+            },
+            brush => brush,
+        };
+
+        self.inner.paint_filled_rect(rect, brush);
+    }
+
+    fn paint_image_region(&mut self, rect: Rect<f32>, image: Image, source: Rect<f32>) {
+        if let Some(image) = self.retained.resolve(image) {
+            self.inner.paint_image_region(rect, image, source);
+        }
+    }
+
+    #[cfg(feature = "svg")]
+    fn paint_svg(&mut self, rect: Rect<f32>, svg: &SvgDocument) {
+        self.inner.paint_svg(rect, svg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::default::Size2D;
+
+    use crate::ResourceNamespace;
+
+    use super::*;
+
+    fn retained(backend_switching: bool) -> Retained {
+        Retained {
+            windows: Vec::new(),
+            builder: ContextBuilder::new().with_backend_switching(backend_switching),
+            images: HashMap::new(),
+            id_counter: 0,
+        }
+    }
+
+    fn image(id: usize) -> Image {
+        Image::new(ResourceId::new(ResourceNamespace::Image, id), Size2D::new(2, 2))
+    }
+
+    #[test]
+    fn ids_are_translated_when_switching() {
+        let mut retained = retained(true);
+        let first = retained.insert(image(7), ImageSource::Pixels(RgbaImage::new(2, 2)));
+        let second = retained.insert(image(3), ImageSource::Pixels(RgbaImage::new(2, 2)));
+
+        assert_eq!(retained.resolve(first).map(|image| image.id), Some(image(7).id));
+        assert_eq!(retained.resolve(second).map(|image| image.id), Some(image(3).id));
+    }
+
+    #[test]
+    fn ids_are_kept_without_switching() {
+        let retained = retained(false);
+        assert!(!retained.keeps_images());
+        assert_eq!(retained.resolve(image(7)).map(|image| image.id), Some(image(7).id));
+    }
+
+    #[test]
+    fn unloaded_images_are_not_resolved() {
+        let mut retained = retained(true);
+        let image = retained.insert(image(7), ImageSource::Pixels(RgbaImage::new(2, 2)));
+        retained.remove(image);
+
+        assert!(retained.resolve(image).is_none());
+        let result = retained.update(image, Rect::from_size(Size2D::new(1, 1)), &RgbaImage::new(1, 1));
+        assert!(matches!(result, Err(ImageLoadError::UnknownImage)));
+    }
+}
@@ -7,7 +7,9 @@ const SOLID_COLOR_VERTEX_SHADER: &str = include_str!("../../res/solid_color_vert
 const SOLID_COLOR_FRAGMENT_SHADER: &str = include_str!("../../res/solid_color_fragment.glsl");
 
 const TEXTURED_VERTEX_SHADER: &str = include_str!("../../res/textured_vertex.glsl");
-const TEXTURED_FRAGMENT_SHADER: &str = include_str!("../../res/solid_color_fragment.glsl");
+const TEXTURED_FRAGMENT_SHADER: &str = include_str!("../../res/textured_fragment.glsl");
+
+const HDR_FRAGMENT_SHADER: &str = include_str!("../../res/hdr_fragment.glsl");
 
 pub struct ShaderPrograms;
 
@@ -19,4 +21,8 @@ impl ShaderPrograms {
     pub fn create_textured(display: &Display<WindowSurface>) -> Program {
         Program::from_source(display, TEXTURED_VERTEX_SHADER, TEXTURED_FRAGMENT_SHADER, None).unwrap()
     }
+
+    pub fn create_hdr(display: &Display<WindowSurface>) -> Program {
+        Program::from_source(display, TEXTURED_VERTEX_SHADER, HDR_FRAGMENT_SHADER, None).unwrap()
+    }
 }
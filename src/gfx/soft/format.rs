@@ -0,0 +1,337 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use crate::{srgb_to_linear, Color};
+
+use super::{simd::{self, scalar::swap_pixel}, tile::TILE_SIZE};
+
+/// The number of pixels that are converted at once on the stack, instead of
+/// allocating per span. Spans are at most a tile wide.
+const CHUNK: usize = TILE_SIZE;
+
+/// The layout of the pixels that the software renderer paints into. The
+/// 8-bit formats hold sRGB encoded colors, [`Self::RgbaF32`] holds linear
+/// ones. Colors with an alpha channel are premultiplied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetFormat {
+    /// 8-bit blue, green, red and alpha, in that order, i.e. `0xAARRGGBB` as
+    /// a little-endian `u32`.
+    Bgra8,
+
+    /// 8-bit red, green, blue and alpha, in that order.
+    Rgba8,
+
+    /// A little-endian `u16` with 5 bits of red in the highest bits, 6 bits
+    /// of green and 5 bits of blue. The pixels are always opaque.
+    Rgb565,
+
+    /// Only an 8-bit alpha channel, e.g. for masks.
+    A8,
+
+    /// 32-bit floating point red, green, blue and alpha, in that order and
+    /// in native endianness.
+    RgbaF32,
+}
+
+impl TargetFormat {
+    #[must_use]
+    pub const fn bytes_per_pixel(&self) -> usize {
+        match self {
+            Self::Bgra8 | Self::Rgba8 => 4,
+            Self::Rgb565 => 2,
+            Self::A8 => 1,
+            Self::RgbaF32 => 16,
+        }
+    }
+
+    /// Fills the pixels with a packed, premultiplied color, see [`simd`].
+    pub(super) fn fill(self, pixels: &mut [u8], color: u32) {
+        if color >> 24 != 0xFF {
+            let colors = [color; CHUNK];
+            for pixels in pixels.chunks_mut(CHUNK * self.bytes_per_pixel()) {
+                self.blend(pixels, &colors);
+            }
+            return;
+        }
+
+        match self {
+            Self::Bgra8 => with_packed(pixels, |span| simd::fill(span, swap_pixel(color))),
+            Self::Rgba8 => with_packed(pixels, |span| simd::fill(span, color)),
+            Self::Rgb565 => {
+                let color = to_rgb565(color);
+                for pixel in pixels.chunks_exact_mut(2) {
+                    pixel.copy_from_slice(&color);
+                }
+            }
+            Self::A8 => pixels.fill(0xFF),
+            Self::RgbaF32 => {
+                let color = to_linear(color);
+                for pixel in pixels.chunks_exact_mut(16) {
+                    store_f32(pixel, color);
+                }
+            }
+        }
+    }
+
+    /// Composites packed, premultiplied colors over the pixels.
+    pub(super) fn blend(self, pixels: &mut [u8], colors: &[u32]) {
+        match self {
+            Self::Bgra8 => with_packed(pixels, |span| simd::blend_over_bgra(span, colors)),
+            Self::Rgba8 => with_packed(pixels, |span| simd::blend_over(span, colors)),
+            Self::Rgb565 => {
+                for (pixel, &color) in pixels.chunks_exact_mut(2).zip(colors) {
+                    let existing = from_rgb565([pixel[0], pixel[1]]);
+                    pixel.copy_from_slice(&to_rgb565(simd::scalar::blend_pixel(color, existing)));
+                }
+            }
+            Self::A8 => {
+                for (pixel, &color) in pixels.iter_mut().zip(colors) {
+                    *pixel = (simd::scalar::blend_pixel(color, (*pixel as u32) << 24) >> 24) as u8;
+                }
+            }
+            Self::RgbaF32 => {
+                for (pixel, &color) in pixels.chunks_exact_mut(16).zip(colors) {
+                    blend_f32(pixel, to_linear(color));
+                }
+            }
+        }
+    }
+
+    /// Composites linear, premultiplied colors over the pixels.
+    pub(super) fn blend_linear(self, pixels: &mut [u8], colors: &[[f32; 4]]) {
+        if self != Self::RgbaF32 {
+            let mut packed = [0; CHUNK];
+            for (pixels, colors) in pixels.chunks_mut(CHUNK * self.bytes_per_pixel()).zip(colors.chunks(CHUNK)) {
+                let packed = &mut packed[..colors.len()];
+                packed.iter_mut().zip(colors).for_each(|(packed, color)| *packed = from_linear(*color));
+                self.blend(pixels, packed);
+            }
+            return;
+        }
+
+        for (pixel, &color) in pixels.chunks_exact_mut(16).zip(colors) {
+            blend_f32(pixel, color);
+        }
+    }
+}
+
+/// Packs and premultiplies a straight alpha color.
+pub(super) fn premultiply(color: Color) -> u32 {
+    let alpha = color.alpha() as u32;
+    let multiply = |channel: u8| ((channel as u32 * alpha + 127) / 255) as u8;
+
+    u32::from_le_bytes([multiply(color.red()), multiply(color.green()), multiply(color.blue()), color.alpha()])
+}
+
+/// Premultiplies straight alpha RGBA8 pixels in place.
+pub(super) fn premultiply_rgba8(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let color = premultiply(Color::new(pixel[0], pixel[1], pixel[2], pixel[3]));
+        pixel.copy_from_slice(&color.to_le_bytes());
+    }
+}
+
+/// Premultiplies straight alpha, floating point RGBA pixels in place.
+pub(super) fn premultiply_rgba_f32(pixels: &mut [f32]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3];
+        pixel[..3].iter_mut().for_each(|channel| *channel *= alpha);
+    }
+}
+
+/// The inverse of [`premultiply_rgba_f32`], leaving transparent pixels black.
+pub(super) fn unpremultiply_rgba_f32(pixels: &mut [f32]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3];
+        if alpha > 0.0 {
+            pixel[..3].iter_mut().for_each(|channel| *channel /= alpha);
+        } else {
+            pixel[..3].fill(0.0);
+        }
+    }
+}
+
+/// Runs `f` on the pixels as packed colors, in place when they are aligned.
+fn with_packed(pixels: &mut [u8], f: impl FnOnce(&mut [u32])) {
+    if cfg!(target_endian = "little") {
+        if let Ok(span) = bytemuck::try_cast_slice_mut(pixels) {
+            return f(span);
+        }
+    }
+
+    let mut span: Vec<_> = pixels.chunks_exact(4)
+        .map(|pixel| u32::from_le_bytes(pixel.try_into().unwrap()))
+        .collect();
+
+    f(&mut span);
+
+    for (pixel, color) in pixels.chunks_exact_mut(4).zip(span) {
+        pixel.copy_from_slice(&color.to_le_bytes());
+    }
+}
+
+fn to_rgb565(color: u32) -> [u8; 2] {
+    let [r, g, b, _] = color.to_le_bytes().map(|channel| channel as u16);
+    let quantize = |channel: u16, max: u16| (channel * max + 127) / 255;
+    let pixel = quantize(r, 31) << 11 | quantize(g, 63) << 5 | quantize(b, 31);
+    pixel.to_le_bytes()
+}
+
+fn from_rgb565(pixel: [u8; 2]) -> u32 {
+    let pixel = u16::from_le_bytes(pixel) as u32;
+    let (r, g, b) = (pixel >> 11, pixel >> 5 & 0x3F, pixel & 0x1F);
+    u32::from_le_bytes([(r * 255 / 31) as u8, (g * 255 / 63) as u8, (b * 255 / 31) as u8, 0xFF])
+}
+
+/// Converts a packed, premultiplied sRGB color to a linear, premultiplied one.
+fn to_linear(color: u32) -> [f32; 4] {
+    let [r, g, b, a] = color.to_le_bytes().map(|channel| channel as f32 / 255.0);
+    if a == 0.0 {
+        return [0.0; 4];
+    }
+
+    let decode = |channel: f32| srgb_to_linear(channel / a) * a;
+    [decode(r), decode(g), decode(b), a]
+}
+
+/// The inverse of [`to_linear`].
+fn from_linear([r, g, b, a]: [f32; 4]) -> u32 {
+    if a <= 0.0 {
+        return 0;
+    }
+
+    premultiply(Color::from_linear([r / a, g / a, b / a, a]))
+}
+
+fn blend_f32(pixel: &mut [u8], color: [f32; 4]) {
+    let inverse_alpha = 1.0 - color[3];
+
+    for (channel, value) in pixel.chunks_exact_mut(4).zip(color) {
+        let existing = f32::from_ne_bytes(channel.try_into().unwrap());
+        channel.copy_from_slice(&(value + existing * inverse_alpha).to_ne_bytes());
+    }
+}
+
+fn store_f32(pixel: &mut [u8], color: [f32; 4]) {
+    for (channel, value) in pixel.chunks_exact_mut(4).zip(color) {
+        channel.copy_from_slice(&value.to_ne_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [TargetFormat; 5] =
+        [TargetFormat::Bgra8, TargetFormat::Rgba8, TargetFormat::Rgb565, TargetFormat::A8, TargetFormat::RgbaF32];
+
+    fn load_f32(pixel: &[u8]) -> [f32; 4] {
+        std::array::from_fn(|i| f32::from_ne_bytes(pixel[i * 4..i * 4 + 4].try_into().unwrap()))
+    }
+
+    #[test]
+    fn fill_opaque() {
+        let color = u32::from_le_bytes([0x10, 0x80, 0xF0, 0xFF]);
+        let expected: [&[u8]; 4] = [&[0xF0, 0x80, 0x10, 0xFF], &[0x10, 0x80, 0xF0, 0xFF], &0x141Du16.to_le_bytes(), &[0xFF]];
+
+        for (format, expected) in FORMATS.into_iter().zip(expected) {
+            let mut pixels = vec![0; 3 * format.bytes_per_pixel()];
+            format.fill(&mut pixels, color);
+            assert!(pixels.chunks(format.bytes_per_pixel()).all(|pixel| pixel == expected), "{format:?}");
+        }
+
+        let mut pixels = vec![0; 16];
+        TargetFormat::RgbaF32.fill(&mut pixels, color);
+        assert_eq!(load_f32(&pixels)[3], 1.0);
+        assert_eq!(from_linear(load_f32(&pixels)), color);
+    }
+
+    #[test]
+    fn fill_translucent_blends_every_pixel() {
+        // Longer than a chunk, so that the colors are reused.
+        let len = CHUNK * 2 + 5;
+        let color = premultiply(Color::new(0xFF, 0x00, 0x00, 0x80));
+
+        for format in FORMATS {
+            let mut pixels = vec![0; len * format.bytes_per_pixel()];
+            format.fill(&mut pixels, color);
+
+            let mut expected = vec![0; len * format.bytes_per_pixel()];
+            format.blend(&mut expected, &vec![color; len]);
+            assert_eq!(pixels, expected, "{format:?}");
+            assert!(pixels.iter().any(|byte| *byte != 0), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn blend_bgra8_matches_rgba8() {
+        let colors: Vec<u32> = (0..67u32).map(|i| premultiply(Color::new((i * 3) as u8, 0x40, 0xC0, (i * 4) as u8))).collect();
+        let destination: Vec<u8> = (0..67 * 4).map(|i| (i * 7) as u8).collect();
+
+        let mut rgba = destination.clone();
+        TargetFormat::Rgba8.blend(&mut rgba, &colors);
+
+        let mut bgra: Vec<u8> = destination.chunks(4).flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]]).collect();
+        TargetFormat::Bgra8.blend(&mut bgra, &colors);
+
+        assert!(rgba.chunks(4).zip(bgra.chunks(4)).all(|(rgba, bgra)| rgba == [bgra[2], bgra[1], bgra[0], bgra[3]]));
+    }
+
+    #[test]
+    fn blend_a8_and_rgb565() {
+        let half_red = premultiply(Color::new(0xFF, 0x00, 0x00, 0x80));
+
+        let mut mask = [0x00, 0x80, 0xFF];
+        TargetFormat::A8.blend(&mut mask, &[half_red; 3]);
+        assert_eq!(mask, [0x80, 0xBF, 0xFF]);
+
+        let mut pixels = 0x001Fu16.to_le_bytes();
+        TargetFormat::Rgb565.blend(&mut pixels, &[half_red]);
+        let blended = from_rgb565(pixels).to_le_bytes();
+        assert_eq!(blended[3], 0xFF);
+        assert!(blended[0].abs_diff(0x80) <= 8 && blended[2].abs_diff(0x7F) <= 8, "{blended:?}");
+    }
+
+    #[test]
+    fn blend_linear_converts_to_srgb() {
+        let colors = vec![[0.25, 0.0, 0.5, 0.5]; CHUNK + 3];
+
+        for format in FORMATS {
+            let mut pixels = vec![0; colors.len() * format.bytes_per_pixel()];
+            format.blend_linear(&mut pixels, &colors);
+
+            let mut expected = vec![0; colors.len() * format.bytes_per_pixel()];
+            if format == TargetFormat::RgbaF32 {
+                expected.chunks_mut(16).for_each(|pixel| store_f32(pixel, colors[0]));
+            } else {
+                format.blend(&mut expected, &vec![from_linear(colors[0]); colors.len()]);
+            }
+            assert_eq!(pixels, expected, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn rgb565_round_trips() {
+        for pixel in [0x0000u16, 0xFFFF, 0xF800, 0x07E0, 0x001F, 0x8410] {
+            assert_eq!(to_rgb565(from_rgb565(pixel.to_le_bytes())), pixel.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn linear_round_trips() {
+        for color in [0, 0xFFFF_FFFF, premultiply(Color::new(0x20, 0x80, 0xE0, 0x80)), premultiply(Color::new(0xFF, 0x00, 0x00, 0xFF))] {
+            assert_eq!(from_linear(to_linear(color)), color, "{color:08X}");
+        }
+        assert_eq!(to_linear(0x0000_00FF), [0.0; 4]);
+    }
+
+    #[test]
+    fn premultiply_and_unpremultiply_f32() {
+        let mut pixels = [1.0, 0.5, 2.0, 0.5, 1.0, 1.0, 1.0, 0.0];
+        premultiply_rgba_f32(&mut pixels);
+        assert_eq!(pixels, [0.5, 0.25, 1.0, 0.5, 0.0, 0.0, 0.0, 0.0]);
+
+        unpremultiply_rgba_f32(&mut pixels);
+        assert_eq!(pixels, [1.0, 0.5, 2.0, 0.5, 0.0, 0.0, 0.0, 0.0]);
+    }
+}
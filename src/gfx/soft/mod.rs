@@ -1,110 +1,181 @@
 // Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
+mod format;
 mod painter;
+mod pixels;
+mod sampler;
+mod simd;
+#[cfg(feature = "terminal")]
+mod terminal;
+mod tile;
+#[cfg(feature = "window")]
+mod window;
+
+use std::{cell::RefCell, rc::Rc, sync::Arc};
+
+use euclid::default::{Rect, Size2D};
+use image::{imageops, Rgba32FImage, RgbaImage};
+use format::{premultiply_rgba8, premultiply_rgba_f32, unpremultiply_rgba_f32};
+use painter::{SoftwarePainter, SoftwareTarget};
+use sampler::Mipmaps;
+
+pub use format::TargetFormat;
+pub use pixels::{PixelBuffer, PixelContext, PixelTarget};
+#[cfg(feature = "terminal")]
+pub use terminal::{TerminalContext, TerminalMode};
+#[cfg(feature = "window")]
+pub use window::SoftwareContext;
+
+use crate::{Color, ImageLoadError, ResourceManager, ResourceNamespace};
+
+use super::{
+    atlas::{apply_defragmentation, write_entry, AtlasAllocator, ATLAS_MIP_LEVELS, ATLAS_PAGE_SIZE},
+    painter::PainterImplementation,
+    Image,
+};
 
-use std::{cell::RefCell, num::NonZero, path::Path, rc::Rc};
-
-use euclid::default::Size2D;
-use glium::winit::{dpi::PhysicalSize, event_loop::EventLoop, window::Window};
-use image::RgbaImage;
-use painter::SoftwarePainter;
-use softbuffer::Surface;
-
-use crate::{EventTy, ImageLoadError, ResourceManager, ResourceNamespace};
-
-use super::{painter::PainterImplementation, ContextImplementation, Image};
-
-type SoftwareSurface = Rc<RefCell<Surface<Rc<Window>, Rc<Window>>>>;
-
-/// A software renderer [`Context`].
-pub struct SoftwareContext {
-    window: Rc<Window>,
-    surface: SoftwareSurface,
-    resources: Rc<SoftwareResources>,
+struct SoftwareResources {
+    images: ResourceManager<Arc<Mipmaps<u8>>>,
+    hdr_images: ResourceManager<Arc<Mipmaps<f32>>>,
+    atlas: RefCell<SoftwareAtlas>,
 }
 
-impl SoftwareContext {
-    pub fn new(event_loop: &EventLoop<EventTy>) -> (Box<dyn ContextImplementation>, Rc<Window>) {
-        let attr = Window::default_attributes()
-            .with_inner_size(PhysicalSize::new(1600, 1200))
-            .with_title("Zinnebeeld");
+impl SoftwareResources {
+    pub fn new() -> Rc<Self> {
+        Rc::new(Self {
+            images: ResourceManager::new(ResourceNamespace::Image),
+            hdr_images: ResourceManager::new(ResourceNamespace::HdrImage),
+            atlas: RefCell::new(SoftwareAtlas::default()),
+        })
+    }
 
-        #[allow(deprecated)]
-        let window = event_loop.create_window(attr).expect("Failed to create window");
-        let window = Rc::new(window);
+    /// The images are premultiplied, so that filtering doesn't bleed the
+    /// color of transparent texels.
+    fn create_image(&self, mut img: RgbaImage) -> Image {
+        let size = Size2D::from(img.dimensions());
+        premultiply_rgba8(&mut img);
 
-        let context = softbuffer::Context::new(window.clone()).unwrap();
+        if AtlasAllocator::accepts(size) {
+            let mut atlas = self.atlas.borrow_mut();
+            let (id, entry) = atlas.allocator.allocate(size);
+            if entry.page == atlas.pages.len() {
+                atlas.pages.push(SoftwareAtlasPage::new());
+            }
 
-        let surface = Surface::new(&context, window.clone()).unwrap();
-        let surface = Rc::new(RefCell::new(surface));
+            let page = &mut atlas.pages[entry.page];
+            write_entry(Arc::make_mut(&mut page.mipmaps).base_mut(), entry, &img);
+            return Image::new(id, size);
+        }
 
-        let this = Self {
-            window: Rc::clone(&window),
-            surface,
-            resources: SoftwareResources::new(),
-        };
+        let id = self.images.add(Arc::new(Mipmaps::new(img)));
 
-        (Box::new(this), window)
+        Image::new(id, size)
     }
 
-    fn set_size(&self, size: Size2D<u32>) {
-        let Some(width) = NonZero::new(size.width) else {
-            return;
-        };
+    fn update_image(&self, image: Image, region: Rect<u32>, mut pixels: RgbaImage) -> Result<(), ImageLoadError> {
+        premultiply_rgba8(&mut pixels);
 
-        let Some(height) = NonZero::new(size.height) else {
-            return;
-        };
+        if image.id.namespace() == ResourceNamespace::AtlasImage {
+            let mut atlas = self.atlas.borrow_mut();
+            let entry = atlas.allocator.get(image.id).ok_or(ImageLoadError::UnknownImage)?;
+            let origin = entry.rect.origin + region.origin.to_vector();
 
-        self.surface.borrow_mut().resize(width, height).unwrap();
-    }
+            let page = &mut atlas.pages[entry.page];
+            imageops::replace(Arc::make_mut(&mut page.mipmaps).base_mut(), &pixels, origin.x as i64, origin.y as i64);
+            return Ok(());
+        }
 
-    fn get_size_from_window(&self) -> Size2D<u32> {
-        let size = self.window.inner_size(); //.to_logical(self.window.scale_factor());
-        Size2D::new(size.width, size.height)
-    }
-}
+        if !self.images.contains(image.id) {
+            return Err(ImageLoadError::UnknownImage);
+        }
 
-impl ContextImplementation for SoftwareContext {
-    fn resize(&mut self, size: Size2D<u32>) {
-        self.set_size(size);
+        self.images.with_mut(image.id, |mipmaps| {
+            let mipmaps = Arc::make_mut(mipmaps);
+            imageops::replace(mipmaps.base_mut(), &pixels, region.min_x() as i64, region.min_y() as i64);
+        });
+        Ok(())
     }
 
-    fn load_image(&mut self, path: &Path) -> Result<Image, ImageLoadError> {
-        let (img, size) = Image::load(path)?;
-        let id = self.resources.images.add(img);
-
-        Ok(Image {
-            id,
-            size,
-        })
+    fn unload_image(&self, image: Image) {
+        match image.id.namespace() {
+            ResourceNamespace::Image | ResourceNamespace::CompressedImage => {
+                self.images.remove(image.id);
+            }
+
+            ResourceNamespace::HdrImage => {
+                self.hdr_images.remove(image.id);
+            }
+
+            ResourceNamespace::AtlasImage => {
+                let mut atlas = self.atlas.borrow_mut();
+                if let Some(defragmentation) = atlas.allocator.free(image.id) {
+                    let page = &mut atlas.pages[defragmentation.page];
+                    let compacted = apply_defragmentation(page.mipmaps.base(), &defragmentation);
+                    *Arc::make_mut(&mut page.mipmaps).base_mut() = compacted;
+                }
+            }
+        }
     }
 
-    fn paint_frame(&self, f: &mut dyn FnMut(&mut dyn PainterImplementation)) {
-        let size = self.get_size_from_window();
-        self.set_size(size);
+    fn create_hdr_image(&self, mut img: Rgba32FImage) -> Image {
+        let size = Size2D::from(img.dimensions());
+        premultiply_rgba_f32(&mut img);
+        let id = self.hdr_images.add(Arc::new(Mipmaps::new(img)));
 
-        let mut surface = self.surface.borrow_mut();
-        let buffer = surface.buffer_mut().unwrap();
+        Image::new(id, size)
+    }
 
-        let mut painter = SoftwarePainter::new(size, self.window.scale_factor(), buffer, Rc::clone(&self.resources));
+    fn paint_offscreen_hdr(self: &Rc<Self>, size: Size2D<u32>, f: &mut dyn FnMut(&mut dyn PainterImplementation)) -> Rgba32FImage {
+        let mut image = Rgba32FImage::new(size.width, size.height);
+        let target = SoftwareTarget::packed(bytemuck::cast_slice_mut(&mut image), size, TargetFormat::RgbaF32);
 
+        let mut painter = SoftwarePainter::new(1.0, target, Rc::clone(self), Color::BLACK);
         f(&mut painter);
-
         painter.finish();
+
+        // The painter blends premultiplied colors, but the image is returned
+        // with straight alpha, like the OpenGL backend does.
+        unpremultiply_rgba_f32(&mut image);
+        image
     }
 
+    /// Paints a frame into the target, if its stride and pixels fit its size.
+    fn paint_pixels(
+        self: &Rc<Self>,
+        target: &mut dyn PixelTarget,
+        scale_factor: f64,
+        f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<(), ImageLoadError> {
+        let (size, stride, format) = (target.size(), target.stride(), target.format());
+
+        let mut result = Ok(());
+        target.with_pixels(&mut |pixels| {
+            result = SoftwareTarget::new(pixels, size, stride, format).map(|target| {
+                let mut painter = SoftwarePainter::new(scale_factor, target, Rc::clone(self), Color::BLACK);
+                f(&mut painter);
+                painter.finish();
+            });
+        });
+        result
+    }
 }
 
-struct SoftwareResources {
-    images: ResourceManager<RgbaImage>,
+#[derive(Default)]
+struct SoftwareAtlas {
+    allocator: AtlasAllocator,
+    pages: Vec<SoftwareAtlasPage>,
 }
 
-impl SoftwareResources {
-    pub fn new() -> Rc<Self> {
-        Rc::new(Self {
-            images: ResourceManager::new(ResourceNamespace::Image),
-        })
+/// The levels stop at [`ATLAS_MIP_LEVELS`], where the padding is one texel.
+struct SoftwareAtlasPage {
+    mipmaps: Arc<Mipmaps<u8>>,
+}
+
+impl SoftwareAtlasPage {
+    fn new() -> Self {
+        Self {
+            mipmaps: Arc::new(Mipmaps::with_max_level(RgbaImage::new(ATLAS_PAGE_SIZE, ATLAS_PAGE_SIZE), ATLAS_MIP_LEVELS as usize)),
+        }
     }
 }
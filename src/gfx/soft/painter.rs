@@ -1,101 +1,211 @@
 // Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
 // All Rights Reserved.
 
-use std::rc::Rc;
+use std::{rc::Rc, sync::Arc};
 
-use euclid::default::{Point2D, Rect, Size2D};
-use glium::winit::window::Window;
-use image::Rgba;
-use softbuffer::Buffer;
+use euclid::default::{Point2D, Rect, Size2D, Vector2D};
+use image::{Pixel, Rgba};
 
-use crate::{gfx::painter::PainterImplementation, Color, Image, Material};
+use crate::{gfx::{atlas::ATLAS_MAX_SAMPLING, painter::PainterImplementation}, Color, Image, ImageLoadError, Material, ResourceNamespace, SamplingQuality};
 
-use super::SoftwareResources;
+use super::{
+    format::{premultiply, TargetFormat},
+    sampler::{Mipmaps, Texel},
+    tile::{self, Command},
+    SoftwareResources,
+};
 
-pub(super) struct SoftwarePainter<'ctx> {
+/// The memory a [`SoftwarePainter`] paints into.
+pub(super) struct SoftwareTarget<'p> {
+    pixels: &'p mut [u8],
     size: Size2D<u32>,
+
+    /// The number of bytes from the start of a row to the start of the next.
+    stride: usize,
+    format: TargetFormat,
+}
+
+impl<'p> SoftwareTarget<'p> {
+    /// Checks that the rows fit in the pixels, as they may come from outside
+    /// of the crate.
+    pub fn new(pixels: &'p mut [u8], size: Size2D<u32>, stride: usize, format: TargetFormat) -> Result<Self, ImageLoadError> {
+        let row = size.width as usize * format.bytes_per_pixel();
+        if stride < row {
+            return Err(ImageLoadError::InvalidPixelTarget("the stride is smaller than a row"));
+        }
+
+        let len = stride.checked_mul((size.height as usize).saturating_sub(1)).and_then(|len| len.checked_add(row));
+        if size.height != 0 && len.is_none_or(|len| pixels.len() < len) {
+            return Err(ImageLoadError::InvalidPixelTarget("the buffer is too small for its size and stride"));
+        }
+
+        Ok(Self { pixels, size, stride, format })
+    }
+
+    /// A target without padding between the rows, in a buffer of exactly
+    /// that size.
+    pub fn packed(pixels: &'p mut [u8], size: Size2D<u32>, format: TargetFormat) -> Self {
+        debug_assert_eq!(pixels.len(), size.width as usize * size.height as usize * format.bytes_per_pixel());
+        let stride = size.width as usize * format.bytes_per_pixel();
+        Self { pixels, size, stride, format }
+    }
+}
+
+/// Records the paint operations of a frame, which are rasterized by
+/// [`Self::finish`].
+pub(super) struct SoftwarePainter<'p> {
     scale_factor: f64,
-    buffer: Buffer<'ctx, Rc<Window>, Rc<Window>>,
+    target: SoftwareTarget<'p>,
     resources: Rc<SoftwareResources>,
+    commands: Vec<Command>,
 }
 
-impl<'ctx> SoftwarePainter<'ctx> {
-    pub fn new(
-        size: Size2D<u32>,
-        scale_factor: f64,
-        buffer: Buffer<'ctx, Rc<Window>, Rc<Window>>,
-        resources: Rc<SoftwareResources>,
-    ) -> Self {
+impl<'p> SoftwarePainter<'p> {
+    /// The frame starts out filled with `clear_color`. Coordinates are
+    /// multiplied by `scale_factor` to get the pixels of the target.
+    pub fn new(scale_factor: f64, target: SoftwareTarget<'p>, resources: Rc<SoftwareResources>, clear_color: Color) -> Self {
         let mut this = Self {
-            size,
-            scale_factor: 1.0 / scale_factor,
-            buffer,
+            scale_factor,
+            commands: Vec::new(),
+            target,
             resources,
         };
 
-        this.paint_filled_rect(Rect::new(Point2D::zero(), size.cast()), Color::BLACK.into());
+        this.commands.push(Command::Fill {
+            rect: Rect::from_size(this.target.size.cast()),
+            color: premultiply(clear_color),
+        });
 
         this
     }
 
     pub fn finish(self) {
-        self.buffer.present().unwrap();
+        let target = self.target;
+        tile::rasterize(target.pixels, target.size, target.stride, target.format, &self.commands);
     }
 
-    fn paint_rect_with_color(&mut self, rect: Rect<usize>, color: Color) {
-        let color = color.as_bgra();
+    fn record_image(&mut self, rect: Rect<isize>, image: Image, source: Rect<f32>) {
+        let mipmaps = self.resources.images.with_mut(image.id, |m| prepare(m, image.sampling));
+
+        self.commands.push(Command::Image {
+            rect,
+            source,
+            footprint: footprint(mipmaps.base().dimensions(), source, rect),
+            sampling: image.sampling,
+            mipmaps,
+        });
+    }
 
-        for y in rect.y_range() {
-            let offset = y * self.size.width as usize;
+    /// The entry is drawn from the page as a whole, with the source rectangle
+    /// mapped into the page.
+    fn record_atlas_image(&mut self, rect: Rect<isize>, image: Image, source: Rect<f32>) {
+        let sampling = image.sampling.min(ATLAS_MAX_SAMPLING);
+        let mut atlas = self.resources.atlas.borrow_mut();
+        let Some(entry) = atlas.allocator.get(image.id) else {
+            return;
+        };
 
-            let start = offset + rect.min_x();
-            let end = offset + rect.max_x();
+        let mipmaps = prepare(&mut atlas.pages[entry.page].mipmaps, sampling);
+        drop(atlas);
 
-            self.buffer[start..end].fill(color);
-        }
+        let uv = entry.uv_rect();
+        let footprint = footprint(entry.rect.size.to_tuple(), source, rect);
+        let source = Rect::new(
+            Point2D::new(uv.min_x() + source.min_x() * uv.width(), uv.min_y() + source.min_y() * uv.height()),
+            Size2D::new(source.width() * uv.width(), source.height() * uv.height()),
+        );
+
+        self.commands.push(Command::Image {
+            rect,
+            source,
+            footprint,
+            sampling,
+            mipmaps,
+        });
     }
 
-    fn paint_rect_with_image(&mut self, rect: Rect<usize>, image: Image) {
-        self.resources.images.with(image.id, |image| {
-            for y in rect.y_range() {
-                let line_offset = y * self.size.width as usize;
+    fn record_hdr_image(&mut self, rect: Rect<isize>, image: Image, source: Rect<f32>) {
+        let mipmaps = self.resources.hdr_images.with_mut(image.id, |m| prepare(m, image.sampling));
+
+        self.commands.push(Command::HdrImage {
+            rect,
+            source,
+            footprint: footprint(mipmaps.base().dimensions(), source, rect),
+            sampling: image.sampling,
+            exposure: image.exposure,
+            tone_mapping: image.tone_mapping,
+            mipmaps,
+        });
+    }
 
-                for x in rect.x_range() {
-                    let offset = line_offset + x;
+    /// Converts a rectangle in logical pixels to the pixels of the target.
+    fn to_target_rect(&self, rect: Rect<f32>) -> Rect<isize> {
+        let rect = rect.cast::<f64>().scale(self.scale_factor, self.scale_factor);
 
-                    let u = (x - rect.min_x()) as f64 / rect.width() as f64;
-                    let v = (y - rect.min_y()) as f64 / rect.width() as f64;
-                    let u = (u * image.width() as f64) as u32;
-                    let v = (v * image.height() as f64) as u32;
-                    let pixel: Color = image.get_pixel(u, v).into();
+        Rect::new(
+            Point2D::new(rect.min_x().round() as isize, rect.min_y().round() as isize),
+            Size2D::new(rect.width().round() as isize, rect.height().round() as isize),
+        )
+    }
+}
 
-                    self.buffer[offset] = pixel.as_bgra();
-                }
-            }
-        });
+/// Regenerates stale mipmaps if `sampling` uses the lower levels.
+fn prepare<T: Texel>(mipmaps: &mut Arc<Mipmaps<T>>, sampling: SamplingQuality) -> Arc<Mipmaps<T>>
+        where Rgba<T>: Pixel<Subpixel = T> {
+    if sampling.uses_mipmaps() && mipmaps.is_stale() {
+        Arc::make_mut(mipmaps).regenerate();
     }
+
+    Arc::clone(mipmaps)
 }
 
-impl<'ctx> PainterImplementation for SoftwarePainter<'ctx> {
-    fn paint_filled_rect(&mut self, rect: Rect<f32>, brush: Material) {
-        let rect = rect.cast::<f64>().scale(self.scale_factor, self.scale_factor);
+/// The number of texels that a single pixel covers, horizontally and vertically.
+fn footprint(dimensions: (u32, u32), source: Rect<f32>, rect: Rect<isize>) -> Vector2D<f32> {
+    Vector2D::new(
+        dimensions.0 as f32 * source.width() / rect.width().max(1) as f32,
+        dimensions.1 as f32 * source.height() / rect.height().max(1) as f32,
+    )
+}
 
-        let rect = Rect::new(
-            Point2D::new(
-                (rect.min_x().round() as usize).max(0),
-                (rect.min_y().round() as usize).max(0),
-            ),
-            Size2D::new(
-                (rect.width().round() as usize).min(self.size.width as usize - 1),
-                (rect.height().round() as usize).min(self.size.height as usize - 1),
-            ),
-        );
+/// The normalized source rectangle covering the whole texture.
+const FULL_SOURCE: Rect<f32> = Rect::new(Point2D::new(0.0, 0.0), Size2D::new(1.0, 1.0));
 
+impl PainterImplementation for SoftwarePainter<'_> {
+    fn paint_filled_rect(&mut self, rect: Rect<f32>, brush: Material) {
         match brush {
-            Material::Color(color) => self.paint_rect_with_color(rect, color),
-            Material::Image(image) => self.paint_rect_with_image(rect, image),
+            Material::Color(color) => self.commands.push(Command::Fill {
+                rect: self.to_target_rect(rect),
+                color: premultiply(color),
+            }),
+            Material::Image(image) => self.paint_image_region(rect, image, FULL_SOURCE),
         }
     }
+
+    fn paint_image_region(&mut self, rect: Rect<f32>, image: Image, source: Rect<f32>) {
+        let rect = self.to_target_rect(rect);
+
+        match image.id.namespace() {
+            ResourceNamespace::HdrImage => self.record_hdr_image(rect, image, source),
+            ResourceNamespace::AtlasImage => self.record_atlas_image(rect, image, source),
+            ResourceNamespace::Image | ResourceNamespace::CompressedImage => {
+                self.record_image(rect, image, source)
+            }
+        }
+    }
+
+    /// The document is rasterized right away, as it is only needed once.
+    #[cfg(feature = "svg")]
+    fn paint_svg(&mut self, rect: Rect<f32>, svg: &crate::SvgDocument) {
+        let rect = self.to_target_rect(rect);
+        if rect.is_empty() {
+            return;
+        }
+
+        let mut pixels = svg.rasterize(rect.size.cast());
+        super::format::premultiply_rgba8(&mut pixels);
+
+        self.commands.push(Command::Pixels { rect, pixels });
+    }
 }
 
 impl From<&Rgba<u8>> for Color {
@@ -103,3 +213,4 @@ impl From<&Rgba<u8>> for Color {
         Self::new(value.0[0], value.0[1], value.0[2], value.0[3])
     }
 }
+
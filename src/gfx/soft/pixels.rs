@@ -0,0 +1,190 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::{cell::RefCell, rc::Rc};
+
+use euclid::default::{Rect, Size2D};
+use image::{Rgba32FImage, RgbaImage};
+
+use crate::{gfx::painter::PainterImplementation, BackendInfo, Capabilities, CompressedImage, ContextImplementation, Image, ImageLoadError};
+
+use super::{SoftwareResources, TargetFormat};
+
+/// Memory owned by another program, e.g. the framebuffer of an emulator, a
+/// VNC server or a shared-memory buffer, that a
+/// [`Context::from_pixels`](crate::Context::from_pixels) paints into.
+///
+/// Buffers of `u32` pixels that are only borrowed can be painted into with a
+/// [`PixelBuffer`], using [`TargetFormat::Bgra8`] for `0xAARRGGBB` pixels.
+/// Frames of which the pixels don't fit the size and stride are skipped.
+pub trait PixelTarget {
+    /// The size in pixels, which may change between frames.
+    fn size(&self) -> Size2D<u32>;
+
+    fn format(&self) -> TargetFormat;
+
+    /// The number of bytes from the start of a row to the start of the next.
+    fn stride(&self) -> usize {
+        self.size().width as usize * self.format().bytes_per_pixel()
+    }
+
+    /// Calls `f` with the pixels, which must hold at least `size().height`
+    /// rows of `stride()` bytes. This is the place to lock the memory, if it
+    /// is shared.
+    fn with_pixels(&mut self, f: &mut dyn FnMut(&mut [u8]));
+}
+
+impl PixelTarget for RgbaImage {
+    fn size(&self) -> Size2D<u32> {
+        Size2D::from(self.dimensions())
+    }
+
+    fn format(&self) -> TargetFormat {
+        TargetFormat::Rgba8
+    }
+
+    fn with_pixels(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+        f(self);
+    }
+}
+
+impl PixelTarget for Rgba32FImage {
+    fn size(&self) -> Size2D<u32> {
+        Size2D::from(self.dimensions())
+    }
+
+    fn format(&self) -> TargetFormat {
+        TargetFormat::RgbaF32
+    }
+
+    fn with_pixels(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+        f(bytemuck::cast_slice_mut(self));
+    }
+}
+
+/// Pixels that are only borrowed, e.g. for [`Context::paint_pixels`](crate::Context::paint_pixels).
+pub struct PixelBuffer<'a> {
+    pixels: &'a mut [u32],
+    size: Size2D<u32>,
+    stride: usize,
+    format: TargetFormat,
+}
+
+impl<'a> PixelBuffer<'a> {
+    /// The `stride` is in bytes, like [`PixelTarget::stride`]. Whether the
+    /// pixels fit is checked when painting.
+    pub fn new(pixels: &'a mut [u32], size: Size2D<u32>, stride: usize, format: TargetFormat) -> Self {
+        Self { pixels, size, stride, format }
+    }
+}
+
+impl PixelTarget for PixelBuffer<'_> {
+    fn size(&self) -> Size2D<u32> {
+        self.size
+    }
+
+    fn format(&self) -> TargetFormat {
+        self.format
+    }
+
+    fn stride(&self) -> usize {
+        self.stride
+    }
+
+    fn with_pixels(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+        f(bytemuck::cast_slice_mut(self.pixels));
+    }
+}
+
+/// Lets the program keep access to the target after giving it to the context.
+impl<T: PixelTarget> PixelTarget for Rc<RefCell<T>> {
+    fn size(&self) -> Size2D<u32> {
+        self.borrow().size()
+    }
+
+    fn format(&self) -> TargetFormat {
+        self.borrow().format()
+    }
+
+    fn stride(&self) -> usize {
+        self.borrow().stride()
+    }
+
+    fn with_pixels(&mut self, f: &mut dyn FnMut(&mut [u8])) {
+        self.borrow_mut().with_pixels(f);
+    }
+}
+
+/// A software renderer [`Context`](crate::Context) that paints into a
+/// [`PixelTarget`], without a window.
+pub struct PixelContext {
+    target: RefCell<Box<dyn PixelTarget>>,
+    resources: Rc<SoftwareResources>,
+}
+
+impl PixelContext {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(target: Box<dyn PixelTarget>) -> Box<dyn ContextImplementation> {
+        Box::new(Self {
+            target: RefCell::new(target),
+            resources: SoftwareResources::new(),
+        })
+    }
+}
+
+impl ContextImplementation for PixelContext {
+    /// The size is that of the target.
+    fn resize(&mut self, _: Size2D<u32>) {}
+
+    fn create_image(&mut self, img: RgbaImage) -> Result<Image, ImageLoadError> {
+        Ok(self.resources.create_image(img))
+    }
+
+    fn update_image(&mut self, image: Image, region: Rect<u32>, pixels: RgbaImage) -> Result<(), ImageLoadError> {
+        self.resources.update_image(image, region, pixels)
+    }
+
+    fn unload_image(&mut self, image: Image) {
+        self.resources.unload_image(image);
+    }
+
+    fn paint_frame(&self, scale_factor: f64, f: &mut dyn FnMut(&mut dyn PainterImplementation)) {
+        let mut target = self.target.borrow_mut();
+        if let Err(error) = self.resources.paint_pixels(&mut **target, scale_factor, f) {
+            log::warn!("skipping a frame: {error}");
+        }
+    }
+
+    fn paint_pixels(
+        &self,
+        target: &mut dyn PixelTarget,
+        scale_factor: f64,
+        f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<(), ImageLoadError> {
+        self.resources.paint_pixels(target, scale_factor, f)
+    }
+
+    fn create_hdr_image(&mut self, img: Rgba32FImage) -> Result<Image, ImageLoadError> {
+        Ok(self.resources.create_hdr_image(img))
+    }
+
+    fn create_compressed_image(&mut self, image: CompressedImage) -> Result<Image, ImageLoadError> {
+        self.create_image(image.decode()?)
+    }
+
+    fn paint_offscreen_hdr(
+        &self,
+        size: Size2D<u32>,
+        f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<Rgba32FImage, ImageLoadError> {
+        Ok(self.resources.paint_offscreen_hdr(size, f))
+    }
+
+    fn backend_info(&self) -> BackendInfo {
+        BackendInfo::new("software", Some(String::from("pixel target")))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::cpu()
+    }
+}
@@ -0,0 +1,289 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use euclid::default::Vector2D;
+use image::{ImageBuffer, Pixel, Rgba};
+
+use crate::SamplingQuality;
+
+/// The maximum number of samples taken along the major axis with
+/// [`SamplingQuality::Anisotropic`].
+const MAX_ANISOTROPY: f32 = 16.0;
+
+pub(super) trait Texel: Copy + 'static {
+    fn to_f32(self) -> f32;
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Texel for u8 {
+    fn to_f32(self) -> f32 {
+        self as f32 / 255.0
+    }
+
+    fn from_f32(value: f32) -> Self {
+        (value * 255.0).round().clamp(0.0, 255.0) as u8
+    }
+}
+
+impl Texel for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+type Level<T> = ImageBuffer<Rgba<T>, Vec<T>>;
+
+/// An image together with its box-filtered mipmap pyramid, the software
+/// counterpart of a mipmapped OpenGL texture.
+#[derive(Clone)]
+pub(super) struct Mipmaps<T: Texel>
+        where Rgba<T>: Pixel<Subpixel = T> {
+    levels: Vec<Level<T>>,
+
+    /// Whether the lower levels are out of date with the base level. They're
+    /// regenerated lazily, as images that are updated every frame or are never
+    /// scaled down would otherwise pay for them needlessly.
+    stale: bool,
+
+    /// The number of levels below the base level that are generated.
+    max_level: usize,
+}
+
+impl<T: Texel> Mipmaps<T>
+        where Rgba<T>: Pixel<Subpixel = T> {
+    pub fn new(base: Level<T>) -> Self {
+        Self::with_max_level(base, usize::MAX)
+    }
+
+    /// Stops generating levels after `max_level`, for atlas pages.
+    pub fn with_max_level(base: Level<T>, max_level: usize) -> Self {
+        Self {
+            levels: vec![base],
+            stale: true,
+            max_level,
+        }
+    }
+
+    pub fn base(&self) -> &Level<T> {
+        &self.levels[0]
+    }
+
+    /// Marks the lower levels as stale, see [`Self::is_stale`].
+    pub fn base_mut(&mut self) -> &mut Level<T> {
+        self.stale = true;
+        &mut self.levels[0]
+    }
+
+    /// Whether [`Self::regenerate`] must be called before sampling with a
+    /// quality that uses the lower levels.
+    pub const fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Rebuilds all levels from the base level.
+    pub fn regenerate(&mut self) {
+        self.stale = false;
+        self.levels.truncate(1);
+
+        loop {
+            let prev = self.levels.last().unwrap();
+            if (prev.width() == 1 && prev.height() == 1) || self.levels.len() > self.max_level {
+                break;
+            }
+
+            let next = downsample(prev);
+            self.levels.push(next);
+        }
+    }
+
+    /// Samples the image at the normalized coordinates `u` and `v`.
+    /// `footprint` is the number of base-level texels covered by a single
+    /// destination pixel, in both directions.
+    pub fn sample(&self, u: f32, v: f32, footprint: Vector2D<f32>, quality: SamplingQuality) -> [f32; 4] {
+        match quality {
+            SamplingQuality::Nearest => self.sample_nearest(0, u, v),
+            SamplingQuality::Linear => self.sample_bilinear(0, u, v),
+            SamplingQuality::Trilinear => {
+                self.sample_trilinear(u, v, footprint.x.max(footprint.y))
+            }
+            SamplingQuality::Anisotropic => self.sample_anisotropic(u, v, footprint),
+        }
+    }
+
+    fn sample_anisotropic(&self, u: f32, v: f32, footprint: Vector2D<f32>) -> [f32; 4] {
+        let major = footprint.x.max(footprint.y);
+        let minor = footprint.x.min(footprint.y).max(1.0);
+        let taps = (major / minor).ceil().clamp(1.0, MAX_ANISOTROPY);
+        if taps <= 1.0 {
+            return self.sample_trilinear(u, v, major);
+        }
+
+        // Spread the taps along the major axis, each sampling the level that
+        // matches the minor axis.
+        let base = self.base();
+        let (du, dv) = if footprint.x >= footprint.y {
+            (footprint.x / base.width() as f32, 0.0)
+        } else {
+            (0.0, footprint.y / base.height() as f32)
+        };
+
+        let lod_footprint = major / taps;
+        let mut sum = [0.0; 4];
+        for tap in 0..taps as u32 {
+            let t = (tap as f32 + 0.5) / taps - 0.5;
+            let sample = self.sample_trilinear(u + du * t, v + dv * t, lod_footprint);
+            for (sum, sample) in sum.iter_mut().zip(sample) {
+                *sum += sample / taps;
+            }
+        }
+        sum
+    }
+
+    fn sample_trilinear(&self, u: f32, v: f32, footprint: f32) -> [f32; 4] {
+        let lod = footprint.max(1.0).log2().min((self.levels.len() - 1) as f32);
+        let lower = lod.floor() as usize;
+        let upper = (lower + 1).min(self.levels.len() - 1);
+        let t = lod - lower as f32;
+
+        let a = self.sample_bilinear(lower, u, v);
+        if t == 0.0 || lower == upper {
+            return a;
+        }
+
+        let b = self.sample_bilinear(upper, u, v);
+        std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t)
+    }
+
+    fn sample_nearest(&self, level: usize, u: f32, v: f32) -> [f32; 4] {
+        let image = &self.levels[level];
+        let x = ((u * image.width() as f32) as u32).min(image.width() - 1);
+        let y = ((v * image.height() as f32) as u32).min(image.height() - 1);
+        image.get_pixel(x, y).0.map(T::to_f32)
+    }
+
+    fn sample_bilinear(&self, level: usize, u: f32, v: f32) -> [f32; 4] {
+        let image = &self.levels[level];
+        let max_x = image.width() as i64 - 1;
+        let max_y = image.height() as i64 - 1;
+
+        let x = u * image.width() as f32 - 0.5;
+        let y = v * image.height() as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        let texel = |x: i64, y: i64| {
+            image.get_pixel(x.clamp(0, max_x) as u32, y.clamp(0, max_y) as u32).0.map(T::to_f32)
+        };
+
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let a = texel(x0, y0);
+        let b = texel(x0 + 1, y0);
+        let c = texel(x0, y0 + 1);
+        let d = texel(x0 + 1, y0 + 1);
+
+        std::array::from_fn(|i| {
+            let top = a[i] + (b[i] - a[i]) * tx;
+            let bottom = c[i] + (d[i] - c[i]) * tx;
+            top + (bottom - top) * ty
+        })
+    }
+}
+
+/// Halves the image using a 2x2 box filter. Odd edges reuse the last texel.
+fn downsample<T: Texel>(image: &Level<T>) -> Level<T>
+        where Rgba<T>: Pixel<Subpixel = T> {
+    let width = (image.width() / 2).max(1);
+    let height = (image.height() / 2).max(1);
+    let max_x = image.width() - 1;
+    let max_y = image.height() - 1;
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let (x0, y0) = (x * 2, y * 2);
+        let texels = [
+            image.get_pixel(x0, y0),
+            image.get_pixel((x0 + 1).min(max_x), y0),
+            image.get_pixel(x0, (y0 + 1).min(max_y)),
+            image.get_pixel((x0 + 1).min(max_x), (y0 + 1).min(max_y)),
+        ];
+
+        Rgba(std::array::from_fn(|i| {
+            let sum: f32 = texels.iter().map(|texel| texel.0[i].to_f32()).sum();
+            T::from_f32(sum / 4.0)
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mipmaps(width: u32, height: u32, f: impl Fn(u32, u32) -> [u8; 4]) -> Mipmaps<u8> {
+        let mut mipmaps = Mipmaps::new(ImageBuffer::from_fn(width, height, |x, y| Rgba(f(x, y))));
+        mipmaps.regenerate();
+        mipmaps
+    }
+
+    #[test]
+    fn levels_halve_down_to_one_texel() {
+        let mipmaps = mipmaps(5, 2, |_, _| [0; 4]);
+        let sizes: Vec<_> = mipmaps.levels.iter().map(|level| level.dimensions()).collect();
+        assert_eq!(sizes, [(5, 2), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn levels_stop_at_the_max_level() {
+        let mut mipmaps = Mipmaps::<u8>::with_max_level(ImageBuffer::new(16, 16), 2);
+        mipmaps.regenerate();
+        assert_eq!(mipmaps.levels.len(), 3);
+    }
+
+    #[test]
+    fn downsample_averages_blocks() {
+        let mipmaps = mipmaps(2, 2, |x, y| [(x * 100 + y * 20) as u8, 0, 0, 255]);
+        assert_eq!(mipmaps.levels[1].get_pixel(0, 0).0, [60, 0, 0, 255]);
+    }
+
+    #[test]
+    fn base_mut_marks_stale() {
+        let mut mipmaps = Mipmaps::<u8>::new(ImageBuffer::new(2, 2));
+        assert!(mipmaps.is_stale());
+
+        mipmaps.regenerate();
+        assert!(!mipmaps.is_stale());
+
+        mipmaps.base_mut().put_pixel(0, 0, Rgba([255; 4]));
+        assert!(mipmaps.is_stale());
+
+        mipmaps.regenerate();
+        assert_eq!(mipmaps.levels[1].get_pixel(0, 0).0, [64; 4]);
+    }
+
+    #[test]
+    fn nearest_and_linear_sampling() {
+        let mipmaps = mipmaps(2, 1, |x, _| [x as u8 * 255, 0, 0, 255]);
+        let footprint = Vector2D::new(1.0, 1.0);
+
+        assert_eq!(mipmaps.sample(0.25, 0.5, footprint, SamplingQuality::Nearest)[0], 0.0);
+        assert_eq!(mipmaps.sample(0.75, 0.5, footprint, SamplingQuality::Nearest)[0], 1.0);
+        assert_eq!(mipmaps.sample(0.5, 0.5, footprint, SamplingQuality::Linear)[0], 0.5);
+
+        // Coordinates outside of the image are clamped to the edge.
+        assert_eq!(mipmaps.sample(1.5, 0.5, footprint, SamplingQuality::Nearest)[0], 1.0);
+        assert_eq!(mipmaps.sample(-0.5, 0.5, footprint, SamplingQuality::Linear)[0], 0.0);
+    }
+
+    #[test]
+    fn trilinear_selects_level_by_footprint() {
+        let mipmaps = mipmaps(4, 4, |x, y| if (x + y) % 2 == 0 { [255; 4] } else { [0; 4] });
+
+        let full = mipmaps.sample(0.125, 0.125, Vector2D::new(1.0, 1.0), SamplingQuality::Trilinear);
+        assert_eq!(full[0], 1.0);
+
+        let minified = mipmaps.sample(0.125, 0.125, Vector2D::new(2.0, 2.0), SamplingQuality::Trilinear);
+        assert!((minified[0] - 0.5).abs() < 0.01, "{minified:?}");
+    }
+}
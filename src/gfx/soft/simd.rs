@@ -0,0 +1,385 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Span operations of the software rasterizer on packed pixels, i.e. RGBA8
+//! with red in the lowest byte (see [`Color::as_bgra`](crate::Color::as_bgra))
+//! and premultiplied alpha. AVX2 is used when the CPU supports it, which is
+//! detected at runtime; the [`scalar`] versions produce identical results.
+//!
+//! This module only depends on `std`, so the benchmarks can include it.
+
+/// Fills the span with a single packed color.
+pub fn fill(span: &mut [u32], color: u32) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 is supported.
+        return unsafe { avx2::fill(span, color) };
+    }
+
+    scalar::fill(span, color);
+}
+
+/// Composites the packed colors over the pixels of the span with the
+/// source-over operator.
+pub fn blend_over(span: &mut [u32], colors: &[u32]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 is supported.
+        return unsafe { avx2::blend_over(span, colors) };
+    }
+
+    scalar::blend_over(span, colors);
+}
+
+/// Like [`blend_over`], but the span holds BGRA8 pixels. The colors are
+/// still packed RGBA8.
+pub fn blend_over_bgra(span: &mut [u32], colors: &[u32]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 is supported.
+        return unsafe { avx2::blend_over_bgra(span, colors) };
+    }
+
+    scalar::blend_over_bgra(span, colors);
+}
+
+/// Converts normalized (`0.0..=1.0`) colors to packed colors.
+pub fn pack_unorm(colors: &[[f32; 4]], packed: &mut [u32]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 is supported.
+        return unsafe { avx2::pack_unorm(colors, packed) };
+    }
+
+    scalar::pack_unorm(colors, packed);
+}
+
+/// Fetches the texels of an RGBA8 row for nearest sampling. The texel of
+/// pixel `i` is at the normalized coordinate `u + i * step`, which is clamped
+/// to the row, computed with the same rounding as the sampler. The row must
+/// be at most `1 << 24` texels wide, so that the indices are exact.
+pub fn fetch_nearest(row: &[u8], u: f32, step: f32, texels: &mut [u32]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 is supported.
+        return unsafe { avx2::fetch_nearest(row, u, step, texels) };
+    }
+
+    scalar::fetch_nearest(row, u, step, texels);
+}
+
+pub mod scalar {
+    pub fn fill(span: &mut [u32], color: u32) {
+        for pixel in span {
+            *pixel = color;
+        }
+    }
+
+    pub fn blend_over(span: &mut [u32], colors: &[u32]) {
+        for (pixel, &color) in span.iter_mut().zip(colors) {
+            *pixel = blend_pixel(color, *pixel);
+        }
+    }
+
+    pub fn blend_over_bgra(span: &mut [u32], colors: &[u32]) {
+        for (pixel, &color) in span.iter_mut().zip(colors) {
+            *pixel = blend_pixel(swap_pixel(color), *pixel);
+        }
+    }
+
+    /// Swaps the red and blue channels, converting between RGBA8 and BGRA8.
+    pub fn swap_pixel(pixel: u32) -> u32 {
+        pixel & 0xFF00_FF00 | (pixel & 0xFF) << 16 | pixel >> 16 & 0xFF
+    }
+
+    pub fn pack_unorm(colors: &[[f32; 4]], packed: &mut [u32]) {
+        for (packed, color) in packed.iter_mut().zip(colors) {
+            *packed = u32::from_le_bytes(color.map(|value| (value * 255.0 + 0.5).clamp(0.0, 255.0) as u8));
+        }
+    }
+
+    pub fn fetch_nearest(row: &[u8], u: f32, step: f32, texels: &mut [u32]) {
+        fetch_nearest_from(row, u, step, 0, texels);
+    }
+
+    /// Like [`fetch_nearest`], with the first texel being that of pixel `first`.
+    pub(super) fn fetch_nearest_from(row: &[u8], u: f32, step: f32, first: usize, texels: &mut [u32]) {
+        let width = row.len() / 4;
+
+        for (i, texel) in (first..).zip(texels) {
+            let x = (((u + i as f32 * step) * width as f32) as usize).min(width - 1);
+            *texel = u32::from_le_bytes(row[x * 4..x * 4 + 4].try_into().unwrap());
+        }
+    }
+
+    /// `s + d * (255 - a) / 255` for every channel, saturating in case the
+    /// source isn't a valid premultiplied color.
+    pub fn blend_pixel(source: u32, destination: u32) -> u32 {
+        let inverse_alpha = 255 - (source >> 24);
+
+        let mix = |shift: u32| {
+            let s = source >> shift & 0xFF;
+            let d = destination >> shift & 0xFF;
+            (s + div_255(d * inverse_alpha)).min(255) << shift
+        };
+
+        mix(0) | mix(8) | mix(16) | mix(24)
+    }
+
+    /// Divides by 255, rounding down, for values up to `255 * 255`.
+    fn div_255(value: u32) -> u32 {
+        (value + 1 + (value >> 8)) >> 8
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    /// # Safety
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn fill(span: &mut [u32], color: u32) {
+        let color_x8 = _mm256_set1_epi32(color as i32);
+
+        let mut chunks = span.chunks_exact_mut(8);
+        for chunk in &mut chunks {
+            _mm256_storeu_si256(chunk.as_mut_ptr().cast(), color_x8);
+        }
+
+        super::scalar::fill(chunks.into_remainder(), color);
+    }
+
+    /// # Safety
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn blend_over(span: &mut [u32], colors: &[u32]) {
+        let len = span.len().min(colors.len());
+        let (span, colors) = (&mut span[..len], &colors[..len]);
+
+        let mut spans = span.chunks_exact_mut(8);
+        let mut sources = colors.chunks_exact(8);
+        for (span, colors) in (&mut spans).zip(&mut sources) {
+            let destination = _mm256_loadu_si256(span.as_ptr().cast());
+            let source = _mm256_loadu_si256(colors.as_ptr().cast());
+            _mm256_storeu_si256(span.as_mut_ptr().cast(), blend(source, destination));
+        }
+
+        super::scalar::blend_over(spans.into_remainder(), sources.remainder());
+    }
+
+    /// # Safety
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn blend_over_bgra(span: &mut [u32], colors: &[u32]) {
+        let len = span.len().min(colors.len());
+        let (span, colors) = (&mut span[..len], &colors[..len]);
+
+        let mut spans = span.chunks_exact_mut(8);
+        let mut sources = colors.chunks_exact(8);
+        for (span, colors) in (&mut spans).zip(&mut sources) {
+            let destination = _mm256_loadu_si256(span.as_ptr().cast());
+            let source = _mm256_shuffle_epi8(_mm256_loadu_si256(colors.as_ptr().cast()), swap_order());
+            _mm256_storeu_si256(span.as_mut_ptr().cast(), blend(source, destination));
+        }
+
+        super::scalar::blend_over_bgra(spans.into_remainder(), sources.remainder());
+    }
+
+    /// Blends eight packed pixels, see `scalar::blend_pixel`.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn blend(source: __m256i, destination: __m256i) -> __m256i {
+        let zero = _mm256_setzero_si256();
+        let low = mix(_mm256_unpacklo_epi8(source, zero), _mm256_unpacklo_epi8(destination, zero));
+        let high = mix(_mm256_unpackhi_epi8(source, zero), _mm256_unpackhi_epi8(destination, zero));
+
+        // Saturates, like `scalar::blend_pixel`.
+        _mm256_packus_epi16(low, high)
+    }
+
+    /// Blends four pixels with 16-bit channels, see `scalar::blend_pixel`.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn mix(source: __m256i, destination: __m256i) -> __m256i {
+        // Copies the alpha of every pixel to all of its channels.
+        let broadcast_alpha = _mm256_setr_epi8(
+            6, 7, 6, 7, 6, 7, 6, 7, 14, 15, 14, 15, 14, 15, 14, 15,
+            6, 7, 6, 7, 6, 7, 6, 7, 14, 15, 14, 15, 14, 15, 14, 15,
+        );
+        let inverse_alpha = _mm256_sub_epi16(_mm256_set1_epi16(255), _mm256_shuffle_epi8(source, broadcast_alpha));
+
+        // Divides by 255, see `scalar::div_255`.
+        let value = _mm256_mullo_epi16(destination, inverse_alpha);
+        let value = _mm256_add_epi16(_mm256_add_epi16(value, _mm256_set1_epi16(1)), _mm256_srli_epi16::<8>(value));
+
+        _mm256_add_epi16(source, _mm256_srli_epi16::<8>(value))
+    }
+
+    /// The byte order that swaps the red and blue channels of every pixel.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn swap_order() -> __m256i {
+        _mm256_setr_epi8(
+            2, 1, 0, 3, 6, 5, 4, 7, 10, 9, 8, 11, 14, 13, 12, 15,
+            2, 1, 0, 3, 6, 5, 4, 7, 10, 9, 8, 11, 14, 13, 12, 15,
+        )
+    }
+
+    /// # Safety
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn pack_unorm(colors: &[[f32; 4]], packed: &mut [u32]) {
+        let len = colors.len().min(packed.len());
+        let (colors, packed) = (&colors[..len], &mut packed[..len]);
+
+        // After packing, the lanes hold the pixels 0, 2, 0, 2, 1, 3, 1, 3.
+        let order = _mm256_setr_epi32(0, 4, 1, 5, 2, 6, 3, 7);
+
+        let mut sources = colors.chunks_exact(4);
+        let mut destinations = packed.chunks_exact_mut(4);
+        for (colors, packed) in (&mut sources).zip(&mut destinations) {
+            let first = unorm_to_i32(&colors[..2]);
+            let second = unorm_to_i32(&colors[2..]);
+
+            let words = _mm256_packus_epi32(first, second);
+            let bytes = _mm256_packus_epi16(words, words);
+            let pixels = _mm256_permutevar8x32_epi32(bytes, order);
+
+            _mm_storeu_si128(packed.as_mut_ptr().cast(), _mm256_castsi256_si128(pixels));
+        }
+
+        super::scalar::pack_unorm(sources.remainder(), destinations.into_remainder());
+    }
+
+    /// Scales and rounds the channels of two colors.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn unorm_to_i32(colors: &[[f32; 4]]) -> __m256i {
+        let scale = _mm256_set1_ps(255.0);
+
+        let value = _mm256_loadu_ps(colors.as_ptr().cast());
+        let value = _mm256_add_ps(_mm256_mul_ps(value, scale), _mm256_set1_ps(0.5));
+        let value = _mm256_min_ps(_mm256_max_ps(value, _mm256_setzero_ps()), scale);
+        _mm256_cvttps_epi32(value)
+    }
+
+    /// # Safety
+    /// The CPU must support AVX2.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn fetch_nearest(row: &[u8], u: f32, step: f32, texels: &mut [u32]) {
+        let width = row.len() / 4;
+        let (u_x8, step_x8) = (_mm256_set1_ps(u), _mm256_set1_ps(step));
+        let width_x8 = _mm256_set1_ps(width as f32);
+        let last = _mm256_set1_ps((width - 1) as f32);
+
+        let mut pixel = _mm256_setr_ps(0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0);
+
+        let done = texels.len() / 8 * 8;
+
+        let mut chunks = texels.chunks_exact_mut(8);
+        for chunk in &mut chunks {
+            // Separate multiplications and additions, as in the scalar
+            // version, so that the rounding is identical.
+            let x = _mm256_mul_ps(_mm256_add_ps(u_x8, _mm256_mul_ps(pixel, step_x8)), width_x8);
+
+            // Clamps before truncating, which also maps NaN to zero.
+            let index = _mm256_cvttps_epi32(_mm256_min_ps(_mm256_max_ps(x, _mm256_setzero_ps()), last));
+
+            // SAFETY: the indices are clamped to the row.
+            let texels = _mm256_i32gather_epi32::<4>(row.as_ptr().cast(), index);
+            _mm256_storeu_si256(chunk.as_mut_ptr().cast(), texels);
+
+            pixel = _mm256_add_ps(pixel, _mm256_set1_ps(8.0));
+        }
+
+        super::scalar::fetch_nearest_from(row, u, step, done, chunks.into_remainder());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Odd lengths, so that every version has a tail to handle.
+    const LENGTHS: [usize; 6] = [0, 1, 7, 9, 31, 67];
+
+    fn colors(len: usize) -> Vec<u32> {
+        (0..len as u32).map(|i| i.wrapping_mul(0x9E37_79B9)).collect()
+    }
+
+    /// Slices that start one element into a buffer, so they're unaligned.
+    fn unaligned(len: usize, value: impl Fn(usize) -> u32) -> Vec<u32> {
+        (0..len + 1).map(value).collect()
+    }
+
+    #[test]
+    fn fill_matches_scalar() {
+        for len in LENGTHS {
+            let mut scalar = unaligned(len, |_| 0);
+            let mut simd = scalar.clone();
+            scalar::fill(&mut scalar[1..], 0x8040_20FF);
+            fill(&mut simd[1..], 0x8040_20FF);
+            assert_eq!(scalar, simd, "length {len}");
+        }
+    }
+
+    #[test]
+    fn blend_over_matches_scalar() {
+        for len in LENGTHS {
+            let colors = unaligned(len, |i| (i as u32).wrapping_mul(0x9E37_79B9));
+            let mut scalar = unaligned(len, |i| !(i as u32).wrapping_mul(0x2545_F491));
+            let mut simd = scalar.clone();
+            scalar::blend_over(&mut scalar[1..], &colors[1..]);
+            blend_over(&mut simd[1..], &colors[1..]);
+            assert_eq!(scalar, simd, "length {len}");
+        }
+    }
+
+    #[test]
+    fn blend_over_bgra_matches_scalar() {
+        for len in LENGTHS {
+            let colors = unaligned(len, |i| (i as u32).wrapping_mul(0x9E37_79B9));
+            let mut scalar = unaligned(len, |i| !(i as u32).wrapping_mul(0x2545_F491));
+            let mut simd = scalar.clone();
+            scalar::blend_over_bgra(&mut scalar[1..], &colors[1..]);
+            blend_over_bgra(&mut simd[1..], &colors[1..]);
+            assert_eq!(scalar, simd, "length {len}");
+
+            // Equal to blending in RGBA8 order.
+            let mut rgba = unaligned(len, |i| scalar::swap_pixel(!(i as u32).wrapping_mul(0x2545_F491)));
+            scalar::blend_over(&mut rgba[1..], &colors[1..]);
+            assert!(rgba.iter().zip(&simd).all(|(rgba, bgra)| *rgba == scalar::swap_pixel(*bgra)), "length {len}");
+        }
+    }
+
+    #[test]
+    fn pack_unorm_matches_scalar() {
+        for len in LENGTHS {
+            // Includes values outside of [0, 1], which are clamped.
+            let unorm: Vec<[f32; 4]> = (0..len + 1)
+                .map(|i| [i as f32 / 7.0 - 0.5, 0.25, i as f32 / 31.0, 1.0 - i as f32 / 67.0])
+                .collect();
+            let mut scalar = vec![0; len];
+            let mut simd = vec![0; len];
+            scalar::pack_unorm(&unorm[1..], &mut scalar);
+            pack_unorm(&unorm[1..], &mut simd);
+            assert_eq!(scalar, simd, "length {len}");
+        }
+    }
+
+    #[test]
+    fn fetch_nearest_matches_scalar() {
+        let texture: Vec<u8> = colors(37).iter().flat_map(|c| c.to_le_bytes()).collect();
+
+        for len in LENGTHS {
+            for (u, step) in [(0.0, 0.01), (0.1, 0.8 / 1000.0), (-0.3, 0.05), (0.9, 1.0 / 3.0)] {
+                let mut scalar = unaligned(len, |_| 0);
+                let mut simd = scalar.clone();
+                scalar::fetch_nearest(&texture, u, step, &mut scalar[1..]);
+                fetch_nearest(&texture, u, step, &mut simd[1..]);
+                assert_eq!(scalar, simd, "length {len}, u {u}, step {step}");
+            }
+        }
+    }
+}
@@ -0,0 +1,464 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::{
+    fmt::Write as _,
+    io::{stdout, Write as _},
+    rc::Rc,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use euclid::default::{Rect, Size2D};
+use image::{Rgb, Rgba32FImage, RgbaImage};
+use terminal_size::{terminal_size, Height, Width};
+
+use crate::{gfx::painter::PainterImplementation, BackendInfo, Capabilities, Color, CompressedImage, ContextImplementation, Image, ImageLoadError};
+
+use super::{
+    painter::{SoftwarePainter, SoftwareTarget},
+    PixelTarget,
+    SoftwareResources,
+    TargetFormat,
+};
+
+/// The size of a character cell in pixels, used by the graphics protocols.
+/// Terminals don't reliably report it, so a common font size is assumed.
+const CELL_SIZE: Size2D<u32> = Size2D::new(8, 16);
+
+/// The number of bytes of base64 per kitty graphics escape sequence.
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// How a [`TerminalContext`] writes the rasterized frame to the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalMode {
+    /// Two pixels per character cell using `▀`, with 24-bit ANSI colors.
+    /// Works in nearly every modern terminal.
+    HalfBlocks,
+
+    /// 2×4 pixels per character cell using Braille dots. Each cell has only
+    /// two colors, so this trades color accuracy for resolution.
+    Braille,
+
+    /// The DEC sixel graphics protocol, quantized to 216 colors.
+    Sixel,
+
+    /// The kitty graphics protocol, in full color.
+    Kitty,
+}
+
+/// A [`Context`](crate::Context) that rasterizes every frame with the
+/// software painter and writes it to standard output, for previewing scenes
+/// without a display server.
+pub struct TerminalContext {
+    mode: TerminalMode,
+
+    /// The size in character cells, used when standard output isn't a
+    /// terminal.
+    size: Size2D<u32>,
+    resources: Rc<SoftwareResources>,
+}
+
+impl TerminalContext {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(mode: TerminalMode) -> Box<dyn ContextImplementation> {
+        Box::new(Self {
+            mode,
+            size: Size2D::new(80, 23),
+            resources: SoftwareResources::new(),
+        })
+    }
+
+    /// The size of the terminal in character cells. The last row is kept free,
+    /// so the cursor resting below the frame doesn't scroll it.
+    fn cells(&self) -> Size2D<u32> {
+        match terminal_size() {
+            Some((Width(columns), Height(rows))) => Size2D::new(columns as u32, (rows as u32).saturating_sub(1)),
+            None => self.size,
+        }
+    }
+
+    /// The number of pixels in a character cell.
+    fn cell_size(&self) -> Size2D<u32> {
+        match self.mode {
+            TerminalMode::HalfBlocks => Size2D::new(1, 2),
+            TerminalMode::Braille => Size2D::new(2, 4),
+            TerminalMode::Sixel | TerminalMode::Kitty => CELL_SIZE,
+        }
+    }
+
+    fn pixel_size(&self, cells: Size2D<u32>) -> Size2D<u32> {
+        let cell = self.cell_size();
+        Size2D::new(cells.width * cell.width, cells.height * cell.height)
+    }
+}
+
+impl ContextImplementation for TerminalContext {
+    /// The size is in pixels, which is rounded up to whole character cells.
+    /// It only applies when standard output isn't a terminal.
+    fn resize(&mut self, size: Size2D<u32>) {
+        let cell = self.cell_size();
+        self.size = Size2D::new(size.width.div_ceil(cell.width), size.height.div_ceil(cell.height));
+    }
+
+    fn create_image(&mut self, img: RgbaImage) -> Result<Image, ImageLoadError> {
+        Ok(self.resources.create_image(img))
+    }
+
+    fn update_image(&mut self, image: Image, region: Rect<u32>, pixels: RgbaImage) -> Result<(), ImageLoadError> {
+        self.resources.update_image(image, region, pixels)
+    }
+
+    fn unload_image(&mut self, image: Image) {
+        self.resources.unload_image(image);
+    }
+
+    fn paint_frame(&self, scale_factor: f64, f: &mut dyn FnMut(&mut dyn PainterImplementation)) {
+        let size = self.pixel_size(self.cells());
+        if size.is_empty() {
+            return;
+        }
+
+        let mut frame = RgbaImage::new(size.width, size.height);
+        let target = SoftwareTarget::packed(&mut frame, size, TargetFormat::Rgba8);
+
+        let mut painter = SoftwarePainter::new(scale_factor, target, Rc::clone(&self.resources), Color::BLACK);
+        f(&mut painter);
+        painter.finish();
+
+        let output = match self.mode {
+            TerminalMode::HalfBlocks => encode_half_blocks(&frame),
+            TerminalMode::Braille => encode_braille(&frame),
+            TerminalMode::Sixel => encode_sixel(&frame),
+            TerminalMode::Kitty => encode_kitty(&frame),
+        };
+
+        let mut stdout = stdout().lock();
+        _ = stdout.write_all(output.as_bytes());
+        _ = stdout.flush();
+    }
+
+    fn create_hdr_image(&mut self, img: Rgba32FImage) -> Result<Image, ImageLoadError> {
+        Ok(self.resources.create_hdr_image(img))
+    }
+
+    fn create_compressed_image(&mut self, image: CompressedImage) -> Result<Image, ImageLoadError> {
+        self.create_image(image.decode()?)
+    }
+
+    fn paint_offscreen_hdr(
+        &self,
+        size: Size2D<u32>,
+        f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<Rgba32FImage, ImageLoadError> {
+        Ok(self.resources.paint_offscreen_hdr(size, f))
+    }
+
+    fn paint_pixels(
+        &self,
+        target: &mut dyn PixelTarget,
+        scale_factor: f64,
+        f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<(), ImageLoadError> {
+        self.resources.paint_pixels(target, scale_factor, f)
+    }
+
+    fn backend_info(&self) -> BackendInfo {
+        BackendInfo::new("terminal", Some(format!("{:?}", self.mode)))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::cpu()
+    }
+}
+
+fn rgb(frame: &RgbaImage, x: u32, y: u32) -> Rgb<u8> {
+    let [r, g, b, _] = frame.get_pixel(x, y).0;
+    Rgb([r, g, b])
+}
+
+/// Moves the cursor to the top left, so every frame overwrites the last.
+fn home() -> String {
+    String::from("\x1b[H")
+}
+
+fn encode_half_blocks(frame: &RgbaImage) -> String {
+    let mut output = home();
+
+    for y in (0..frame.height()).step_by(2) {
+        for x in 0..frame.width() {
+            let Rgb([tr, tg, tb]) = rgb(frame, x, y);
+            let Rgb([br, bg, bb]) = rgb(frame, x, y + 1);
+            _ = write!(output, "\x1b[38;2;{tr};{tg};{tb}m\x1b[48;2;{br};{bg};{bb}m▀");
+        }
+        output.push_str("\x1b[0m\r\n");
+    }
+
+    output
+}
+
+/// Every cell is split into the dots brighter than its average, drawn in
+/// their mean color, and the rest, which become the background.
+fn encode_braille(frame: &RgbaImage) -> String {
+    /// The bit of the dot at `[y][x]` in the Braille block.
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+    let mut output = home();
+
+    for cell_y in (0..frame.height()).step_by(4) {
+        for cell_x in (0..frame.width()).step_by(2) {
+            let pixels: Vec<_> = (0..4)
+                .flat_map(|y| (0..2).map(move |x| (x, y)))
+                .map(|(x, y)| ((x, y), rgb(frame, cell_x + x, cell_y + y)))
+                .collect();
+
+            let threshold = pixels.iter().map(|(_, pixel)| luma(*pixel)).sum::<u32>() / 8;
+
+            let mut dots = 0;
+            let mut foreground = Average::default();
+            let mut background = Average::default();
+            for ((x, y), pixel) in pixels {
+                if luma(pixel) > threshold {
+                    dots |= DOTS[y as usize][x as usize];
+                    foreground.add(pixel);
+                } else {
+                    background.add(pixel);
+                }
+            }
+
+            let Rgb([fr, fg, fb]) = foreground.get();
+            let Rgb([br, bg, bb]) = background.get();
+            let character = char::from_u32(0x2800 + dots).unwrap();
+            _ = write!(output, "\x1b[38;2;{fr};{fg};{fb}m\x1b[48;2;{br};{bg};{bb}m{character}");
+        }
+        output.push_str("\x1b[0m\r\n");
+    }
+
+    output
+}
+
+fn luma(Rgb([r, g, b]): Rgb<u8>) -> u32 {
+    (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000
+}
+
+#[derive(Default)]
+struct Average {
+    sum: [u32; 3],
+    count: u32,
+}
+
+impl Average {
+    fn add(&mut self, Rgb(pixel): Rgb<u8>) {
+        for (sum, value) in self.sum.iter_mut().zip(pixel) {
+            *sum += value as u32;
+        }
+        self.count += 1;
+    }
+
+    fn get(&self) -> Rgb<u8> {
+        let count = self.count.max(1);
+        Rgb(self.sum.map(|sum| (sum / count) as u8))
+    }
+}
+
+/// Each band of six rows is written once per color that occurs in it, with
+/// runs of the same sixel compressed.
+fn encode_sixel(frame: &RgbaImage) -> String {
+    let mut output = home();
+    _ = write!(output, "\x1bPq\"1;1;{};{}", frame.width(), frame.height());
+
+    for index in 0..216 {
+        let [r, g, b] = [index / 36, index / 6 % 6, index % 6].map(|level| level * 100 / 5);
+        _ = write!(output, "#{index};2;{r};{g};{b}");
+    }
+
+    let width = frame.width() as usize;
+    let mut indices = vec![0_u8; width * 6];
+
+    for band in (0..frame.height()).step_by(6) {
+        let rows = (frame.height() - band).min(6);
+
+        let mut used = [false; 216];
+        for y in 0..rows {
+            for x in 0..frame.width() {
+                let index = palette_index(rgb(frame, x, band + y));
+                indices[y as usize * width + x as usize] = index;
+                used[index as usize] = true;
+            }
+        }
+
+        for index in (0..216).filter(|index| used[*index as usize]) {
+            _ = write!(output, "#{index}");
+
+            let sixels = (0..width).map(|x| {
+                (0..rows as usize)
+                    .filter(|y| indices[y * width + x] == index)
+                    .fold(0, |bits, y| bits | 1 << y)
+            });
+            write_runs(&mut output, sixels);
+
+            output.push('$');
+        }
+
+        output.push('-');
+    }
+
+    output.push_str("\x1b\\");
+    output
+}
+
+fn palette_index(Rgb([r, g, b]): Rgb<u8>) -> u8 {
+    let level = |value: u8| (value as u32 * 5 + 127) / 255;
+    (level(r) * 36 + level(g) * 6 + level(b)) as u8
+}
+
+/// Writes the sixels, using `!<count>` for repeated ones.
+fn write_runs(output: &mut String, sixels: impl Iterator<Item = u8>) {
+    let mut sixels = sixels.peekable();
+
+    while let Some(sixel) = sixels.next() {
+        let mut count = 1;
+        while sixels.next_if_eq(&sixel).is_some() {
+            count += 1;
+        }
+
+        let character = char::from(0x3F + sixel);
+        if count > 3 {
+            _ = write!(output, "!{count}{character}");
+        } else {
+            (0..count).for_each(|_| output.push(character));
+        }
+    }
+}
+
+/// Replaces the image of the previous frame, which is sent as raw RGBA.
+fn encode_kitty(frame: &RgbaImage) -> String {
+    let mut output = home();
+    output.push_str("\x1b_Ga=d,q=2\x1b\\");
+
+    let data = STANDARD.encode(frame.as_raw());
+    let mut chunks = data.as_bytes().chunks(KITTY_CHUNK_SIZE).peekable();
+    let mut first = true;
+
+    while let Some(chunk) = chunks.next() {
+        let more = u8::from(chunks.peek().is_some());
+        let chunk = std::str::from_utf8(chunk).unwrap();
+
+        if first {
+            _ = write!(output, "\x1b_Ga=T,f=32,s={},v={},C=1,q=2,m={more};{chunk}\x1b\\", frame.width(), frame.height());
+            first = false;
+        } else {
+            _ = write!(output, "\x1b_Gm={more};{chunk}\x1b\\");
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 0xFF]);
+    const WHITE: Rgba<u8> = Rgba([0xFF; 4]);
+
+    #[test]
+    fn half_blocks() {
+        let colors = [[Rgba([0xFF, 0, 0, 0xFF]), Rgba([0, 0xFF, 0, 0xFF])], [Rgba([0, 0, 0xFF, 0xFF]), WHITE]];
+        let frame = RgbaImage::from_fn(2, 2, |x, y| colors[y as usize][x as usize]);
+
+        assert_eq!(
+            encode_half_blocks(&frame),
+            "\x1b[H\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀\x1b[38;2;0;255;0m\x1b[48;2;255;255;255m▀\x1b[0m\r\n",
+        );
+    }
+
+    #[test]
+    fn braille() {
+        let frame = RgbaImage::from_fn(2, 4, |x, _| if x == 0 { WHITE } else { BLACK });
+        assert_eq!(encode_braille(&frame), "\x1b[H\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m⡇\x1b[0m\r\n");
+
+        let frame = RgbaImage::from_fn(2, 4, |x, y| if (x, y) == (1, 3) { WHITE } else { BLACK });
+        assert_eq!(encode_braille(&frame), "\x1b[H\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m⢀\x1b[0m\r\n");
+    }
+
+    #[test]
+    fn palette_indices() {
+        assert_eq!(palette_index(Rgb([0, 0, 0])), 0);
+        assert_eq!(palette_index(Rgb([0, 0, 51])), 1);
+        assert_eq!(palette_index(Rgb([0xFF, 0, 0])), 180);
+        assert_eq!(palette_index(Rgb([0x80, 0x80, 0x80])), 129);
+        assert_eq!(palette_index(Rgb([0xFF, 0xFF, 0xFF])), 215);
+    }
+
+    #[test]
+    fn runs_of_more_than_three_are_compressed() {
+        let mut output = String::new();
+        write_runs(&mut output, [0, 0, 0, 0, 1, 1, 1, 2, 0x3F, 0x3F, 0x3F, 0x3F, 0x3F].into_iter());
+        assert_eq!(output, "!4?@@@A!5~");
+    }
+
+    #[test]
+    fn sixel() {
+        let frame = RgbaImage::from_pixel(5, 1, BLACK);
+        let output = encode_sixel(&frame);
+        assert!(output.starts_with("\x1b[H\x1bPq\"1;1;5;1#0;2;0;0;0#1;2;0;0;20#2;2;0;0;40"), "{output:?}");
+        assert!(output.ends_with("#215;2;100;100;100#0!5@$-\x1b\\"), "{output:?}");
+
+        // Every color of a band gets its own pass, with a bit per row.
+        let frame = RgbaImage::from_fn(1, 7, |_, y| if y % 2 == 0 { BLACK } else { WHITE });
+        let output = encode_sixel(&frame);
+        let bands = &output[output.find("#215;2;100;100;100").unwrap() + 18..];
+        assert_eq!(bands, "#0T$#215i$-#0@$-\x1b\\");
+    }
+
+    /// The payloads of the escape sequences after the delete command.
+    fn kitty_chunks(output: &str) -> Vec<(&str, &str)> {
+        let output = output.strip_prefix("\x1b[H\x1b_Ga=d,q=2\x1b\\").unwrap();
+        output.split_terminator("\x1b\\")
+            .map(|sequence| sequence.strip_prefix("\x1b_G").unwrap().split_once(';').unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn kitty() {
+        let frame = RgbaImage::from_pixel(2, 1, WHITE);
+        let output = encode_kitty(&frame);
+        assert_eq!(kitty_chunks(&output), [("a=T,f=32,s=2,v=1,C=1,q=2,m=0", "//////////8=")]);
+    }
+
+    #[test]
+    fn kitty_chunks_large_frames() {
+        // 4096 bytes of pixels take 5464 bytes of base64.
+        let frame = RgbaImage::from_fn(32, 32, |x, y| Rgba([x as u8, y as u8, 0, 0xFF]));
+        let output = encode_kitty(&frame);
+        let chunks = kitty_chunks(&output);
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].0, "a=T,f=32,s=32,v=32,C=1,q=2,m=1");
+        assert_eq!(chunks[0].1.len(), KITTY_CHUNK_SIZE);
+        assert_eq!(chunks[1].0, "m=0");
+        assert_eq!(chunks[1].1.len(), 5464 - KITTY_CHUNK_SIZE);
+
+        let data: String = chunks.iter().map(|(_, payload)| *payload).collect();
+        assert_eq!(STANDARD.decode(data).unwrap(), frame.into_raw());
+    }
+
+    #[test]
+    fn resize_converts_pixels_to_cells() {
+        let mut context = TerminalContext {
+            mode: TerminalMode::Sixel,
+            size: Size2D::zero(),
+            resources: SoftwareResources::new(),
+        };
+
+        context.resize(Size2D::new(640, 385));
+        assert_eq!(context.size, Size2D::new(80, 25));
+        assert_eq!(context.pixel_size(context.size), Size2D::new(640, 400));
+
+        context.mode = TerminalMode::HalfBlocks;
+        context.resize(Size2D::new(80, 47));
+        assert_eq!(context.size, Size2D::new(80, 24));
+    }
+}
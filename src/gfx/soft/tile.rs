@@ -0,0 +1,334 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! Commands are recorded for the whole frame, binned into the tiles they
+//! overlap and then every tile is rasterized on its own thread. A tile only
+//! touches its own pixels and runs its commands in recording order, so the
+//! output doesn't depend on the number of threads.
+
+use std::sync::Arc;
+
+use euclid::default::{Point2D, Rect, Size2D, Vector2D};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{SamplingQuality, ToneMapping};
+
+use super::{format::TargetFormat, sampler::Mipmaps, simd};
+
+/// The width and height of a tile, in pixels.
+pub(super) const TILE_SIZE: usize = 64;
+
+/// The widest texture that nearest sampling fetches from with
+/// [`simd::fetch_nearest`], of which the texel indices are exact in `f32`.
+const MAX_NEAREST_WIDTH: u32 = 1 << 24;
+
+/// A recorded paint operation. Rectangles are in pixels of the target and
+/// may extend outside of it.
+pub(super) enum Command {
+    /// A packed, premultiplied color, see [`simd`].
+    Fill {
+        rect: Rect<isize>,
+        color: u32,
+    },
+
+    Image {
+        rect: Rect<isize>,
+        source: Rect<f32>,
+        footprint: Vector2D<f32>,
+        sampling: SamplingQuality,
+        mipmaps: Arc<Mipmaps<u8>>,
+    },
+
+    HdrImage {
+        rect: Rect<isize>,
+        source: Rect<f32>,
+        footprint: Vector2D<f32>,
+        sampling: SamplingQuality,
+        exposure: f32,
+        tone_mapping: ToneMapping,
+        mipmaps: Arc<Mipmaps<f32>>,
+    },
+
+    /// An SVG document, rasterized and premultiplied while recording.
+    #[cfg(feature = "svg")]
+    Pixels {
+        rect: Rect<isize>,
+        pixels: image::RgbaImage,
+    },
+}
+
+impl Command {
+    fn rect(&self) -> Rect<isize> {
+        match self {
+            Self::Fill { rect, .. } => *rect,
+            Self::Image { rect, .. } => *rect,
+            Self::HdrImage { rect, .. } => *rect,
+            #[cfg(feature = "svg")]
+            Self::Pixels { rect, .. } => *rect,
+        }
+    }
+}
+
+/// Rasterizes the commands into the pixels of a `size` target.
+pub(super) fn rasterize(pixels: &mut [u8], size: Size2D<u32>, stride: usize, format: TargetFormat, commands: &[Command]) {
+    if size.is_empty() {
+        return;
+    }
+
+    let mut tiles = split(pixels, size, stride, format);
+    let columns = (size.width as usize).div_ceil(TILE_SIZE);
+
+    for (index, command) in commands.iter().enumerate() {
+        let Some(rect) = command.rect().intersection(&Rect::from_size(size.cast())) else {
+            continue;
+        };
+
+        let (min, max) = (rect.min().cast::<usize>() / TILE_SIZE, (rect.max().cast::<usize>() - Size2D::new(1, 1)) / TILE_SIZE);
+        for row in min.y..=max.y {
+            for column in min.x..=max.x {
+                tiles[row * columns + column].commands.push(index);
+            }
+        }
+    }
+
+    tiles.into_par_iter().for_each(|tile| tile.rasterize(format, commands));
+}
+
+/// Splits the target into tiles, row by row and left to right.
+fn split(pixels: &mut [u8], size: Size2D<u32>, stride: usize, format: TargetFormat) -> Vec<Tile<'_>> {
+    let (width, height) = (size.width as usize, size.height as usize);
+    let columns = width.div_ceil(TILE_SIZE);
+
+    let mut tiles: Vec<_> = (0..height.div_ceil(TILE_SIZE))
+        .flat_map(|row| (0..columns).map(move |column| (row, column)))
+        .map(|(row, column)| {
+            let origin = Point2D::new(column * TILE_SIZE, row * TILE_SIZE);
+            let size = Size2D::new(TILE_SIZE.min(width - origin.x), TILE_SIZE.min(height - origin.y));
+
+            Tile {
+                rect: Rect::new(origin, size).cast(),
+                rows: Vec::with_capacity(size.height),
+                commands: Vec::new(),
+            }
+        })
+        .collect();
+
+    let bytes_per_pixel = format.bytes_per_pixel();
+    for (y, row) in pixels.chunks_mut(stride).take(height).enumerate() {
+        for (column, span) in row[..width * bytes_per_pixel].chunks_mut(TILE_SIZE * bytes_per_pixel).enumerate() {
+            tiles[y / TILE_SIZE * columns + column].rows.push(span);
+        }
+    }
+
+    tiles
+}
+
+struct Tile<'t> {
+    rect: Rect<isize>,
+    rows: Vec<&'t mut [u8]>,
+
+    /// The indices of the commands that overlap the tile, in recording order.
+    commands: Vec<usize>,
+}
+
+impl Tile<'_> {
+    fn rasterize(mut self, format: TargetFormat, commands: &[Command]) {
+        for index in std::mem::take(&mut self.commands) {
+            let command = &commands[index];
+            let clip = command.rect().intersection(&self.rect).unwrap();
+
+            match command {
+                Command::Fill { color, .. } => {
+                    for y in clip.y_range() {
+                        format.fill(self.span(format, y, clip), *color);
+                    }
+                }
+
+                Command::Image { rect, source, footprint, sampling, mipmaps } => {
+                    let mut colors = vec![0; clip.width() as usize];
+                    let mut samples = Vec::new();
+
+                    let base = mipmaps.base();
+                    let texel_step = texel_step(*rect, *source);
+                    let nearest = *sampling == SamplingQuality::Nearest && base.width() <= MAX_NEAREST_WIDTH;
+
+                    for y in clip.y_range() {
+                        let (u, v) = first_texel(*rect, clip, *source, texel_step, y);
+
+                        if nearest {
+                            let y = ((v * base.height() as f32) as usize).min(base.height() as usize - 1);
+                            let stride = base.width() as usize * 4;
+                            let row = &base.as_raw()[y * stride..(y + 1) * stride];
+                            simd::fetch_nearest(row, u, texel_step.x, &mut colors);
+                        } else {
+                            samples.clear();
+                            samples.extend((0..colors.len()).map(|i| {
+                                mipmaps.sample(u + i as f32 * texel_step.x, v, *footprint, *sampling)
+                            }));
+                            simd::pack_unorm(&samples, &mut colors);
+                        }
+
+                        format.blend(self.span(format, y, clip), &colors);
+                    }
+                }
+
+                Command::HdrImage { rect, source, footprint, sampling, exposure, tone_mapping, mipmaps } => {
+                    let texel_step = texel_step(*rect, *source);
+                    let mut colors = Vec::with_capacity(clip.width() as usize);
+
+                    for y in clip.y_range() {
+                        let (u, v) = first_texel(*rect, clip, *source, texel_step, y);
+
+                        // Tone mapping isn't linear, so it's applied to the
+                        // straight alpha color.
+                        colors.clear();
+                        colors.extend((0..clip.width()).map(|i| {
+                            let [r, g, b, a] = mipmaps.sample(u + i as f32 * texel_step.x, v, *footprint, *sampling);
+                            if a <= 0.0 {
+                                return [0.0; 4];
+                            }
+
+                            let map = |channel: f32| tone_mapping.apply(channel / a, *exposure) * a;
+                            [map(r), map(g), map(b), a]
+                        }));
+
+                        format.blend_linear(self.span(format, y, clip), &colors);
+                    }
+                }
+
+                #[cfg(feature = "svg")]
+                Command::Pixels { rect, pixels } => {
+                    let mut colors = vec![0; clip.width() as usize];
+                    let stride = pixels.width() as usize * 4;
+                    let start = (clip.min_x() - rect.min_x()) as usize * 4;
+
+                    for y in clip.y_range() {
+                        let offset = (y - rect.min_y()) as usize * stride + start;
+                        let row = &pixels.as_raw()[offset..offset + colors.len() * 4];
+                        for (color, pixel) in colors.iter_mut().zip(row.chunks_exact(4)) {
+                            *color = u32::from_le_bytes(pixel.try_into().unwrap());
+                        }
+
+                        format.blend(self.span(format, y, clip), &colors);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The pixels of row `y` within the horizontal range of `clip`.
+    fn span(&mut self, format: TargetFormat, y: isize, clip: Rect<isize>) -> &mut [u8] {
+        let start = (clip.min_x() - self.rect.min_x()) as usize * format.bytes_per_pixel();
+        let end = (clip.max_x() - self.rect.min_x()) as usize * format.bytes_per_pixel();
+        &mut self.rows[(y - self.rect.min_y()) as usize][start..end]
+    }
+}
+
+/// The distance between the texture coordinates of neighbouring pixels, when
+/// the normalized `source` rectangle of a texture is stretched over `rect`.
+fn texel_step(rect: Rect<isize>, source: Rect<f32>) -> Vector2D<f32> {
+    Vector2D::new(source.width() / rect.width() as f32, source.height() / rect.height() as f32)
+}
+
+/// The normalized texture coordinates of the center of the first pixel of
+/// `clip` on row `y`.
+fn first_texel(rect: Rect<isize>, clip: Rect<isize>, source: Rect<f32>, step: Vector2D<f32>, y: isize) -> (f32, f32) {
+    (
+        source.min_x() + ((clip.min_x() - rect.min_x()) as f32 + 0.5) * step.x,
+        source.min_y() + ((y - rect.min_y()) as f32 + 0.5) * step.y,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, Rgba32FImage, RgbaImage};
+
+    use super::*;
+
+    fn commands() -> Vec<Command> {
+        let mut texture = Mipmaps::new(RgbaImage::from_fn(37, 23, |x, y| {
+            let alpha = (x * 7 + y * 3) as u8 | 0x80;
+            Rgba([(x * 11) as u8 & alpha, (y * 13) as u8 & alpha, 0x40 & alpha, alpha])
+        }));
+        texture.regenerate();
+        let texture = Arc::new(texture);
+
+        let mut hdr = Mipmaps::new(Rgba32FImage::from_fn(9, 5, |x, y| Rgba([x as f32, y as f32 * 0.5, 2.0, 1.0])));
+        hdr.regenerate();
+        let hdr = Arc::new(hdr);
+
+        let rect = |x, y, width, height| Rect::new(Point2D::new(x, y), Size2D::new(width, height));
+        let image = |rect, sampling| Command::Image {
+            rect,
+            source: Rect::new(Point2D::new(0.1, 0.0), Size2D::new(0.8, 1.0)),
+            footprint: Vector2D::new(0.3, 0.3),
+            sampling,
+            mipmaps: Arc::clone(&texture),
+        };
+
+        vec![
+            Command::Fill { rect: rect(-20, -20, 500, 500), color: 0xFF20_4060 },
+            Command::Fill { rect: rect(60, 30, 10, 100), color: 0x8000_4080 },
+            image(rect(-15, 50, 90, 80), SamplingQuality::Nearest),
+            image(rect(63, 63, 130, 130), SamplingQuality::Linear),
+            image(rect(120, -30, 40, 200), SamplingQuality::Trilinear),
+            image(rect(5, 100, 250, 20), SamplingQuality::Anisotropic),
+            Command::HdrImage {
+                rect: rect(100, 10, 110, 70),
+                source: Rect::from_size(Size2D::new(1.0, 1.0)),
+                footprint: Vector2D::new(0.1, 0.1),
+                sampling: SamplingQuality::Linear,
+                exposure: -1.0,
+                tone_mapping: ToneMapping::Aces,
+                mipmaps: hdr,
+            },
+            Command::Fill { rect: rect(190, 140, 30, 30), color: 0x40FF_FFFF },
+        ]
+    }
+
+    fn rasterize_with_threads(threads: usize, format: TargetFormat) -> Vec<u8> {
+        let size = Size2D::new(200, 150);
+        let stride = size.width as usize * format.bytes_per_pixel() + 12;
+        let mut pixels = vec![0; stride * size.height as usize];
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        pool.install(|| rasterize(&mut pixels, size, stride, format, &commands()));
+        pixels
+    }
+
+    #[test]
+    fn output_does_not_depend_on_thread_count() {
+        for format in [TargetFormat::Rgba8, TargetFormat::Bgra8, TargetFormat::Rgb565, TargetFormat::RgbaF32] {
+            let single = rasterize_with_threads(1, format);
+            assert!(single.iter().any(|byte| *byte != 0));
+
+            // The padding at the end of every row is left alone.
+            let width = 200 * format.bytes_per_pixel();
+            assert!(single.chunks(width + 12).all(|row| row[width..].iter().all(|byte| *byte == 0)));
+
+            for threads in [2, 3, 8] {
+                assert!(single == rasterize_with_threads(threads, format), "{format:?} with {threads} threads");
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_fast_path_matches_sampler() {
+        let texture = Mipmaps::new(RgbaImage::from_fn(37, 1, |x, _| Rgba([x as u8, 0, 0, 255])));
+        let row = texture.base().as_raw();
+
+        // Steps that aren't exact in fixed point, over spans of a tile and of
+        // a full row, so that errors would accumulate.
+        for (u, step) in [(0.1, 0.8 / 1000.0), (0.0, 1.0 / 3.0 / 64.0), (0.37, 1.0 / 3840.0), (-0.2, 0.01)] {
+            for len in [64, 1001, 3840] {
+                let mut texels = vec![0; len];
+                simd::fetch_nearest(row, u, step, &mut texels);
+
+                for (i, texel) in texels.iter().enumerate() {
+                    let [r, ..] = texture.sample(u + i as f32 * step, 0.0, Vector2D::new(1.0, 1.0), SamplingQuality::Nearest);
+                    assert_eq!(*texel & 0xFF, (r * 255.0).round() as u32, "u {u}, step {step}, pixel {i}");
+                }
+            }
+        }
+    }
+}
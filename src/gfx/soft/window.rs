@@ -0,0 +1,189 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::{cell::RefCell, collections::HashMap, num::NonZero, rc::Rc};
+
+use euclid::default::{Rect, Size2D};
+use image::{Rgba32FImage, RgbaImage};
+use softbuffer::Surface;
+use winit::{
+    event_loop::EventLoop,
+    window::{Window, WindowId},
+};
+
+use crate::{gfx::painter::PainterImplementation, BackendInfo, Capabilities, Color, CompressedImage, ContextBuilder, ContextCreationError, ContextImplementation, EventTy, Image, ImageLoadError};
+
+use super::{
+    painter::{SoftwarePainter, SoftwareTarget},
+    PixelTarget,
+    SoftwareResources,
+    TargetFormat,
+};
+
+/// A window with the surface that is painted into.
+struct SoftwareWindow {
+    window: Rc<Window>,
+    surface: RefCell<Surface<Rc<Window>, Rc<Window>>>,
+}
+
+impl SoftwareWindow {
+    fn new(window: Rc<Window>) -> Result<Self, ContextCreationError> {
+        let context = softbuffer::Context::new(window.clone())?;
+        let surface = Surface::new(&context, window.clone())?;
+
+        Ok(Self {
+            window,
+            surface: RefCell::new(surface),
+        })
+    }
+
+    fn set_size(&self, size: Size2D<u32>) {
+        let Some(width) = NonZero::new(size.width) else {
+            return;
+        };
+
+        let Some(height) = NonZero::new(size.height) else {
+            return;
+        };
+
+        self.surface.borrow_mut().resize(width, height).unwrap();
+    }
+
+    fn get_size_from_window(&self) -> Size2D<u32> {
+        let size = self.window.inner_size();
+        Size2D::new(size.width, size.height)
+    }
+}
+
+/// A software renderer [`Context`], painting into any number of windows.
+pub struct SoftwareContext {
+    /// The window that [`ContextImplementation::paint_frame`] paints.
+    primary: WindowId,
+    windows: HashMap<WindowId, SoftwareWindow>,
+    resources: Rc<SoftwareResources>,
+    clear_color: Color,
+}
+
+impl SoftwareContext {
+    /// The vsync and multisampling of the builder don't apply to software
+    /// rendering.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        event_loop: &EventLoop<EventTy>,
+        builder: &ContextBuilder,
+    ) -> Result<(Box<dyn ContextImplementation>, Rc<Window>), ContextCreationError> {
+        #[allow(deprecated)]
+        let window = event_loop.create_window(builder.window_attributes())?;
+        let window = Rc::new(window);
+
+        Ok((Self::with_window(Rc::clone(&window), builder)?, window))
+    }
+
+    /// Creates the context on an existing window, e.g. one that OpenGL painted
+    /// into.
+    pub fn with_window(window: Rc<Window>, builder: &ContextBuilder) -> Result<Box<dyn ContextImplementation>, ContextCreationError> {
+        let primary = window.id();
+        let windows = HashMap::from([(primary, SoftwareWindow::new(window)?)]);
+
+        Ok(Box::new(Self {
+            primary,
+            windows,
+            resources: SoftwareResources::new(),
+            clear_color: builder.clear_color,
+        }))
+    }
+}
+
+impl ContextImplementation for SoftwareContext {
+    fn resize(&mut self, size: Size2D<u32>) {
+        self.resize_window(self.primary, size);
+    }
+
+    fn create_image(&mut self, img: RgbaImage) -> Result<Image, ImageLoadError> {
+        Ok(self.resources.create_image(img))
+    }
+
+    fn update_image(&mut self, image: Image, region: Rect<u32>, pixels: RgbaImage) -> Result<(), ImageLoadError> {
+        self.resources.update_image(image, region, pixels)
+    }
+
+    fn unload_image(&mut self, image: Image) {
+        self.resources.unload_image(image);
+    }
+
+    fn paint_frame(&self, scale_factor: f64, f: &mut dyn FnMut(&mut dyn PainterImplementation)) {
+        self.paint_window(self.primary, scale_factor, f);
+    }
+
+    fn create_hdr_image(&mut self, img: Rgba32FImage) -> Result<Image, ImageLoadError> {
+        Ok(self.resources.create_hdr_image(img))
+    }
+
+    /// There is no benefit to keeping the blocks in software, so they are
+    /// decoded right away.
+    fn create_compressed_image(&mut self, image: CompressedImage) -> Result<Image, ImageLoadError> {
+        self.create_image(image.decode()?)
+    }
+
+    fn paint_offscreen_hdr(
+        &self,
+        size: Size2D<u32>,
+        f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<Rgba32FImage, ImageLoadError> {
+        Ok(self.resources.paint_offscreen_hdr(size, f))
+    }
+
+    fn paint_pixels(
+        &self,
+        target: &mut dyn PixelTarget,
+        scale_factor: f64,
+        f: &mut dyn FnMut(&mut dyn PainterImplementation),
+    ) -> Result<(), ImageLoadError> {
+        self.resources.paint_pixels(target, scale_factor, f)
+    }
+
+    fn backend_info(&self) -> BackendInfo {
+        BackendInfo::new("software", None)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::cpu()
+    }
+
+    fn attach_window(&mut self, window: Rc<Window>) -> Result<(), ContextCreationError> {
+        self.windows.insert(window.id(), SoftwareWindow::new(window)?);
+        Ok(())
+    }
+
+    fn detach_window(&mut self, id: WindowId) {
+        self.windows.remove(&id);
+    }
+
+    fn resize_window(&mut self, id: WindowId, size: Size2D<u32>) {
+        if let Some(window) = self.windows.get(&id) {
+            window.set_size(size);
+        }
+    }
+
+    fn paint_window(&self, id: WindowId, scale_factor: f64, f: &mut dyn FnMut(&mut dyn PainterImplementation)) {
+        let Some(window) = self.windows.get(&id) else {
+            return;
+        };
+
+        let size = window.get_size_from_window();
+        window.set_size(size);
+
+        let mut surface = window.surface.borrow_mut();
+        let mut buffer = surface.buffer_mut().unwrap();
+
+        // The buffer holds `0xAARRGGBB` pixels, i.e. BGRA on little-endian
+        // platforms.
+        let target = SoftwareTarget::packed(bytemuck::cast_slice_mut(&mut buffer), size, TargetFormat::Bgra8);
+
+        let mut painter = SoftwarePainter::new(scale_factor, target, Rc::clone(&self.resources), self.clear_color);
+        f(&mut painter);
+        painter.finish();
+
+        buffer.present().unwrap();
+    }
+}
@@ -0,0 +1,502 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::{ops::RangeInclusive, time::Duration};
+
+use euclid::default::{Point2D, Rect, Size2D};
+use serde::Deserialize;
+
+use crate::{Image, ImageLoadError};
+
+/// The duration of frames that don't specify one, e.g. in grids and
+/// TexturePacker files.
+pub const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(100);
+
+/// The layout of a sprite sheet without a description file, where every
+/// frame is a cell of the same size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteGrid {
+    pub frame_size: Size2D<u32>,
+
+    /// The space between two adjacent frames.
+    pub spacing: u32,
+
+    /// The space between the border of the image and the frames.
+    pub margin: u32,
+
+    pub frame_duration: Duration,
+}
+
+impl SpriteGrid {
+    #[must_use]
+    pub const fn new(frame_size: Size2D<u32>) -> Self {
+        Self {
+            frame_size,
+            spacing: 0,
+            margin: 0,
+            frame_duration: DEFAULT_FRAME_DURATION,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_spacing(self, spacing: u32) -> Self {
+        Self { spacing, ..self }
+    }
+
+    #[must_use]
+    pub const fn with_margin(self, margin: u32) -> Self {
+        Self { margin, ..self }
+    }
+
+    #[must_use]
+    pub const fn with_frame_duration(self, frame_duration: Duration) -> Self {
+        Self { frame_duration, ..self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpriteFrame {
+    pub name: String,
+
+    /// The area of the frame within the sheet.
+    pub rect: Rect<u32>,
+
+    pub duration: Duration,
+
+    /// The size of the frame before transparent borders were trimmed off.
+    pub source_size: Size2D<u32>,
+
+    /// Where [`Self::rect`] is placed within the untrimmed frame.
+    pub offset: Point2D<u32>,
+}
+
+/// The order in which the frames of an [`AnimationTag`] are played.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationDirection {
+    #[default]
+    Forward,
+    Reverse,
+
+    /// Forward, then backward, without repeating the first and last frame.
+    PingPong,
+
+    /// Backward, then forward, without repeating the first and last frame.
+    PingPongReverse,
+}
+
+/// A named range of frames, e.g. `walk` or `jump`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnimationTag {
+    pub(super) name: String,
+    pub(super) frames: RangeInclusive<usize>,
+    pub(super) direction: AnimationDirection,
+}
+
+impl AnimationTag {
+    /// Returns `None` when the range of frames is empty.
+    #[must_use]
+    pub fn new(name: impl Into<String>, frames: RangeInclusive<usize>, direction: AnimationDirection) -> Option<Self> {
+        if frames.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            name: name.into(),
+            frames,
+            direction,
+        })
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn frames(&self) -> RangeInclusive<usize> {
+        self.frames.clone()
+    }
+
+    #[must_use]
+    pub const fn direction(&self) -> AnimationDirection {
+        self.direction
+    }
+
+    /// The frame indices of a single play of the animation.
+    #[must_use]
+    pub fn sequence(&self) -> Vec<usize> {
+        let forward = self.frames.clone();
+        let backward = forward.clone().rev();
+
+        let (start, end) = (*self.frames.start(), *self.frames.end());
+        let inner = (start + 1)..end;
+
+        match self.direction {
+            AnimationDirection::Forward => forward.collect(),
+            AnimationDirection::Reverse => backward.collect(),
+            AnimationDirection::PingPong => forward.chain(inner.rev()).collect(),
+            AnimationDirection::PingPongReverse => backward.chain(inner).collect(),
+        }
+    }
+}
+
+/// An image containing multiple frames, with named animations.
+#[derive(Debug, Clone)]
+pub struct SpriteSheet {
+    pub(super) image: Image,
+    pub(super) frames: Vec<SpriteFrame>,
+    pub(super) tags: Vec<AnimationTag>,
+}
+
+impl SpriteSheet {
+    /// Slices the image into cells, row by row. The frames are named after
+    /// their index.
+    pub(super) fn from_grid(image: Image, grid: SpriteGrid) -> Self {
+        let cell = grid.frame_size + Size2D::new(grid.spacing, grid.spacing);
+        let columns = (image.size().width.saturating_sub(grid.margin * 2) + grid.spacing) / cell.width.max(1);
+        let rows = (image.size().height.saturating_sub(grid.margin * 2) + grid.spacing) / cell.height.max(1);
+
+        let frames = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (row, column)))
+            .enumerate()
+            .map(|(index, (row, column))| SpriteFrame {
+                name: index.to_string(),
+                rect: Rect::new(
+                    Point2D::new(grid.margin + column * cell.width, grid.margin + row * cell.height),
+                    grid.frame_size,
+                ),
+                duration: grid.frame_duration,
+                source_size: grid.frame_size,
+                offset: Point2D::zero(),
+            })
+            .collect();
+
+        Self {
+            image,
+            frames,
+            tags: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn image(&self) -> Image {
+        self.image
+    }
+
+    #[must_use]
+    pub fn frames(&self) -> &[SpriteFrame] {
+        &self.frames
+    }
+
+    #[must_use]
+    pub fn frame(&self, index: usize) -> Option<&SpriteFrame> {
+        self.frames.get(index)
+    }
+
+    #[must_use]
+    pub fn frame_index(&self, name: &str) -> Option<usize> {
+        self.frames.iter().position(|frame| frame.name == name)
+    }
+
+    #[must_use]
+    pub fn tags(&self) -> &[AnimationTag] {
+        &self.tags
+    }
+
+    #[must_use]
+    pub fn tag(&self, name: &str) -> Option<&AnimationTag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+
+    /// The duration of a single play of the animation. Frames that the sheet
+    /// doesn't have take no time.
+    #[must_use]
+    pub fn tag_duration(&self, tag: &AnimationTag) -> Duration {
+        tag.sequence().into_iter().map(|index| self.frame_duration(index)).sum()
+    }
+
+    /// Returns the index of the frame that should be visible `time` after
+    /// the looping animation started.
+    #[must_use]
+    pub fn frame_at(&self, tag: &AnimationTag, time: Duration) -> usize {
+        let sequence = tag.sequence();
+
+        let duration = self.tag_duration(tag);
+        if duration.is_zero() {
+            return sequence[0];
+        }
+
+        let mut remaining = Duration::from_nanos((time.as_nanos() % duration.as_nanos()) as u64);
+        for &index in &sequence {
+            let delay = self.frame_duration(index);
+            if remaining < delay {
+                return index;
+            }
+            remaining -= delay;
+        }
+
+        sequence[sequence.len() - 1]
+    }
+
+    fn frame_duration(&self, index: usize) -> Duration {
+        self.frames.get(index).map_or(Duration::ZERO, |frame| frame.duration)
+    }
+}
+
+/// The contents of an Aseprite or TexturePacker JSON file, in either the
+/// hash or the array layout.
+pub(super) struct SpriteSheetDescription {
+    /// The path of the image, relative to the JSON file.
+    pub image: String,
+    pub frames: Vec<SpriteFrame>,
+    pub tags: Vec<AnimationTag>,
+}
+
+impl SpriteSheetDescription {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ImageLoadError> {
+        let json: JsonSheet = serde_json::from_slice(bytes)?;
+
+        let frames: Vec<(String, JsonFrame)> = match json.frames {
+            JsonFrames::Array(frames) => frames.into_iter()
+                .map(|frame| (frame.filename.clone(), frame))
+                .collect(),
+            JsonFrames::Hash(frames) => frames.into_iter()
+                .map(|(name, frame)| Ok((name, serde_json::from_value(frame)?)))
+                .collect::<Result<_, serde_json::Error>>()?,
+        };
+
+        let frames = frames.into_iter()
+            .map(|(name, frame)| frame.into_sprite_frame(name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tags = json.meta.frame_tags.into_iter()
+            .map(|tag| {
+                if tag.to >= frames.len() {
+                    return Err(ImageLoadError::InvalidSpriteSheet("animation tag refers to missing frames"));
+                }
+
+                let direction = match tag.direction.as_str() {
+                    "reverse" => AnimationDirection::Reverse,
+                    "pingpong" => AnimationDirection::PingPong,
+                    "pingpong_reverse" => AnimationDirection::PingPongReverse,
+                    _ => AnimationDirection::Forward,
+                };
+
+                AnimationTag::new(tag.name, tag.from..=tag.to, direction)
+                    .ok_or(ImageLoadError::InvalidSpriteSheet("animation tag ends before it starts"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            image: json.meta.image,
+            frames,
+            tags,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonSheet {
+    frames: JsonFrames,
+    meta: JsonMeta,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonFrames {
+    Array(Vec<JsonFrame>),
+
+    /// Kept as raw values, because the order of the keys is the frame order.
+    Hash(serde_json::Map<String, serde_json::Value>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonFrame {
+    #[serde(default)]
+    filename: String,
+    frame: JsonRect,
+    #[serde(default)]
+    rotated: bool,
+    sprite_source_size: Option<JsonRect>,
+    source_size: Option<JsonSize>,
+
+    /// In milliseconds, only written by Aseprite.
+    duration: Option<u64>,
+}
+
+impl JsonFrame {
+    fn into_sprite_frame(self, name: String) -> Result<SpriteFrame, ImageLoadError> {
+        if self.rotated {
+            return Err(ImageLoadError::InvalidSpriteSheet("rotated frames are not supported"));
+        }
+
+        let rect = self.frame.into_rect();
+        let source_size = self.source_size.map_or(rect.size, |size| Size2D::new(size.w, size.h));
+        if source_size.is_empty() {
+            return Err(ImageLoadError::InvalidSpriteSheet("frame has no size"));
+        }
+
+        Ok(SpriteFrame {
+            name,
+            rect,
+            duration: self.duration.map_or(DEFAULT_FRAME_DURATION, Duration::from_millis),
+            source_size,
+            offset: self.sprite_source_size.map_or(Point2D::zero(), |trim| Point2D::new(trim.x, trim.y)),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+impl JsonRect {
+    fn into_rect(self) -> Rect<u32> {
+        Rect::new(Point2D::new(self.x, self.y), Size2D::new(self.w, self.h))
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonSize {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonMeta {
+    image: String,
+    #[serde(default)]
+    frame_tags: Vec<JsonTag>,
+}
+
+#[derive(Deserialize)]
+struct JsonTag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ResourceId, ResourceNamespace};
+
+    use super::*;
+
+    const ASEPRITE: &str = r#"{
+        "frames": {
+            "walk 0": { "frame": { "x": 0, "y": 0, "w": 16, "h": 16 }, "duration": 50 },
+            "walk 1": { "frame": { "x": 16, "y": 0, "w": 14, "h": 15 }, "duration": 150,
+                        "spriteSourceSize": { "x": 1, "y": 1, "w": 14, "h": 15 },
+                        "sourceSize": { "w": 16, "h": 16 } },
+            "walk 2": { "frame": { "x": 32, "y": 0, "w": 16, "h": 16 } }
+        },
+        "meta": {
+            "image": "walk.png",
+            "frameTags": [{ "name": "walk", "from": 0, "to": 2, "direction": "pingpong" }]
+        }
+    }"#;
+
+    fn sheet(description: SpriteSheetDescription) -> SpriteSheet {
+        SpriteSheet {
+            image: Image::new(ResourceId::new(ResourceNamespace::Image, 0), Size2D::new(48, 16)),
+            frames: description.frames,
+            tags: description.tags,
+        }
+    }
+
+    #[test]
+    fn parse_hash_layout() {
+        let description = SpriteSheetDescription::parse(ASEPRITE.as_bytes()).unwrap();
+        assert_eq!(description.image, "walk.png");
+
+        let names: Vec<_> = description.frames.iter().map(|frame| frame.name.as_str()).collect();
+        assert_eq!(names, ["walk 0", "walk 1", "walk 2"]);
+
+        let trimmed = &description.frames[1];
+        assert_eq!(trimmed.rect, Rect::new(Point2D::new(16, 0), Size2D::new(14, 15)));
+        assert_eq!(trimmed.offset, Point2D::new(1, 1));
+        assert_eq!(trimmed.source_size, Size2D::new(16, 16));
+        assert_eq!(trimmed.duration, Duration::from_millis(150));
+        assert_eq!(description.frames[2].duration, DEFAULT_FRAME_DURATION);
+
+        assert_eq!(description.tags, [AnimationTag::new("walk", 0..=2, AnimationDirection::PingPong).unwrap()]);
+    }
+
+    #[test]
+    fn parse_array_layout() {
+        let json = r#"{
+            "frames": [
+                { "filename": "a.png", "frame": { "x": 0, "y": 0, "w": 8, "h": 8 } },
+                { "filename": "b.png", "frame": { "x": 8, "y": 0, "w": 8, "h": 8 } }
+            ],
+            "meta": { "image": "sheet.png" }
+        }"#;
+
+        let description = SpriteSheetDescription::parse(json.as_bytes()).unwrap();
+        let names: Vec<_> = description.frames.iter().map(|frame| frame.name.as_str()).collect();
+        assert_eq!(names, ["a.png", "b.png"]);
+        assert!(description.tags.is_empty());
+    }
+
+    #[test]
+    fn reject_invalid_tags_and_frames() {
+        let reversed = ASEPRITE.replace(r#""from": 0, "to": 2"#, r#""from": 2, "to": 1"#);
+        let missing = ASEPRITE.replace(r#""from": 0, "to": 2"#, r#""from": 0, "to": 3"#);
+        let rotated = ASEPRITE.replace(r#""duration": 50"#, r#""duration": 50, "rotated": true"#);
+        let empty = ASEPRITE.replace(r#""sourceSize": { "w": 16, "h": 16 }"#, r#""sourceSize": { "w": 0, "h": 16 }"#);
+
+        for json in [reversed, missing, rotated, empty] {
+            let result = SpriteSheetDescription::parse(json.as_bytes());
+            assert!(matches!(result, Err(ImageLoadError::InvalidSpriteSheet(_))));
+        }
+
+        assert!(AnimationTag::new("empty", RangeInclusive::new(2, 1), AnimationDirection::Forward).is_none());
+    }
+
+    #[test]
+    fn sequences() {
+        let sequence = |direction| AnimationTag::new("tag", 1..=3, direction).unwrap().sequence();
+        assert_eq!(sequence(AnimationDirection::Forward), [1, 2, 3]);
+        assert_eq!(sequence(AnimationDirection::Reverse), [3, 2, 1]);
+        assert_eq!(sequence(AnimationDirection::PingPong), [1, 2, 3, 2]);
+        assert_eq!(sequence(AnimationDirection::PingPongReverse), [3, 2, 1, 2]);
+
+        let single = AnimationTag::new("tag", 4..=4, AnimationDirection::PingPong).unwrap();
+        assert_eq!(single.sequence(), [4]);
+    }
+
+    #[test]
+    fn frame_at_loops() {
+        let sheet = sheet(SpriteSheetDescription::parse(ASEPRITE.as_bytes()).unwrap());
+        let tag = sheet.tag("walk").unwrap();
+
+        // 50 + 150 + 100 + 150 milliseconds for 0, 1, 2, 1.
+        assert_eq!(sheet.tag_duration(tag), Duration::from_millis(450));
+        let frame_at = |millis| sheet.frame_at(tag, Duration::from_millis(millis));
+        assert_eq!([0, 49, 50, 199, 200, 300, 449, 450].map(frame_at), [0, 0, 1, 1, 2, 1, 1, 0]);
+
+        // Frames beyond the sheet take no time instead of panicking.
+        let beyond = AnimationTag::new("beyond", 2..=5, AnimationDirection::Forward).unwrap();
+        assert_eq!(sheet.tag_duration(&beyond), DEFAULT_FRAME_DURATION);
+        assert_eq!(sheet.frame_at(&beyond, Duration::from_millis(10)), 2);
+    }
+
+    #[test]
+    fn grid_cells() {
+        let image = Image::new(ResourceId::new(ResourceNamespace::Image, 0), Size2D::new(34, 28));
+        let grid = SpriteGrid::new(Size2D::new(8, 8)).with_spacing(2).with_margin(1);
+        let sheet = SpriteSheet::from_grid(image, grid);
+
+        assert_eq!(sheet.frames().len(), 6);
+        assert_eq!(sheet.frame(4).unwrap().rect.origin, Point2D::new(11, 11));
+        assert_eq!(sheet.frame_index("5"), Some(5));
+    }
+}
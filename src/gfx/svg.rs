@@ -0,0 +1,119 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+use std::{path::Path, sync::Arc};
+
+use euclid::default::Size2D;
+use image::RgbaImage;
+use resvg::{tiny_skia::{Pixmap, Transform}, usvg::{fontdb::Database, Options, Tree}};
+
+use crate::ImageLoadError;
+
+/// A parsed SVG document. Shapes, strokes and text are kept as vector paths,
+/// so the document stays sharp at any size.
+#[derive(Debug, Clone)]
+pub struct SvgDocument {
+    tree: Arc<Tree>,
+}
+
+impl SvgDocument {
+    /// Relative references (e.g. `<image href="...">`) are resolved against
+    /// `resources_dir`.
+    pub(super) fn parse(bytes: &[u8], resources_dir: Option<&Path>, fonts: Arc<Database>) -> Result<Self, ImageLoadError> {
+        let options = Options {
+            resources_dir: resources_dir.map(Path::to_path_buf),
+            fontdb: fonts,
+            ..Default::default()
+        };
+
+        Ok(Self {
+            tree: Arc::new(Tree::from_data(bytes, &options)?),
+        })
+    }
+
+    /// The intrinsic size of the document, in CSS pixels.
+    #[must_use]
+    pub fn size(&self) -> Size2D<f32> {
+        let size = self.tree.size();
+        Size2D::new(size.width(), size.height())
+    }
+
+    /// Serializes the normalized document, e.g. for embedding it in another
+    /// document.
+    #[cfg(feature = "svg-export")]
+    pub(super) fn to_svg_string(&self) -> String {
+        self.tree.to_string(&resvg::usvg::WriteOptions::default())
+    }
+
+    /// Renders the document, stretched to `size`. The pixels have straight
+    /// alpha, like every other [`RgbaImage`].
+    pub(super) fn rasterize(&self, size: Size2D<u32>) -> RgbaImage {
+        let Some(mut pixmap) = Pixmap::new(size.width, size.height) else {
+            return RgbaImage::new(size.width, size.height);
+        };
+
+        let intrinsic = self.size();
+        let transform = Transform::from_scale(size.width as f32 / intrinsic.width, size.height as f32 / intrinsic.height);
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+
+        let pixels = pixmap.pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let pixel = pixel.demultiply();
+                [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]
+            })
+            .collect();
+
+        RgbaImage::from_raw(size.width, size.height, pixels).unwrap()
+    }
+}
+
+/// The system fonts, used to convert `<text>` elements to paths. Loading them
+/// is slow, so they are only loaded once.
+pub(super) fn system_fonts() -> Arc<Database> {
+    static FONTS: std::sync::OnceLock<Arc<Database>> = std::sync::OnceLock::new();
+
+    Arc::clone(FONTS.get_or_init(|| {
+        let mut database = Database::new();
+        database.load_system_fonts();
+        Arc::new(database)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &[u8] = br##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10">
+        <defs>
+            <linearGradient id="ramp">
+                <stop offset="0" stop-color="#000"/>
+                <stop offset="1" stop-color="#fff"/>
+            </linearGradient>
+        </defs>
+        <rect width="10" height="10" fill="#f00"/>
+        <rect width="10" height="10" fill="url(#ramp)" transform="translate(10 0)"/>
+    </svg>"##;
+
+    #[test]
+    fn rasterize_scales_to_the_requested_size() {
+        let document = SvgDocument::parse(DOCUMENT, None, Arc::new(Database::new())).unwrap();
+        assert_eq!(document.size(), Size2D::new(20.0, 10.0));
+
+        let pixels = document.rasterize(Size2D::new(40, 20));
+        assert_eq!(pixels.dimensions(), (40, 20));
+        assert_eq!(pixels.get_pixel(0, 0).0, [0xFF, 0, 0, 0xFF]);
+        assert_eq!(pixels.get_pixel(19, 19).0, [0xFF, 0, 0, 0xFF]);
+
+        // The gradient starts at the translated rect and brightens to the right.
+        let ramp: Vec<_> = (20..40).map(|x| pixels.get_pixel(x, 10).0).collect();
+        assert!(ramp.iter().all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2] && pixel[3] == 0xFF), "{ramp:?}");
+        assert!(ramp.windows(2).all(|pair| pair[0][0] < pair[1][0]), "{ramp:?}");
+        assert!(ramp[0][0] < 0x10 && ramp[19][0] > 0xF0, "{ramp:?}");
+    }
+
+    #[test]
+    fn invalid_documents_are_rejected() {
+        assert!(SvgDocument::parse(b"<svg", None, Arc::new(Database::new())).is_err());
+    }
+}
@@ -15,4 +15,4 @@ pub struct TexturedVertex {
 }
 
 implement_vertex!(Vertex, position);
-implement_vertex!(TexturedVertex, position);
+implement_vertex!(TexturedVertex, position, tex_coords);
@@ -0,0 +1,36 @@
+// Copyright (C) 2024 Tristan Gerritsen <tristan@thewoosh.org>
+// All Rights Reserved.
+
+//! A 2D graphics library with OpenGL and software backends, which paints into
+//! windows, pixel buffers, the terminal or SVG and PDF documents.
+//!
+//! Everything is painted in logical pixels, which are multiplied by the
+//! [`Context::scale_factor`] to get the pixels of the target, independently
+//! of the backend. Only [`Context::resize`] and the sizes of images are in
+//! physical pixels.
+
+// Without a backend a `Context` can't be created, which leaves most of the
+// internals unused.
+#![cfg_attr(not(any(feature = "gl", feature = "software", feature = "svg-export", feature = "pdf-export")), allow(dead_code, unused_imports))]
+
+#[cfg(all(feature = "window", not(any(feature = "gl", feature = "software"))))]
+compile_error!("the `window` feature requires the `gl` or `software` backend");
+
+mod error;
+mod gfx;
+mod resource;
+
+/// The user event type of the [`winit`] event loop.
+#[cfg(feature = "window")]
+pub type EventTy = ();
+
+#[cfg(feature = "window")]
+pub use winit;
+
+pub use self::{
+    error::*,
+    gfx::*,
+    resource::{ResourceId, ResourceNamespace},
+};
+
+use self::resource::ResourceManager;
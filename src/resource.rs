@@ -5,15 +5,18 @@ use std::cell::RefCell;
 
 use dashmap::DashMap;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ResourceId {
     namespace: ResourceNamespace,
     id: usize,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ResourceNamespace {
     Image,
+    HdrImage,
+    CompressedImage,
+    AtlasImage,
 }
 
 impl ResourceId {
@@ -58,6 +61,7 @@ impl<T> ResourceManager<T> {
         id
     }
 
+    #[cfg(any(feature = "gl", feature = "svg-export", feature = "pdf-export"))]
     pub fn with<F: FnOnce(&T)>(&self, id: ResourceId, f: F) {
         debug_assert_eq!(id.namespace, self.namespace);
 
@@ -65,6 +69,26 @@ impl<T> ResourceManager<T> {
         f(&val)
     }
 
+    #[cfg(any(feature = "software", feature = "svg-export", feature = "pdf-export"))]
+    pub fn with_mut<F: FnOnce(&mut T) -> R, R>(&self, id: ResourceId, f: F) -> R {
+        debug_assert_eq!(id.namespace, self.namespace);
+
+        let mut val = self.map.get_mut(&id.id).unwrap();
+        f(&mut val)
+    }
+
+    pub fn contains(&self, id: ResourceId) -> bool {
+        debug_assert_eq!(id.namespace, self.namespace);
+
+        self.map.contains_key(&id.id)
+    }
+
+    pub fn remove(&self, id: ResourceId) -> Option<T> {
+        debug_assert_eq!(id.namespace, self.namespace);
+
+        self.map.remove(&id.id).map(|(_, val)| val)
+    }
+
     fn create_id(&self) -> ResourceId {
         let id = *self.id_counter.borrow();
         *self.id_counter.borrow_mut() += 1;